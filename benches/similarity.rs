@@ -0,0 +1,89 @@
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use half::{bf16, f16};
+use storage_core::vector::eval::uniform_vectors;
+use storage_core::vector::search::{VectorSearchOptions, vector_search, vector_search_batch};
+use storage_core::vector::similarity::{
+    cosine_similarity, cosine_similarity_compact, dot_product, hamming_distance, kernel_name, normalize, pack_signs,
+    squared_euclidean,
+};
+
+// naive_cosine is the straightforward implementation the kernels are measured against:
+// three passes and powf for the squares
+fn naive_cosine(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    let mag_a = vec_a.iter().map(|e| e.powf(2.0)).sum::<f32>().sqrt();
+    let mag_b = vec_b.iter().map(|e| e.powf(2.0)).sum::<f32>().sqrt();
+    let dot_prod = vec_a.iter().zip(vec_b).map(|(x, y)| x * y).sum::<f32>();
+    if mag_a == 0.0 || mag_b == 0.0 {
+        return 0.0;
+    }
+    dot_prod / (mag_a * mag_b)
+}
+
+fn naive_dot(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    vec_a.iter().zip(vec_b).map(|(x, y)| x * y).sum::<f32>()
+}
+
+fn naive_squared_euclidean(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    vec_a.iter().zip(vec_b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>()
+}
+
+fn kernels(c: &mut Criterion) {
+    println!("similarity kernels: {}", kernel_name());
+    for dimension in [128, 384, 1536] {
+        let pair = uniform_vectors(2, dimension, dimension as u64);
+        let (vec_a, vec_b) = (&pair[0], &pair[1]);
+        let (unit_a, unit_b) = (normalize(vec_a), normalize(vec_b));
+
+        let mut group = c.benchmark_group(format!("similarity/{}", dimension));
+        group.bench_function("cosine_naive", |b| b.iter(|| naive_cosine(black_box(vec_a), black_box(vec_b))));
+        group.bench_function("cosine", |b| b.iter(|| cosine_similarity(black_box(vec_a), black_box(vec_b))));
+        // cosine of pre-normalized vectors, as stored by the vector indexes
+        group.bench_function("cosine_normalized", |b| {
+            b.iter(|| dot_product(black_box(&unit_a), black_box(&unit_b)))
+        });
+        group.bench_function("dot_naive", |b| b.iter(|| naive_dot(black_box(vec_a), black_box(vec_b))));
+        group.bench_function("dot", |b| b.iter(|| dot_product(black_box(vec_a), black_box(vec_b))));
+        group.bench_function("l2_naive", |b| {
+            b.iter(|| naive_squared_euclidean(black_box(vec_a), black_box(vec_b)))
+        });
+        group.bench_function("l2", |b| b.iter(|| squared_euclidean(black_box(vec_a), black_box(vec_b))));
+        // compact storage formats, compared with the f32 query
+        let halves: Vec<f16> = vec_b.iter().map(|value| f16::from_f32(*value)).collect();
+        let brains: Vec<bf16> = vec_b.iter().map(|value| bf16::from_f32(*value)).collect();
+        let (signs_a, signs_b) = (pack_signs(vec_a), pack_signs(vec_b));
+        group.bench_function("cosine_f16", |b| {
+            b.iter(|| cosine_similarity_compact(black_box(vec_a), black_box(&halves)))
+        });
+        group.bench_function("cosine_bf16", |b| {
+            b.iter(|| cosine_similarity_compact(black_box(vec_a), black_box(&brains)))
+        });
+        group.bench_function("hamming", |b| b.iter(|| hamming_distance(black_box(&signs_a), black_box(&signs_b))));
+        group.finish();
+    }
+}
+
+fn search(c: &mut Criterion) {
+    let dimension = 384;
+    let candidates: Vec<(usize, Vec<f32>)> = uniform_vectors(10_000, dimension, 3).into_iter().enumerate().collect();
+    let query = uniform_vectors(1, dimension, 5).remove(0);
+
+    let mut group = c.benchmark_group("vector_search");
+    group.sample_size(20);
+    group.bench_with_input(BenchmarkId::new("top10", candidates.len()), &candidates, |b, candidates| {
+        b.iter(|| vector_search(black_box(&query), candidates, 10))
+    });
+
+    // 32 queries one after the other, and in one parallel pass
+    let queries = uniform_vectors(32, dimension, 7);
+    let options = VectorSearchOptions::new();
+    group.bench_function("top10_x32_sequential", |b| {
+        b.iter(|| queries.iter().map(|query| vector_search(black_box(query), &candidates, 10)).collect::<Vec<_>>())
+    });
+    group.bench_function("top10_x32_batch", |b| {
+        b.iter(|| vector_search_batch(black_box(&queries), &candidates, 10, &options))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, kernels, search);
+criterion_main!(benches);
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use storage_core::{core::RepoModel, fs::database::FsDatabase};
+use storage_core::{
+    core::{RepoModel, Searchable, SortValue},
+    fs::database::FsDatabase,
+};
 use tokio::sync::Mutex;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -46,6 +49,17 @@ impl RepoModel<String> for Account {
     }
 }
 
+impl Searchable for Account {
+    fn get_field_value(&self, field: &str) -> Option<SortValue> {
+        match field {
+            "id" => Some(SortValue::String(self.id.clone())),
+            "user_id" => Some(SortValue::String(self.user_id.clone())),
+            "account_id" => Some(SortValue::String(self.account_id.clone())),
+            _ => None,
+        }
+    }
+}
+
 pub(crate) struct Service {
     pub db: Arc<Mutex<FsDatabase>>,
 }
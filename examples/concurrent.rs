@@ -23,7 +23,7 @@ async fn main() -> Result<(), anyhow::Error> {
     let handle1: JoinHandle<Result<(), anyhow::Error>> = tokio::spawn(async move {
         // Lock the mutex inside the thread to read and write
         let mut guard = db1.lock().await;
-        let _ = guard.register_collection::<String, User>("user".to_string());
+        guard.register_collection::<String, User>("users".to_string()).await?;
         {
             let urepo = guard
                 .collection::<String, User>("users".to_string())
@@ -49,7 +49,7 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let handle2: JoinHandle<Result<(), anyhow::Error>> = tokio::spawn(async move {
         let mut guard = db2.lock().await;
-        let _ = guard.register_collection::<String, Account>("account".to_string());
+        guard.register_collection::<String, Account>("account".to_string()).await?;
 
         println!("Starting account thread");
         for i in 0..4 {
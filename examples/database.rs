@@ -1,7 +1,12 @@
+#[allow(dead_code)]
 mod common;
 
 use anyhow::Result;
-use storage_core::fs::database::FsDatabase;
+use storage_core::fs::{
+    database::FsDatabase,
+    index::IndexDefinition,
+    search::{SearchCriteria, SearchOp, SearchValue},
+};
 use tracing::Level;
 use tracing_subscriber::{filter, layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -46,7 +51,11 @@ async fn main() -> Result<()>{
 
     {
 
-        let _ = fsdb.register_collection::<String, Account>("account".to_string()).await?;
+        fsdb.register_indexed_collection::<String, Account>(
+            "account".to_string(),
+            vec![IndexDefinition::new("user_id")],
+        )
+        .await?;
         let arepo = fsdb.collection("account".to_string()).await?;
         
         let account1 = Account::new("1".to_string(), "1".to_string());
@@ -57,6 +66,12 @@ async fn main() -> Result<()>{
     
         let accounts = arepo.find_all().await;
         println!("account count {:?}", accounts.len());
+
+        // user_id is indexed, so only the accounts of user 1 are read
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("1".to_string()));
+        let accounts = arepo.find(Some(criteria)).await;
+        println!("user 1 accounts {:?}", accounts);
     
     }    
     Ok(())        
@@ -1,4 +1,5 @@
-pub mod common;
+#[allow(dead_code)]
+mod common;
 use anyhow::Result;
 use std::path::PathBuf;
 use storage_core::{
@@ -30,7 +31,7 @@ async fn main() -> Result<()> {
         name: "Alice".to_string(),
     };
 
-    let _ = repo.insert(user).await?;
+    repo.insert(user).await?;
 
     // Find by ID
     let found = repo.find_by_id("5".to_string()).await;
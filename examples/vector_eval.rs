@@ -0,0 +1,81 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use storage_core::{
+    core::{Initializable, RepoModel, Repository, VectorEmbedding},
+    fs::repository::FsRepository,
+    vector::{
+        eval::{clustered_vectors, evaluate_index},
+        hnsw::HnswParams,
+        index::VectorIndexDefinition,
+        ivf::IvfParams,
+        quantization::Quantization,
+    },
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Passage {
+    id: String,
+    vector: Vec<f32>,
+}
+
+impl RepoModel<String> for Passage {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn collection(&self) -> &'static str {
+        "passage"
+    }
+}
+
+impl VectorEmbedding for Passage {
+    fn vector(&self) -> &[f32] {
+        &self.vector
+    }
+}
+
+// Compares the vector indexes with the exact search over a collection of passages:
+//   cargo run --release --example vector_eval -- [vectors] [dimension] [queries] [top_k]
+// The collection is filled with clustered random vectors when empty; delete
+// data/examples/passages to generate another one.
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<usize> = std::env::args().skip(1).map(|arg| arg.parse()).collect::<Result<_, _>>()?;
+    let arg = |i: usize, default: usize| args.get(i).copied().unwrap_or(default);
+    let (count, dimension, query_count, top_k) = (arg(0, 10_000), arg(1, 128), arg(2, 200), arg(3, 10));
+
+    let pb = PathBuf::from("data/examples/passages");
+    let mut repo = FsRepository::<String, Passage>::new("passages".to_string(), pb)?;
+    repo.initialize().await?;
+    let mut passages = repo.find_all().await;
+    if passages.is_empty() {
+        println!("Generating {} vectors of dimension {}", count, dimension);
+        for (i, vector) in clustered_vectors(count, dimension, 32, 1).into_iter().enumerate() {
+            repo.insert(Passage { id: i.to_string(), vector }).await?;
+        }
+        passages = repo.find_all().await;
+    }
+
+    let candidates: Vec<(String, Vec<f32>)> =
+        passages.into_iter().map(|passage| (passage.id, passage.vector)).collect();
+    let dimension = candidates.first().map_or(dimension, |(_, vector)| vector.len());
+    let queries = clustered_vectors(query_count, dimension, 32, 2);
+    println!("{} vectors, {} queries, top {}\n", candidates.len(), queries.len(), top_k);
+
+    let definitions = [
+        VectorIndexDefinition::Hnsw(HnswParams::new(16, 200, 32)),
+        VectorIndexDefinition::Hnsw(HnswParams::default()),
+        VectorIndexDefinition::Hnsw(HnswParams::new(16, 200, 256)),
+        VectorIndexDefinition::IvfFlat(IvfParams::new(64, 4)),
+        VectorIndexDefinition::IvfFlat(IvfParams::default()),
+        VectorIndexDefinition::IvfFlat(IvfParams::default().with_quantization(Quantization::Scalar)),
+        VectorIndexDefinition::IvfFlat(
+            IvfParams::default().with_quantization(Quantization::Product { subspaces: dimension.div_ceil(8) }),
+        ),
+    ];
+    for definition in &definitions {
+        println!("{}\n", evaluate_index(definition, &candidates, &queries, top_k));
+    }
+    Ok(())
+}
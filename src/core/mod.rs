@@ -1,14 +1,22 @@
 use std::{
     any::Any,
+    cmp::Ordering,
     fmt::{Debug, Display},
     hash::Hash,
 };
 
 use anyhow::Result;
 use async_trait::async_trait;
+use rust_decimal::{Decimal, prelude::ToPrimitive};
 use serde::{Serialize, de::DeserializeOwned};
 
+use crate::fs::aggregate::{AggregateQuery, AggregateRow};
+use crate::fs::planner::QueryExplain;
 use crate::fs::search::SearchCriteria;
+use crate::vector::hybrid::Fusion;
+use crate::vector::metric::DistanceMetric;
+use crate::vector::multi::{DocumentMatch, MultiVectorOptions};
+use crate::vector::search::VectorSearchOptions;
 
 
 // 1. Define a trait alias to consolidate constraints
@@ -42,14 +50,100 @@ pub trait Repository<K, M>: Send + Sync {
     async fn find(&mut self, search: Option<SearchCriteria>) -> Vec<M>
         where M: Searchable;
 
+    // explain runs the query and reports how the records were found
+    async fn explain(&mut self, criteria: SearchCriteria) -> QueryExplain
+        where M: Searchable;
+
+    // count returns the number of records matching the conditions of the criteria
+    async fn count(&mut self, criteria: Option<SearchCriteria>) -> usize
+        where M: Searchable;
+
+    // aggregate groups the records matching the conditions of the criteria
+    async fn aggregate(
+        &mut self,
+        criteria: Option<SearchCriteria>,
+        query: AggregateQuery,
+    ) -> Vec<AggregateRow>
+    where
+        M: Searchable;
+
+    async fn text_search(
+        &mut self,
+        query: &str,
+        top_k: usize,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: Searchable;
+
+    async fn hybrid_search(
+        &mut self,
+        query_vector: &[f32],
+        query_text: &str,
+        top_k: usize,
+        fusion: Fusion,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable;
+
     async fn semantic_search(
         &mut self,
         query_vector: &[f32],
         top_k: usize,
         criteria: Option<SearchCriteria>,
-    ) -> Vec<(M, f32)>
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>;
+
+    async fn semantic_search_with_metric(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        metric: DistanceMetric,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>;
+
+    async fn semantic_search_with_options(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        options: VectorSearchOptions,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>;
+
+    async fn semantic_search_text(
+        &mut self,
+        query_text: &str,
+        top_k: usize,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>;
+
+    async fn semantic_search_batch(
+        &mut self,
+        query_vectors: &[Vec<f32>],
+        top_k: usize,
+        options: VectorSearchOptions,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<Vec<(M, f32)>>>
     where
         M: VectorEmbedding + Searchable + RepoModel<K>;
+
+    async fn multi_vector_search(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        options: MultiVectorOptions,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<DocumentMatch<M>>>
+    where
+        M: MultiVectorEmbedding + Searchable + RepoModel<K>;
 }
 
 #[async_trait]
@@ -65,19 +159,189 @@ pub trait VectorEmbedding: Send + Sync + Debug {
     fn vector(&self) -> &[f32];
 }
 
+// Embeddable - models whose vector is computed from their text by the embedder of the collection
+pub trait Embeddable: VectorEmbedding {
+    fn embedding_text(&self) -> String;
+    fn set_vector(&mut self, vector: Vec<f32>);
+}
+
+// MultiVectorEmbedding - models split into chunks, each with its own embedding
+pub trait MultiVectorEmbedding: Send + Sync + Debug {
+    fn vectors(&self) -> &[Vec<f32>];
+}
+
 //Searchable trait 
 pub trait Searchable {
-    fn matches_filter(&self, _criteria: &SearchCriteria) -> bool {
-        true  // Default: pass all (no Searching)
+    // MATCHES_BY_FIELDS is true when matches_filter only checks the conditions against
+    // get_field_value, as the default does, so searches may check them against the indexes
+    // without reading the records. Models overriding matches_filter otherwise set it to false.
+    const MATCHES_BY_FIELDS: bool = true;
+
+    fn matches_filter(&self, criteria: &SearchCriteria) -> bool {
+        criteria.matches(self)  // Default: evaluate every condition with get_field_value
     }
 
     fn get_field_value(&self, field: &str) -> Option<SortValue>; 
 
 }
 
-#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+// SortValue is the comparable value of a model field. Values of different types
+// follow a total ordering: Null < Bool < numbers < String < DateTime. Int, Float
+// and Decimal compare by their numeric value.
+#[derive(Debug, Clone)]
 pub enum SortValue {
-    String(String),
-    Decimal(rust_decimal::Decimal),
+    Null,
+    Bool(bool),
     Int(i64),
-}
\ No newline at end of file
+    Float(f64),
+    Decimal(rust_decimal::Decimal),
+    String(String),
+    DateTime(bson::DateTime),
+}
+
+impl SortValue {
+    pub fn is_null(&self) -> bool {
+        matches!(self, SortValue::Null)
+    }
+
+    // comparable_with returns true when both values belong to the same type class
+    pub fn comparable_with(&self, other: &SortValue) -> bool {
+        self.type_rank() == other.type_rank()
+    }
+
+    // type_rank orders the value types relative to each other
+    fn type_rank(&self) -> u8 {
+        match self {
+            SortValue::Null => 0,
+            SortValue::Bool(_) => 1,
+            SortValue::Int(_) | SortValue::Float(_) | SortValue::Decimal(_) => 2,
+            SortValue::String(_) => 3,
+            SortValue::DateTime(_) => 4,
+        }
+    }
+
+    // as_f64 converts numeric values for comparisons involving floats
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            SortValue::Int(v) => Some(*v as f64),
+            SortValue::Float(v) => Some(*v),
+            SortValue::Decimal(v) => v.to_f64(),
+            _ => None,
+        }
+    }
+
+    // integer_parts splits Int and Decimal values into their floor and the fraction above it
+    fn integer_parts(&self) -> Option<(i128, f64)> {
+        match self {
+            SortValue::Int(v) => Some((*v as i128, 0.0)),
+            SortValue::Decimal(v) => {
+                let floor = v.floor();
+                Some((floor.to_i128()?, (v - floor).to_f64()?))
+            }
+            _ => None,
+        }
+    }
+}
+
+// cmp_float compares the number integer + fraction with a float without rounding the integer
+// to f64, which would make large integers equal to several distinct floats. NaNs are placed
+// like f64::total_cmp does, negative ones first and positive ones last.
+fn cmp_float(integer: i128, fraction: f64, float: f64) -> Ordering {
+    const LIMIT: f64 = i128::MAX as f64;
+    if float.is_nan() {
+        return if float.is_sign_negative() { Ordering::Greater } else { Ordering::Less };
+    }
+    if float >= LIMIT {
+        return Ordering::Less;
+    }
+    if float < -LIMIT {
+        return Ordering::Greater;
+    }
+    // the floor of a float in range is an exact integer, and so is the difference
+    let floor = float.floor();
+    integer.cmp(&(floor as i128)).then(fraction.total_cmp(&(float - floor)))
+}
+
+impl Ord for SortValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SortValue::Null, SortValue::Null) => Ordering::Equal,
+            (SortValue::Bool(a), SortValue::Bool(b)) => a.cmp(b),
+            (SortValue::Int(a), SortValue::Int(b)) => a.cmp(b),
+            (SortValue::Decimal(a), SortValue::Decimal(b)) => a.cmp(b),
+            (SortValue::Int(a), SortValue::Decimal(b)) => Decimal::from(*a).cmp(b),
+            (SortValue::Decimal(a), SortValue::Int(b)) => a.cmp(&Decimal::from(*b)),
+            (SortValue::String(a), SortValue::String(b)) => a.cmp(b),
+            (SortValue::DateTime(a), SortValue::DateTime(b)) => a.cmp(b),
+            // -0.0 and 0.0 are equal, as they are to Int(0)
+            (SortValue::Float(a), SortValue::Float(b)) if a == b => Ordering::Equal,
+            (SortValue::Float(a), SortValue::Float(b)) => a.total_cmp(b),
+            (a, b) => match (a, a.integer_parts(), b, b.integer_parts()) {
+                (_, Some((integer, fraction)), SortValue::Float(y), _) => cmp_float(integer, fraction, *y),
+                (SortValue::Float(x), _, _, Some((integer, fraction))) => cmp_float(integer, fraction, *x).reverse(),
+                _ => a.type_rank().cmp(&b.type_rank()),
+            },
+        }
+    }
+}
+
+impl PartialOrd for SortValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortValue {}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::core::SortValue;
+
+    #[test]
+    fn test_sort_value_type_ordering() {
+        let mut values = [
+            SortValue::DateTime(bson::DateTime::from_millis(0)),
+            SortValue::String("a".to_string()),
+            SortValue::Int(1),
+            SortValue::Bool(true),
+            SortValue::Null,
+        ];
+        values.sort();
+        assert!(values[0].is_null());
+        assert_eq!(values[1], SortValue::Bool(true));
+        assert_eq!(values[2], SortValue::Int(1));
+        assert_eq!(values[3], SortValue::String("a".to_string()));
+        assert_eq!(values[4], SortValue::DateTime(bson::DateTime::from_millis(0)));
+    }
+
+    #[test]
+    fn test_sort_value_numeric_ordering() {
+        assert_eq!(SortValue::Int(2), SortValue::Decimal(Decimal::new(200, 2)));
+        assert_eq!(SortValue::Int(2), SortValue::Float(2.0));
+        assert!(SortValue::Float(1.5) < SortValue::Int(2));
+        assert!(SortValue::Decimal(Decimal::new(25, 1)) > SortValue::Float(2.4));
+        assert!(SortValue::Int(-1) < SortValue::Decimal(Decimal::ZERO));
+
+        // integers beyond 2^53 are not rounded to the nearest float
+        let float = SortValue::Float(9_007_199_254_740_992.0);
+        assert!(SortValue::Int(9_007_199_254_740_993) > float);
+        assert_eq!(SortValue::Int(9_007_199_254_740_992), float);
+        assert!(SortValue::Decimal(Decimal::from(9_007_199_254_740_993i64)) > float);
+        assert!(SortValue::Int(i64::MAX) < SortValue::Float(i64::MAX as f64));
+        assert!(SortValue::Int(-3) > SortValue::Float(-3.5));
+        assert!(SortValue::Decimal(Decimal::new(-35, 1)) < SortValue::Float(-3.25));
+        assert!(SortValue::Int(i64::MAX) < SortValue::Float(f64::INFINITY));
+        assert!(SortValue::Int(i64::MIN) > SortValue::Float(f64::NEG_INFINITY));
+        assert!(SortValue::Int(0) < SortValue::Float(f64::NAN));
+        assert_eq!(SortValue::Float(-0.0), SortValue::Int(0));
+        assert_eq!(SortValue::Float(-0.0), SortValue::Float(0.0));
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Searchable, SortValue};
+
+// Aggregate is a function computed over the records of each group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    Count,          // Number of records
+    Sum(String),    // Sum of the numeric values of the field
+    Avg(String),    // Average of the numeric values of the field
+    Min(String),    // Smallest non-null value of the field
+    Max(String),    // Largest non-null value of the field
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AggregateQuery {
+    #[serde(default)]
+    pub group_by: Vec<String>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+// AggregateRow holds the group_by values (Null when missing) and the aggregate
+// results, both in the order they were requested
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateRow {
+    pub group: Vec<SortValue>,
+    pub values: Vec<SortValue>,
+}
+
+impl AggregateQuery {
+    pub fn new() -> AggregateQuery {
+        AggregateQuery::default()
+    }
+
+    // add group by field
+    pub fn add_group_by(&mut self, field: &str) {
+        self.group_by.push(field.to_string());
+    }
+
+    // add aggregate function
+    pub fn add_aggregate(&mut self, aggregate: Aggregate) {
+        self.aggregates.push(aggregate);
+    }
+}
+
+impl AggregateRow {
+    // value returns the result of an aggregate of the query that produced the row
+    pub fn value(&self, query: &AggregateQuery, aggregate: &Aggregate) -> Option<&SortValue> {
+        let index = query.aggregates.iter().position(|a| a == aggregate)?;
+        self.values.get(index)
+    }
+}
+
+// Aggregator accumulates records into groups ordered by their group_by values
+pub struct Aggregator<'a> {
+    query: &'a AggregateQuery,
+    groups: BTreeMap<Vec<SortValue>, Vec<Accumulator>>,
+}
+
+impl<'a> Aggregator<'a> {
+    pub fn new(query: &'a AggregateQuery) -> Self {
+        Self {
+            query,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn add<M: Searchable>(&mut self, item: &M) {
+        let key: Vec<SortValue> = self
+            .query
+            .group_by
+            .iter()
+            .map(|field| item.get_field_value(field).unwrap_or(SortValue::Null))
+            .collect();
+
+        let accumulators = self.groups.entry(key).or_insert_with(|| {
+            self.query
+                .aggregates
+                .iter()
+                .map(|_| Accumulator::default())
+                .collect()
+        });
+
+        for (aggregate, accumulator) in self.query.aggregates.iter().zip(accumulators) {
+            let value = match aggregate {
+                Aggregate::Count => None,
+                Aggregate::Sum(field)
+                | Aggregate::Avg(field)
+                | Aggregate::Min(field)
+                | Aggregate::Max(field) => item.get_field_value(field),
+            };
+            accumulator.add(value);
+        }
+    }
+
+    // finish returns one row per group. Without group_by fields there is always a single row.
+    pub fn finish(mut self) -> Vec<AggregateRow> {
+        if self.query.group_by.is_empty() && self.groups.is_empty() {
+            let empty = self.query.aggregates.iter().map(|_| Accumulator::default()).collect();
+            self.groups.insert(Vec::new(), empty);
+        }
+
+        self.groups
+            .into_iter()
+            .map(|(group, accumulators)| AggregateRow {
+                group,
+                values: self
+                    .query
+                    .aggregates
+                    .iter()
+                    .zip(accumulators)
+                    .map(|(aggregate, accumulator)| accumulator.result(aggregate))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+// aggregate groups the items and computes the query's aggregates
+pub fn aggregate<'i, M, I>(items: I, query: &AggregateQuery) -> Vec<AggregateRow>
+where
+    M: Searchable + 'i,
+    I: IntoIterator<Item = &'i M>,
+{
+    let mut aggregator = Aggregator::new(query);
+    for item in items {
+        aggregator.add(item);
+    }
+    aggregator.finish()
+}
+
+// Accumulator keeps integer sums exact until a Decimal or Float value, or an
+// overflow, requires a wider type
+#[derive(Debug, Default)]
+struct Accumulator {
+    records: usize,
+    numbers: usize,
+    sum: Sum,
+    min: Option<SortValue>,
+    max: Option<SortValue>,
+}
+
+#[derive(Debug)]
+enum Sum {
+    Int(i64),
+    Decimal(Decimal),
+    Float(f64),
+}
+
+impl Default for Sum {
+    fn default() -> Self {
+        Sum::Int(0)
+    }
+}
+
+impl Sum {
+    fn add(&mut self, value: &SortValue) {
+        *self = match (&*self, value) {
+            (Sum::Int(a), SortValue::Int(b)) => match a.checked_add(*b) {
+                Some(sum) => Sum::Int(sum),
+                None => Sum::decimal(Decimal::from(*a), Decimal::from(*b)),
+            },
+            (Sum::Int(a), SortValue::Decimal(b)) => Sum::decimal(Decimal::from(*a), *b),
+            (Sum::Decimal(a), SortValue::Int(b)) => Sum::decimal(*a, Decimal::from(*b)),
+            (Sum::Decimal(a), SortValue::Decimal(b)) => Sum::decimal(*a, *b),
+            (sum, value) => Sum::Float(sum.as_f64() + SortValue::as_f64(value).unwrap_or(0.0)),
+        };
+    }
+
+    // decimal adds two decimals, widening to a float sum past the range of Decimal
+    fn decimal(a: Decimal, b: Decimal) -> Sum {
+        match a.checked_add(b) {
+            Some(sum) => Sum::Decimal(sum),
+            None => Sum::Float(
+                SortValue::Decimal(a).as_f64().unwrap_or(0.0) + SortValue::Decimal(b).as_f64().unwrap_or(0.0),
+            ),
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Sum::Int(v) => *v as f64,
+            Sum::Decimal(v) => SortValue::Decimal(*v).as_f64().unwrap_or(0.0),
+            Sum::Float(v) => *v,
+        }
+    }
+}
+
+impl Accumulator {
+    fn add(&mut self, value: Option<SortValue>) {
+        self.records += 1;
+
+        let Some(value) = value.filter(|v| !v.is_null()) else {
+            return;
+        };
+
+        if matches!(
+            value,
+            SortValue::Int(_) | SortValue::Decimal(_) | SortValue::Float(_)
+        ) {
+            self.numbers += 1;
+            self.sum.add(&value);
+        }
+
+        if self.min.as_ref().is_none_or(|min| value < *min) {
+            self.min = Some(value.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| value > *max) {
+            self.max = Some(value);
+        }
+    }
+
+    fn result(self, aggregate: &Aggregate) -> SortValue {
+        match aggregate {
+            Aggregate::Count => SortValue::Int(self.records as i64),
+            Aggregate::Sum(_) if self.numbers == 0 => SortValue::Null,
+            Aggregate::Sum(_) => match self.sum {
+                Sum::Int(v) => SortValue::Int(v),
+                Sum::Decimal(v) => SortValue::Decimal(v),
+                Sum::Float(v) => SortValue::Float(v),
+            },
+            Aggregate::Avg(_) if self.numbers == 0 => SortValue::Null,
+            Aggregate::Avg(_) => {
+                let count = self.numbers as u64;
+                match self.sum {
+                    Sum::Int(v) => avg_decimal(Decimal::from(v), count),
+                    Sum::Decimal(v) => avg_decimal(v, count),
+                    Sum::Float(v) => SortValue::Float(v / count as f64),
+                }
+            }
+            Aggregate::Min(_) => self.min.unwrap_or(SortValue::Null),
+            Aggregate::Max(_) => self.max.unwrap_or(SortValue::Null),
+        }
+    }
+}
+
+fn avg_decimal(sum: Decimal, count: u64) -> SortValue {
+    match sum.checked_div(Decimal::from(count)) {
+        Some(avg) => SortValue::Decimal(avg.normalize()),
+        None => SortValue::Float(
+            SortValue::Decimal(sum).as_f64().unwrap_or(0.0) / count as f64,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use rust_decimal::prelude::ToPrimitive;
+
+    use crate::core::{Searchable, SortValue};
+    use crate::fs::aggregate::{Aggregate, AggregateQuery, aggregate};
+
+    struct Account {
+        user_id: &'static str,
+        kind: Option<&'static str>,
+        balance: Decimal,
+        visits: i64,
+    }
+
+    impl Searchable for Account {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "user_id" => Some(SortValue::String(self.user_id.to_string())),
+                "kind" => self.kind.map(|k| SortValue::String(k.to_string())),
+                "balance" => Some(SortValue::Decimal(self.balance)),
+                "visits" => Some(SortValue::Int(self.visits)),
+                _ => None,
+            }
+        }
+    }
+
+    fn accounts() -> Vec<Account> {
+        vec![
+            Account { user_id: "2", kind: Some("savings"), balance: Decimal::new(1050, 2), visits: 3 },
+            Account { user_id: "1", kind: Some("checking"), balance: Decimal::new(200, 2), visits: 1 },
+            Account { user_id: "1", kind: None, balance: Decimal::new(-50, 2), visits: 4 },
+            Account { user_id: "2", kind: Some("checking"), balance: Decimal::new(100, 2), visits: 2 },
+        ]
+    }
+
+    #[test]
+    fn test_aggregate_group_by() {
+        let mut query = AggregateQuery::new();
+        query.add_group_by("user_id");
+        query.add_aggregate(Aggregate::Count);
+        query.add_aggregate(Aggregate::Sum("balance".to_string()));
+        query.add_aggregate(Aggregate::Avg("visits".to_string()));
+        query.add_aggregate(Aggregate::Min("kind".to_string()));
+        query.add_aggregate(Aggregate::Max("balance".to_string()));
+
+        let rows = aggregate(&accounts(), &query);
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!(rows[0].group, vec![SortValue::String("1".to_string())]);
+        assert_eq!(
+            rows[0].values,
+            vec![
+                SortValue::Int(2),
+                SortValue::Decimal(Decimal::new(150, 2)),
+                SortValue::Decimal(Decimal::new(25, 1)),
+                SortValue::String("checking".to_string()),
+                SortValue::Decimal(Decimal::new(2, 0)),
+            ]
+        );
+
+        assert_eq!(rows[1].group, vec![SortValue::String("2".to_string())]);
+        assert_eq!(
+            rows[1].value(&query, &Aggregate::Sum("balance".to_string())),
+            Some(&SortValue::Decimal(Decimal::new(1150, 2)))
+        );
+    }
+
+    #[test]
+    fn test_aggregate_missing_group_values() {
+        let mut query = AggregateQuery::new();
+        query.add_group_by("kind");
+        query.add_aggregate(Aggregate::Sum("visits".to_string()));
+
+        let rows = aggregate(&accounts(), &query);
+        let groups: Vec<&SortValue> = rows.iter().map(|row| &row.group[0]).collect();
+        assert_eq!(
+            groups,
+            vec![
+                &SortValue::Null,
+                &SortValue::String("checking".to_string()),
+                &SortValue::String("savings".to_string())
+            ]
+        );
+        assert_eq!(rows[1].values, vec![SortValue::Int(3)]);
+    }
+
+    #[test]
+    fn test_aggregate_without_records() {
+        let mut query = AggregateQuery::new();
+        query.add_aggregate(Aggregate::Count);
+        query.add_aggregate(Aggregate::Sum("balance".to_string()));
+        query.add_aggregate(Aggregate::Max("balance".to_string()));
+
+        let rows = aggregate(&Vec::<Account>::new(), &query);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].values,
+            vec![SortValue::Int(0), SortValue::Null, SortValue::Null]
+        );
+
+        query.add_group_by("user_id");
+        assert!(aggregate(&Vec::<Account>::new(), &query).is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_int_overflow_widens() {
+        let mut items = accounts();
+        items[0].visits = i64::MAX;
+        let mut query = AggregateQuery::new();
+        query.add_aggregate(Aggregate::Sum("visits".to_string()));
+
+        let rows = aggregate(&items, &query);
+        assert_eq!(
+            rows[0].values[0],
+            SortValue::Decimal(Decimal::from(i64::MAX) + Decimal::from(7))
+        );
+    }
+
+    #[test]
+    fn test_aggregate_decimal_overflow_widens() {
+        let mut items = accounts();
+        items[0].balance = Decimal::MAX;
+        items[1].balance = Decimal::MAX - Decimal::ONE;
+        let mut query = AggregateQuery::new();
+        query.add_aggregate(Aggregate::Sum("balance".to_string()));
+        query.add_aggregate(Aggregate::Avg("balance".to_string()));
+
+        let rows = aggregate(&items, &query);
+        let expected = 2.0 * Decimal::MAX.to_f64().unwrap();
+        let SortValue::Float(sum) = rows[0].values[0] else {
+            panic!("expected a float sum, got {:?}", rows[0].values[0]);
+        };
+        assert!((sum - expected).abs() / expected < 1e-9);
+        let SortValue::Float(avg) = rows[0].values[1] else {
+            panic!("expected a float average, got {:?}", rows[0].values[1]);
+        };
+        assert!((avg - expected / 4.0).abs() / expected < 1e-9);
+    }
+}
@@ -1,8 +1,37 @@
 use serde::{Deserialize, Serialize};
 
-
+use crate::fs::index::IndexDefinition;
+use crate::fs::text::TextIndexDefinition;
+use crate::vector::index::VectorIndexDefinition;
+use crate::vector::config::VectorConfig;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CollectionMetadata {
-    pub name: String
+    pub name: String,
+    #[serde(default)]
+    pub indexes: Vec<IndexDefinition>,
+    #[serde(default)]
+    pub text_index: Option<TextIndexDefinition>,
+    #[serde(default)]
+    pub vector_index: Option<VectorIndexDefinition>,
+    #[serde(default)]
+    pub chunk_index: Option<VectorIndexDefinition>,
+    #[serde(default)]
+    pub vector: Option<VectorConfig>,
+    #[serde(default)]
+    pub vector_store: bool,
+}
+
+impl CollectionMetadata {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            indexes: Vec::new(),
+            text_index: None,
+            vector_index: None,
+            chunk_index: None,
+            vector: None,
+            vector_store: false,
+        }
+    }
 }
@@ -6,14 +6,17 @@ use std::{
     fmt::Debug,
     fs,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 use tracing::debug;
 
 use crate::{
-    core::{Initializable, RepoKey, RepoModel, Repository},
+    core::{Embeddable, Initializable, MultiVectorEmbedding, RepoKey, RepoModel, Repository, Searchable, VectorEmbedding},
     fs::{
-        collections::CollectionMetadata, errors::FsDatabaseError, repository::FsRepository, utils,
+        collections::CollectionMetadata, errors::FsDatabaseError, index::IndexDefinition,
+        repository::FsRepository, text::TextIndexDefinition, utils,
     },
+    vector::{config::VectorConfig, embed::Embedder, index::VectorIndexDefinition},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -81,16 +84,59 @@ impl FsDatabase {
         K: RepoKey,
         M: RepoModel<K>,
     {
-        let full_path = PathBuf::from(&self.file_path).join(&name);
+        let mut repository = self.open_repository::<K, M>(&name).await?;
+        if let Some(config) = self.collections.get(&name).and_then(|metadata| metadata.vector) {
+            repository.declare_vector_config(config);
+        }
+        repository.initialize().await?;
+        self.repos
+            .entry(name.clone())
+            .insert_entry(Box::new(repository));
 
-        if !self.collections.contains_key(&name) {
-            let metadata = CollectionMetadata { name: name.clone() };
-            let _ = self.collections.insert(name.clone(), metadata);
-            let _ = fs::create_dir_all(full_path.clone());
-            self.save_to_file().await?;
+        Ok(())
+    }
+
+    // register_vector_collection registers the collection and checks the writes against its
+    // declared vector config from the start, see register_vector_config
+    pub async fn register_vector_collection<K, M>(&mut self, name: String) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + VectorEmbedding,
+    {
+        self.register_collection::<K, M>(name.clone()).await?;
+        if let Some(config) = self.collections.get(&name).and_then(|metadata| metadata.vector) {
+            self.repository::<K, M>(&name)?.set_vector_config(config);
+        }
+        Ok(())
+    }
+
+    // register_indexed_collection registers the collection with the given secondary indexes
+    // and those declared previously, and the declared text index. The indexes are built
+    // while the collection is initialized.
+    pub async fn register_indexed_collection<K, M>(
+        &mut self,
+        name: String,
+        indexes: Vec<IndexDefinition>,
+    ) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + Searchable,
+    {
+        let mut repository = self.open_repository::<K, M>(&name).await?;
+        self.declare_indexes(&name, indexes).await?;
+
+        if let Some(metadata) = self.collections.get(&name) {
+            for definition in &metadata.indexes {
+                repository.create_index(definition.clone())?;
+            }
+            if let Some(definition) = &metadata.text_index {
+                repository.create_text_index(definition.clone())?;
+            }
+            if let Some(config) = metadata.vector {
+                repository.declare_vector_config(config);
+            }
         }
 
-        let mut repository = FsRepository::<K, M>::new(name.clone(), full_path)?;
         repository.initialize().await?;
         self.repos
             .entry(name.clone())
@@ -99,25 +145,199 @@ impl FsDatabase {
         Ok(())
     }
 
+    // register_index creates a secondary index on a registered collection and declares it
+    pub async fn register_index<K, M>(&mut self, name: String, definition: IndexDefinition) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + Searchable,
+    {
+        self.repository::<K, M>(&name)?
+            .create_index(definition.clone())?;
+        self.declare_indexes(&name, vec![definition]).await
+    }
+
+    // register_text_index creates the full-text index of a registered collection and declares it
+    pub async fn register_text_index<K, M>(
+        &mut self,
+        name: String,
+        definition: TextIndexDefinition,
+    ) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + Searchable,
+    {
+        self.repository::<K, M>(&name)?
+            .create_text_index(definition.clone())?;
+
+        if let Some(metadata) = self.collections.get_mut(&name)
+            && metadata.text_index.as_ref() != Some(&definition)
+        {
+            metadata.text_index = Some(definition);
+            self.save_to_file().await?;
+        }
+        Ok(())
+    }
+
+    // register_vector_index creates the vector index of a registered collection and declares it.
+    // The index is loaded from its snapshot, so it can be registered on every start.
+    pub async fn register_vector_index<K, M>(
+        &mut self,
+        name: String,
+        definition: VectorIndexDefinition,
+    ) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + VectorEmbedding,
+    {
+        self.repository::<K, M>(&name)?
+            .create_vector_index(definition.clone())?;
+
+        if let Some(metadata) = self.collections.get_mut(&name)
+            && metadata.vector_index.as_ref() != Some(&definition)
+        {
+            metadata.vector_index = Some(definition);
+            self.save_to_file().await?;
+        }
+        Ok(())
+    }
+
+    // register_chunk_index creates the index of the chunk embeddings of a registered
+    // collection and declares it. The index is built from the records, so it is registered
+    // on every start after the collection.
+    pub async fn register_chunk_index<K, M>(&mut self, name: String, definition: VectorIndexDefinition) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + MultiVectorEmbedding,
+    {
+        self.repository::<K, M>(&name)?.create_chunk_index(definition.clone());
+
+        if let Some(metadata) = self.collections.get_mut(&name)
+            && metadata.chunk_index.as_ref() != Some(&definition)
+        {
+            metadata.chunk_index = Some(definition);
+            self.save_to_file().await?;
+        }
+        Ok(())
+    }
+
+    // register_vector_store keeps the vectors of a registered collection in a sidecar file
+    // and declares it. Like the vector index, it is registered on every start.
+    pub async fn register_vector_store<K, M>(&mut self, name: String) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + VectorEmbedding,
+    {
+        self.repository::<K, M>(&name)?.create_vector_store()?;
+
+        if let Some(metadata) = self.collections.get_mut(&name)
+            && !metadata.vector_store
+        {
+            metadata.vector_store = true;
+            self.save_to_file().await?;
+        }
+        Ok(())
+    }
+
+    // register_embedder embeds the text of the records written to a registered collection
+    // and of its query texts. Embedders are code, not metadata, so it is not saved and is
+    // registered on every start after the collection.
+    pub fn register_embedder<K, M>(&mut self, name: String, embedder: Arc<dyn Embedder>) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + Embeddable,
+    {
+        self.repository::<K, M>(&name)?.set_embedder(embedder);
+        Ok(())
+    }
+
+    // register_vector_config declares the dimension, metric and storage of the embeddings
+    // of a registered collection, checked on every write and query. Registering the
+    // collection applies the declared config to queries, and to writes with
+    // register_vector_collection or once the vector index or store is registered.
+    pub async fn register_vector_config<K, M>(&mut self, name: String, config: VectorConfig) -> Result<()>
+    where
+        K: RepoKey,
+        M: RepoModel<K> + VectorEmbedding,
+    {
+        self.repository::<K, M>(&name)?.set_vector_config(config);
+
+        if let Some(metadata) = self.collections.get_mut(&name)
+            && metadata.vector != Some(config)
+        {
+            metadata.vector = Some(config);
+            self.save_to_file().await?;
+        }
+        Ok(())
+    }
+
+    // open_repository creates the collection metadata and directory if they do not exist
+    async fn open_repository<K, M>(&mut self, name: &str) -> Result<FsRepository<K, M>>
+    where
+        K: RepoKey,
+        M: RepoModel<K>,
+    {
+        let full_path = PathBuf::from(&self.file_path).join(name);
+
+        if !self.collections.contains_key(name) {
+            let metadata = CollectionMetadata::new(name.to_string());
+            let _ = self.collections.insert(name.to_string(), metadata);
+            let _ = fs::create_dir_all(full_path.clone());
+            self.save_to_file().await?;
+        }
+
+        FsRepository::<K, M>::new(name.to_string(), full_path)
+    }
+
+    // declare_indexes adds index definitions to the collection metadata
+    async fn declare_indexes(&mut self, name: &str, indexes: Vec<IndexDefinition>) -> Result<()> {
+        let Some(metadata) = self.collections.get_mut(name) else {
+            return Ok(());
+        };
+
+        let mut changed = false;
+        for definition in indexes {
+            metadata.indexes.retain(|d| d.field != definition.field || *d == definition);
+            if !metadata.indexes.contains(&definition) {
+                metadata.indexes.push(definition);
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.save_to_file().await?;
+        }
+        Ok(())
+    }
+
     // collection check if the collection exists, errors if it does not
     pub async fn collection<K, M>(&mut self, name: String) -> Result<&mut dyn Repository<K, M>>
     where
         K: RepoKey,
         M: RepoModel<K>,
     {
-        if !self.repos.contains_key(&name) {
+        let repo = self.repository::<K, M>(&name)?;
+        Ok(repo)
+    }
+
+    // repository returns the concrete repository of the collection
+    fn repository<K, M>(&mut self, name: &str) -> Result<&mut FsRepository<K, M>>
+    where
+        K: RepoKey,
+        M: RepoModel<K>,
+    {
+        if !self.repos.contains_key(name) {
             return Err(anyhow::anyhow!(
                 FsDatabaseError::CollectionRepoisitoryMissingError {
-                    path: name.clone().into()
+                    path: name.into()
                 }
             ));
         }
 
         let v = self
             .repos
-            .get_mut(&name)
+            .get_mut(name)
             .ok_or(FsDatabaseError::CollectionRespositoryError {
-                path: name.clone().into(),
+                path: name.into(),
             });
 
         let any: &mut dyn Any = match v {
@@ -125,7 +345,7 @@ impl FsDatabase {
             Err(_e) => {
                 return Err(anyhow::anyhow!(
                     FsDatabaseError::CollectionRepoisitoryMissingError {
-                        path: name.clone().into()
+                        path: name.into()
                     }
                 ));
             }
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use thiserror::Error;
 
+use crate::vector::metric::DistanceMetric;
+
 #[derive(Error, Debug)]
 pub enum FsDatabaseError {
     #[error("Repository for collection {path} could not get created")]
@@ -24,6 +26,59 @@ pub enum FsRepositoryError {
 
     #[error("Failed to delete file to: {path}")]
     FileDeletion { path: PathBuf },
+
+    #[error("Unique constraint violation in {collection}: {field} value {value} already exists")]
+    UniqueConstraintViolation {
+        collection: String,
+        field: String,
+        value: String,
+    },
+
+    #[error("Collection {collection} has no text index")]
+    TextIndexMissing { collection: String },
+
+    #[error("Collection {collection} has no embedder")]
+    EmbedderMissing { collection: String },
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum VectorValidationError {
+    #[error("Vector of {collection} has dimension {actual}, expected {expected}")]
+    DimensionMismatch {
+        collection: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Vector of {collection} has a non-finite value {value} at position {position}")]
+    NonFiniteValue {
+        collection: String,
+        position: usize,
+        value: f32,
+    },
+
+    #[error("Query vector for {collection} has dimension {actual}, expected {expected}")]
+    QueryDimensionMismatch {
+        collection: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Query vector for {collection} has a non-finite value {value} at position {position}")]
+    NonFiniteQuery {
+        collection: String,
+        position: usize,
+        value: f32,
+    },
+
+    #[error("Chunk vectors of {collection} are compared by cosine similarity, not {metric:?}")]
+    UnsupportedChunkMetric {
+        collection: String,
+        metric: DistanceMetric,
+    },
+
+    #[error("Embedder returned {actual} embeddings for {expected} texts")]
+    EmbeddingCountMismatch { expected: usize, actual: usize },
 }
 
 #[derive(Error, Debug)]
@@ -40,4 +95,62 @@ pub enum RecordHeaderError {
         expected: u32,
         actual: u32,
     },
+
+    #[error("Truncated entry at offset {offset}")]
+    TruncatedEntry { offset: u64 },
+
+    #[error("Vector of dimension {dimension} is too long for the vector file")]
+    VectorTooLong { dimension: usize },
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum QueryParseError {
+    #[error("Unexpected character '{character}' at position {position}")]
+    UnexpectedCharacter { position: usize, character: char },
+
+    #[error("Unterminated string starting at position {position}")]
+    UnterminatedString { position: usize },
+
+    #[error("Unexpected '{found}' at position {position}, expected {expected}")]
+    UnexpectedToken {
+        position: usize,
+        found: String,
+        expected: String,
+    },
+
+    #[error("Unexpected end of query at position {position}, expected {expected}")]
+    UnexpectedEnd { position: usize, expected: String },
+
+    #[error("Invalid number '{literal}' at position {position}")]
+    InvalidNumber { position: usize, literal: String },
+
+    #[error("Invalid datetime '{literal}' at position {position}")]
+    InvalidDateTime { position: usize, literal: String },
+}
+
+impl QueryParseError {
+    // position returns the character offset in the query where the error was found
+    pub fn position(&self) -> usize {
+        match self {
+            QueryParseError::UnexpectedCharacter { position, .. }
+            | QueryParseError::UnterminatedString { position }
+            | QueryParseError::UnexpectedToken { position, .. }
+            | QueryParseError::UnexpectedEnd { position, .. }
+            | QueryParseError::InvalidNumber { position, .. }
+            | QueryParseError::InvalidDateTime { position, .. } => *position,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SearchValidationError {
+    #[error("Invalid value for {operator} on field {field}: expected {expected}")]
+    InvalidValue {
+        field: String,
+        operator: String,
+        expected: &'static str,
+    },
+
+    #[error("Invalid regex for field {field}: {message}")]
+    InvalidRegex { field: String, message: String },
 }
@@ -94,9 +94,9 @@ impl RecordHeader {
         Ok(())
     }
 
-    // fn has_flag(&self, flag: u16) -> bool {
-    //     self.flags & flag != 0
-    // }
+    pub(super) fn has_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
 
     fn set_flag(&mut self, flag: u16) {
         self.flags |= flag;
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::Bound;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::SortValue;
+use crate::fs::search::{SearchCondition, SearchOp, SearchValue};
+
+// IndexDefinition declares a secondary index on a model field. A unique index
+// allows each non-null value to be held by one live record only.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub field: String,
+    #[serde(default)]
+    pub unique: bool,
+}
+
+impl IndexDefinition {
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            unique: false,
+        }
+    }
+
+    pub fn unique(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            unique: true,
+        }
+    }
+}
+
+// FieldExtractor reads a field value from a model, normally Searchable::get_field_value
+pub type FieldExtractor<M> = fn(&M, &str) -> Option<SortValue>;
+
+// SecondaryIndex maps the values of a field to the ids of the records holding them.
+// Values are ordered so range conditions can be answered; null and missing values
+// are not indexed.
+#[derive(Debug)]
+pub struct SecondaryIndex<K, M> {
+    definition: IndexDefinition,
+    extract: FieldExtractor<M>,
+    values: BTreeMap<SortValue, HashSet<K>>,
+    keys: HashMap<K, SortValue>,
+}
+
+impl<K, M> SecondaryIndex<K, M>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new(definition: IndexDefinition, extract: FieldExtractor<M>) -> Self {
+        Self {
+            definition,
+            extract,
+            values: BTreeMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn definition(&self) -> &IndexDefinition {
+        &self.definition
+    }
+
+    pub fn field(&self) -> &str {
+        &self.definition.field
+    }
+
+    // value returns the indexed value of the model's field, None for null or missing values
+    pub fn value(&self, model: &M) -> Option<SortValue> {
+        (self.extract)(model, &self.definition.field).filter(|v| !v.is_null())
+    }
+
+    // conflict returns the value of a unique index that a record other than id already holds
+    pub fn conflict(&self, id: &K, model: &M) -> Option<SortValue> {
+        if !self.definition.unique {
+            return None;
+        }
+        let value = self.value(model)?;
+        let ids = self.values.get(&value)?;
+        ids.iter().any(|other| other != id).then_some(value)
+    }
+
+    // insert indexes the model, replacing the value previously indexed for the id
+    pub fn insert(&mut self, id: K, model: &M) {
+        self.remove(&id);
+        if let Some(value) = self.value(model) {
+            self.values.entry(value.clone()).or_default().insert(id.clone());
+            self.keys.insert(id, value);
+        }
+    }
+
+    pub fn remove(&mut self, id: &K) {
+        if let Some(value) = self.keys.remove(id)
+            && let Some(ids) = self.values.get_mut(&value)
+        {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.values.remove(&value);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.keys.clear();
+    }
+
+    // len returns the number of indexed records
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // lookup returns the ids that can satisfy the condition, or None when the
+    // condition cannot be answered from the index
+    pub fn lookup(&self, condition: &SearchCondition) -> Option<HashSet<K>> {
+        let buckets = self.buckets(condition)?;
+        Some(buckets.into_iter().flatten().cloned().collect())
+    }
+
+    // estimate returns the number of ids lookup would return, without collecting them
+    pub fn estimate(&self, condition: &SearchCondition) -> Option<usize> {
+        let buckets = self.buckets(condition)?;
+        Some(buckets.into_iter().map(HashSet::len).sum())
+    }
+
+    // key returns the indexed value of the record with the id
+    pub fn key(&self, id: &K) -> Option<&SortValue> {
+        self.keys.get(id)
+    }
+
+    // runs returns the ids grouped by value, in ascending value order
+    pub fn runs(&self) -> impl DoubleEndedIterator<Item = (&SortValue, &HashSet<K>)> {
+        self.values.iter()
+    }
+
+    // buckets returns the id sets of the values that satisfy the condition
+    fn buckets(&self, condition: &SearchCondition) -> Option<Vec<&HashSet<K>>> {
+        if condition.field != self.definition.field {
+            return None;
+        }
+
+        let buckets = match (condition.operator, &condition.value) {
+            (SearchOp::Eq, value) => {
+                let value = indexable(value)?;
+                self.values.get(&value).into_iter().collect()
+            }
+            (SearchOp::In, SearchValue::Array(values)) => {
+                let values = values.iter().map(indexable).collect::<Option<Vec<_>>>()?;
+                let mut buckets: Vec<&HashSet<K>> = Vec::new();
+                for value in values {
+                    // equal values share a bucket, which must not be counted twice
+                    if let Some(ids) = self.values.get(&value)
+                        && !buckets.iter().any(|b| std::ptr::eq(*b, ids))
+                    {
+                        buckets.push(ids);
+                    }
+                }
+                buckets
+            }
+            (SearchOp::Gt | SearchOp::Gte | SearchOp::Lt | SearchOp::Lte, value) => {
+                let value = indexable(value)?;
+                let (low, high) = match condition.operator {
+                    SearchOp::Gt => (Bound::Excluded(value.clone()), Bound::Unbounded),
+                    SearchOp::Gte => (Bound::Included(value.clone()), Bound::Unbounded),
+                    SearchOp::Lt => (Bound::Unbounded, Bound::Excluded(value.clone())),
+                    _ => (Bound::Unbounded, Bound::Included(value.clone())),
+                };
+                self.range_buckets((low, high), &value)
+            }
+            (SearchOp::Between, SearchValue::Array(bounds)) if bounds.len() == 2 => {
+                let low = indexable(&bounds[0])?;
+                let high = indexable(&bounds[1])?;
+                if !low.comparable_with(&high) || low > high {
+                    return Some(Vec::new());
+                }
+                self.range_buckets((Bound::Included(low.clone()), Bound::Included(high)), &low)
+            }
+            (SearchOp::StartsWith, SearchValue::String(prefix)) => self
+                .values
+                .range(SortValue::String(prefix.clone())..)
+                .take_while(|(value, _)| {
+                    matches!(value, SortValue::String(s) if s.starts_with(prefix.as_str()))
+                })
+                .map(|(_, ids)| ids)
+                .collect(),
+            _ => return None,
+        };
+        Some(buckets)
+    }
+
+    // range_buckets returns the id sets of values in range that belong to the type class of value
+    fn range_buckets(
+        &self,
+        range: (Bound<SortValue>, Bound<SortValue>),
+        value: &SortValue,
+    ) -> Vec<&HashSet<K>> {
+        self.values
+            .range(range)
+            .filter(|(v, _)| v.comparable_with(value))
+            .map(|(_, ids)| ids)
+            .collect()
+    }
+}
+
+// indexable converts a condition value that can be looked up in an index
+fn indexable(value: &SearchValue) -> Option<SortValue> {
+    value.to_sort_value().filter(|v| !v.is_null())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::core::SortValue;
+    use crate::fs::index::{IndexDefinition, SecondaryIndex};
+    use crate::fs::search::{SearchCondition, SearchOp, SearchValue};
+
+    fn value_of(model: &(i64, Option<SortValue>), _field: &str) -> Option<SortValue> {
+        model.1.clone()
+    }
+
+    fn index() -> SecondaryIndex<i64, (i64, Option<SortValue>)> {
+        let mut index = SecondaryIndex::new(IndexDefinition::new("value"), value_of);
+        let models = [
+            (1, Some(SortValue::Int(10))),
+            (2, Some(SortValue::Int(20))),
+            (3, Some(SortValue::Float(20.0))),
+            (4, Some(SortValue::String("apple".to_string()))),
+            (5, Some(SortValue::String("apricot".to_string()))),
+            (6, None),
+            (7, Some(SortValue::Null)),
+        ];
+        for model in models {
+            index.insert(model.0, &model);
+        }
+        index
+    }
+
+    fn lookup(operator: SearchOp, value: SearchValue) -> Option<HashSet<i64>> {
+        let condition = SearchCondition {
+            field: "value".to_string(),
+            operator,
+            value,
+        };
+        index().lookup(&condition)
+    }
+
+    fn ids(ids: &[i64]) -> Option<HashSet<i64>> {
+        Some(ids.iter().copied().collect())
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(index().len(), 5);
+        assert_eq!(lookup(SearchOp::Eq, SearchValue::Int(20)), ids(&[2, 3]));
+        assert_eq!(lookup(SearchOp::Gt, SearchValue::Int(10)), ids(&[2, 3]));
+        assert_eq!(lookup(SearchOp::Gte, SearchValue::Int(10)), ids(&[1, 2, 3]));
+        assert_eq!(lookup(SearchOp::Lt, SearchValue::Float(20.0)), ids(&[1]));
+        assert_eq!(lookup(SearchOp::Lte, SearchValue::String("b".into())), ids(&[4, 5]));
+        assert_eq!(
+            lookup(
+                SearchOp::Between,
+                SearchValue::Array(vec![SearchValue::Int(15), SearchValue::Int(25)])
+            ),
+            ids(&[2, 3])
+        );
+        assert_eq!(
+            lookup(
+                SearchOp::In,
+                SearchValue::Array(vec![SearchValue::Int(10), SearchValue::String("apple".into())])
+            ),
+            ids(&[1, 4])
+        );
+        assert_eq!(lookup(SearchOp::StartsWith, SearchValue::String("ap".into())), ids(&[4, 5]));
+        assert_eq!(
+            lookup(
+                SearchOp::In,
+                SearchValue::Array(vec![SearchValue::Int(20), SearchValue::Float(20.0)])
+            ),
+            ids(&[2, 3])
+        );
+        assert_eq!(lookup(SearchOp::Eq, SearchValue::Null), None);
+        assert_eq!(lookup(SearchOp::Ne, SearchValue::Int(10)), None);
+        assert_eq!(lookup(SearchOp::Contains, SearchValue::String("p".into())), None);
+    }
+
+    #[test]
+    fn test_estimate_and_runs() {
+        let index = index();
+        let condition = |operator, value| SearchCondition {
+            field: "value".to_string(),
+            operator,
+            value,
+        };
+        assert_eq!(index.estimate(&condition(SearchOp::Eq, SearchValue::Int(20))), Some(2));
+        assert_eq!(index.estimate(&condition(SearchOp::Lt, SearchValue::Int(100))), Some(3));
+        assert_eq!(
+            index.estimate(&condition(
+                SearchOp::In,
+                SearchValue::Array(vec![SearchValue::Int(20), SearchValue::Float(20.0)])
+            )),
+            Some(2)
+        );
+        assert_eq!(index.estimate(&condition(SearchOp::Exists, SearchValue::Bool(true))), None);
+
+        let runs: Vec<usize> = index.runs().map(|(_, ids)| ids.len()).collect();
+        assert_eq!(runs, vec![1, 2, 1, 1]);
+        assert_eq!(index.key(&3), Some(&SortValue::Int(20)));
+        assert_eq!(index.key(&6), None);
+    }
+
+    #[test]
+    fn test_insert_replaces_and_remove() {
+        let mut index = index();
+        index.insert(1, &(1, Some(SortValue::Int(30))));
+        index.remove(&2);
+
+        let condition = SearchCondition {
+            field: "value".to_string(),
+            operator: SearchOp::Gte,
+            value: SearchValue::Int(0),
+        };
+        assert_eq!(index.lookup(&condition), ids(&[1, 3]));
+
+        index.insert(3, &(3, None));
+        assert_eq!(index.lookup(&condition), ids(&[1]));
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn test_unique_conflict() {
+        let mut index = SecondaryIndex::new(IndexDefinition::unique("value"), value_of);
+        index.insert(1, &(1, Some(SortValue::Int(10))));
+
+        assert_eq!(index.conflict(&2, &(2, Some(SortValue::Int(10)))), Some(SortValue::Int(10)));
+        assert_eq!(index.conflict(&2, &(2, Some(SortValue::Float(10.0)))), Some(SortValue::Int(10)));
+        assert_eq!(index.conflict(&1, &(1, Some(SortValue::Int(10)))), None);
+        assert_eq!(index.conflict(&2, &(2, Some(SortValue::Int(11)))), None);
+        assert_eq!(index.conflict(&2, &(2, None)), None);
+
+        index.remove(&1);
+        assert_eq!(index.conflict(&2, &(2, Some(SortValue::Int(10)))), None);
+
+        let index = SecondaryIndex::new(IndexDefinition::new("value"), value_of);
+        assert_eq!(index.conflict(&2, &(2, Some(SortValue::Int(10)))), None);
+    }
+}
@@ -5,3 +5,10 @@ pub mod errors;
 pub mod utils;
 pub mod file;
 pub mod search;
+pub mod query;
+pub mod search_json;
+pub mod aggregate;
+pub mod index;
+pub mod planner;
+pub mod text;
+pub mod vectors;
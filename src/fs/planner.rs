@@ -0,0 +1,223 @@
+use std::fmt;
+use std::hash::Hash;
+
+use crate::fs::index::SecondaryIndex;
+use crate::fs::search::{SearchCriteria, SearchOp};
+
+// AccessPath is how the records of a query are found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessPath {
+    // Read every record
+    FullScan,
+    // Read the ids an index returns for one condition of the criteria
+    IndexLookup {
+        field: String,
+        operator: SearchOp,
+        condition: usize,
+    },
+}
+
+// QueryPlan describes how find executes a SearchCriteria
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPlan {
+    pub access: AccessPath,
+    // Index on the first sort field, used to read records in sort order
+    pub order_index: Option<String>,
+    // Whether the records still need apply_sort after they are read
+    pub needs_sort: bool,
+    // Whether reading stops once the limit is reached
+    pub limit_pushed_down: bool,
+    // Upper bound of the number of records read
+    pub estimated_records: usize,
+}
+
+// QueryExplain reports the plan of a query and what executing it cost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryExplain {
+    pub plan: QueryPlan,
+    pub records_scanned: usize,
+    pub records_returned: usize,
+}
+
+// plan_query picks the most selective index that can answer a condition, uses an
+// index on the first sort field to read records in order, and pushes the limit down
+// when the records are read in their final order
+pub fn plan_query<K, M>(
+    indexes: &[SecondaryIndex<K, M>],
+    criteria: &SearchCriteria,
+    total_records: usize,
+) -> QueryPlan
+where
+    K: Eq + Hash + Clone,
+{
+    let best = criteria
+        .conditions
+        .iter()
+        .enumerate()
+        .flat_map(|(position, condition)| {
+            indexes.iter().filter_map(move |index| {
+                index
+                    .estimate(condition)
+                    .map(|estimate| (estimate, position, condition))
+            })
+        })
+        .min_by_key(|(estimate, position, _)| (*estimate, *position));
+
+    let sort_fields = criteria.sort_fields.as_deref().unwrap_or(&[]);
+    let order_index = sort_fields
+        .first()
+        .filter(|sort_field| indexes.iter().any(|index| index.field() == sort_field.field))
+        .map(|sort_field| sort_field.field.clone());
+    let needs_sort = !(sort_fields.is_empty() || order_index.is_some() && sort_fields.len() == 1);
+    let limit_pushed_down = criteria.limit.is_some() && (sort_fields.is_empty() || order_index.is_some());
+
+    let (access, mut estimated_records) = match best {
+        Some((estimate, position, condition)) => (
+            AccessPath::IndexLookup {
+                field: condition.field.clone(),
+                operator: condition.operator,
+                condition: position,
+            },
+            estimate,
+        ),
+        None => (AccessPath::FullScan, total_records),
+    };
+
+    // Without conditions every record read is returned, so reading stops at the limit
+    if let Some(limit) = criteria.limit
+        && limit_pushed_down
+        && criteria.conditions.is_empty()
+    {
+        estimated_records = estimated_records.min(limit);
+    }
+
+    QueryPlan {
+        access,
+        order_index,
+        needs_sort,
+        limit_pushed_down,
+        estimated_records,
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.access {
+            AccessPath::FullScan => write!(f, "full scan")?,
+            AccessPath::IndexLookup {
+                field, operator, ..
+            } => write!(f, "index lookup on {} ({:?})", field, operator)?,
+        }
+        if let Some(field) = &self.order_index {
+            write!(f, ", ordered by index on {}", field)?;
+        }
+        if self.needs_sort {
+            write!(f, ", sort")?;
+        }
+        if self.limit_pushed_down {
+            write!(f, ", limit pushed down")?;
+        }
+        write!(f, ", estimated {} records", self.estimated_records)
+    }
+}
+
+impl fmt::Display for QueryExplain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, scanned {} records, returned {}",
+            self.plan, self.records_scanned, self.records_returned
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::SortValue;
+    use crate::fs::index::{IndexDefinition, SecondaryIndex};
+    use crate::fs::planner::{AccessPath, plan_query};
+    use crate::fs::search::{SearchCriteria, SearchOp, SearchValue};
+
+    type Model = (i64, i64, i64);
+
+    fn field_value(model: &Model, field: &str) -> Option<SortValue> {
+        match field {
+            "a" => Some(SortValue::Int(model.1)),
+            "b" => Some(SortValue::Int(model.2)),
+            _ => None,
+        }
+    }
+
+    // indexes on a (ten distinct values) and b (two distinct values) over 100 models
+    fn indexes() -> Vec<SecondaryIndex<i64, Model>> {
+        let mut a = SecondaryIndex::new(IndexDefinition::new("a"), field_value);
+        let mut b = SecondaryIndex::new(IndexDefinition::new("b"), field_value);
+        for id in 0..100 {
+            let model = (id, id % 10, id % 2);
+            a.insert(id, &model);
+            b.insert(id, &model);
+        }
+        vec![a, b]
+    }
+
+    #[test]
+    fn test_plan_picks_most_selective_index() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("b", SearchOp::Eq, SearchValue::Int(1));
+        criteria.add_condition("c", SearchOp::Eq, SearchValue::Int(1));
+        criteria.add_condition("a", SearchOp::Eq, SearchValue::Int(3));
+
+        let plan = plan_query(&indexes(), &criteria, 100);
+        assert_eq!(
+            plan.access,
+            AccessPath::IndexLookup {
+                field: "a".to_string(),
+                operator: SearchOp::Eq,
+                condition: 2
+            }
+        );
+        assert_eq!(plan.estimated_records, 10);
+        assert!(!plan.needs_sort);
+        assert!(!plan.limit_pushed_down);
+    }
+
+    #[test]
+    fn test_plan_full_scan_with_order_index() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("c", SearchOp::Gt, SearchValue::Int(1));
+        criteria.add_sort("b", false);
+        criteria.add_limit(5);
+
+        let plan = plan_query(&indexes(), &criteria, 100);
+        assert_eq!(plan.access, AccessPath::FullScan);
+        assert_eq!(plan.order_index.as_deref(), Some("b"));
+        assert!(!plan.needs_sort);
+        assert!(plan.limit_pushed_down);
+        assert_eq!(plan.estimated_records, 100);
+
+        criteria.conditions.clear();
+        criteria.add_sort("a", true);
+        let plan = plan_query(&indexes(), &criteria, 100);
+        assert!(plan.needs_sort);
+        assert!(plan.limit_pushed_down);
+        assert_eq!(plan.estimated_records, 5);
+    }
+
+    #[test]
+    fn test_plan_unindexed_sort_keeps_limit() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("a", SearchOp::Lt, SearchValue::Int(2));
+        criteria.add_sort("c", true);
+        criteria.add_limit(5);
+
+        let plan = plan_query(&indexes(), &criteria, 100);
+        assert_eq!(plan.estimated_records, 20);
+        assert_eq!(plan.order_index, None);
+        assert!(plan.needs_sort);
+        assert!(!plan.limit_pushed_down);
+        assert_eq!(
+            plan.to_string(),
+            "index lookup on a (Lt), sort, estimated 20 records"
+        );
+    }
+}
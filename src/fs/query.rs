@@ -0,0 +1,754 @@
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+
+use crate::fs::errors::QueryParseError;
+use crate::fs::search::{
+    NullsOrder, SearchCondition, SearchCriteria, SearchOp, SearchValue, SortField,
+};
+
+// Query syntax parsed into SearchCriteria:
+//
+//   status = "active" AND balance >= 100 ORDER BY name DESC LIMIT 20
+//
+// Conditions are joined with AND and use the operators =, !=, >, >=, <, <=,
+// BETWEEN a AND b, IN (..), NOT IN (..), EXISTS, NOT EXISTS, CONTAINS, STARTSWITH,
+// MATCHES (regex), IEQUALS, ICONTAINS and ISTARTSWITH. Values are "strings",
+// integers (Int), decimals such as 10.50 (Decimal), exponent numbers such as 2.5e0
+// (Float), true, false, null, DATETIME "2024-01-01T00:00:00Z" and (lists).
+// Sort fields take ASC or DESC and NULLS FIRST or NULLS LAST. Keywords are case
+// insensitive; field names that clash with keywords are quoted with backticks.
+
+const KEYWORDS: &[&str] = &[
+    "AND", "ASC", "BETWEEN", "BY", "CONTAINS", "DATETIME", "DESC", "EXISTS", "FALSE", "FIRST",
+    "ICONTAINS", "IEQUALS", "IN", "ISTARTSWITH", "LAST", "LIMIT", "MATCHES", "NOT", "NULL",
+    "NULLS", "ORDER", "STARTSWITH", "TRUE",
+];
+
+// parse_query parses the query syntax into SearchCriteria
+pub fn parse_query(input: &str) -> Result<SearchCriteria, QueryParseError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        end: input.chars().count(),
+    };
+    parser.parse_criteria()
+}
+
+impl FromStr for SearchCriteria {
+    type Err = QueryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_query(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    QuotedIdent(String),
+    Str(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) | Token::Number(word) => write!(f, "{}", word),
+            Token::QuotedIdent(ident) => write_ident(f, ident),
+            Token::Str(text) => write_string(f, text),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Lexed {
+    token: Token,
+    position: usize,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Lexed>, QueryParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let position = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            Token::Word(chars[position..i].iter().collect())
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            i += 1;
+            while i < chars.len() {
+                match chars[i] {
+                    d if d.is_ascii_digit() || d == '.' => i += 1,
+                    'e' | 'E' => {
+                        i += 1;
+                        if matches!(chars.get(i), Some('+') | Some('-')) {
+                            i += 1;
+                        }
+                    }
+                    _ => break,
+                }
+            }
+            Token::Number(chars[position..i].iter().collect())
+        } else if c == '"' {
+            let (text, next) = read_delimited(&chars, i, '"', true)?;
+            i = next;
+            Token::Str(text)
+        } else if c == '`' {
+            let (text, next) = read_delimited(&chars, i, '`', false)?;
+            i = next;
+            Token::QuotedIdent(text)
+        } else {
+            let next = chars.get(i + 1).copied();
+            let (symbol, width) = match (c, next) {
+                ('!', Some('=')) => ("!=", 2),
+                ('>', Some('=')) => (">=", 2),
+                ('<', Some('=')) => ("<=", 2),
+                ('=', _) => ("=", 1),
+                ('>', _) => (">", 1),
+                ('<', _) => ("<", 1),
+                ('(', _) => ("(", 1),
+                (')', _) => (")", 1),
+                (',', _) => (",", 1),
+                _ => {
+                    return Err(QueryParseError::UnexpectedCharacter {
+                        position,
+                        character: c,
+                    });
+                }
+            };
+            i += width;
+            Token::Symbol(symbol)
+        };
+
+        tokens.push(Lexed { token, position });
+    }
+
+    Ok(tokens)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+// read_delimited reads a quoted literal starting at the opening delimiter. A doubled
+// delimiter stands for itself; strings also support backslash escapes.
+fn read_delimited(
+    chars: &[char],
+    start: usize,
+    delimiter: char,
+    escapes: bool,
+) -> Result<(String, usize), QueryParseError> {
+    let mut text = String::new();
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == delimiter {
+            if chars.get(i + 1) == Some(&delimiter) {
+                text.push(delimiter);
+                i += 2;
+                continue;
+            }
+            return Ok((text, i + 1));
+        }
+        if escapes && c == '\\' {
+            match chars.get(i + 1) {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('r') => text.push('\r'),
+                Some(other) => text.push(*other),
+                None => break,
+            }
+            i += 2;
+            continue;
+        }
+        text.push(c);
+        i += 1;
+    }
+
+    Err(QueryParseError::UnterminatedString { position: start })
+}
+
+struct Parser {
+    tokens: Vec<Lexed>,
+    index: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn parse_criteria(&mut self) -> Result<SearchCriteria, QueryParseError> {
+        let mut criteria = SearchCriteria::new();
+
+        if self.peek().is_some() && !self.is_keyword("ORDER") && !self.is_keyword("LIMIT") {
+            loop {
+                let condition = self.parse_condition()?;
+                criteria.conditions.push(condition);
+                if !self.eat_keyword("AND") {
+                    break;
+                }
+            }
+        }
+
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let sort_field = self.parse_sort_field()?;
+                criteria.sort_fields.get_or_insert(Vec::new()).push(sort_field);
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        if self.eat_keyword("LIMIT") {
+            criteria.limit = Some(self.parse_limit()?);
+        }
+
+        if self.peek().is_some() {
+            return Err(self.error("AND, ORDER BY, LIMIT or end of query"));
+        }
+
+        Ok(criteria)
+    }
+
+    fn parse_condition(&mut self) -> Result<SearchCondition, QueryParseError> {
+        let field = self.parse_field()?;
+
+        let (operator, value) = if let Some(Token::Symbol(symbol)) = self.peek().map(|l| &l.token) {
+            let operator = match *symbol {
+                "=" => SearchOp::Eq,
+                "!=" => SearchOp::Ne,
+                ">" => SearchOp::Gt,
+                ">=" => SearchOp::Gte,
+                "<" => SearchOp::Lt,
+                "<=" => SearchOp::Lte,
+                _ => return Err(self.error("operator")),
+            };
+            self.index += 1;
+            (operator, self.parse_value()?)
+        } else if self.eat_keyword("NOT") {
+            if self.eat_keyword("IN") {
+                (SearchOp::NotIn, self.parse_value()?)
+            } else if self.eat_keyword("EXISTS") {
+                (SearchOp::Exists, SearchValue::Bool(false))
+            } else {
+                return Err(self.error("IN or EXISTS"));
+            }
+        } else if self.eat_keyword("EXISTS") {
+            (SearchOp::Exists, SearchValue::Bool(true))
+        } else if self.eat_keyword("BETWEEN") {
+            let low = self.parse_value()?;
+            self.expect_keyword("AND")?;
+            let high = self.parse_value()?;
+            (SearchOp::Between, SearchValue::Array(vec![low, high]))
+        } else {
+            let operator = if self.eat_keyword("IN") {
+                SearchOp::In
+            } else if self.eat_keyword("CONTAINS") {
+                SearchOp::Contains
+            } else if self.eat_keyword("STARTSWITH") {
+                SearchOp::StartsWith
+            } else if self.eat_keyword("MATCHES") {
+                SearchOp::Regex
+            } else if self.eat_keyword("IEQUALS") {
+                SearchOp::EqIgnoreCase
+            } else if self.eat_keyword("ICONTAINS") {
+                SearchOp::ContainsIgnoreCase
+            } else if self.eat_keyword("ISTARTSWITH") {
+                SearchOp::StartsWithIgnoreCase
+            } else {
+                return Err(self.error("operator"));
+            };
+            (operator, self.parse_value()?)
+        };
+
+        Ok(SearchCondition {
+            field,
+            operator,
+            value,
+        })
+    }
+
+    fn parse_sort_field(&mut self) -> Result<SortField, QueryParseError> {
+        let field = self.parse_field()?;
+
+        let ascending = if self.eat_keyword("DESC") {
+            false
+        } else {
+            self.eat_keyword("ASC");
+            true
+        };
+
+        let nulls = if self.eat_keyword("NULLS") {
+            if self.eat_keyword("FIRST") {
+                NullsOrder::First
+            } else if self.eat_keyword("LAST") {
+                NullsOrder::Last
+            } else {
+                return Err(self.error("FIRST or LAST"));
+            }
+        } else {
+            NullsOrder::default()
+        };
+
+        Ok(SortField {
+            field,
+            ascending,
+            nulls,
+        })
+    }
+
+    fn parse_limit(&mut self) -> Result<usize, QueryParseError> {
+        match self.peek() {
+            Some(Lexed {
+                token: Token::Number(literal),
+                position,
+            }) => {
+                let limit = literal.parse().map_err(|_| QueryParseError::InvalidNumber {
+                    position: *position,
+                    literal: literal.clone(),
+                })?;
+                self.index += 1;
+                Ok(limit)
+            }
+            _ => Err(self.error("limit")),
+        }
+    }
+
+    fn parse_field(&mut self) -> Result<String, QueryParseError> {
+        let field = match self.peek().map(|l| &l.token) {
+            Some(Token::Word(word)) if !is_keyword(word) => word.clone(),
+            Some(Token::QuotedIdent(ident)) => ident.clone(),
+            _ => return Err(self.error("field name")),
+        };
+        self.index += 1;
+        Ok(field)
+    }
+
+    fn parse_value(&mut self) -> Result<SearchValue, QueryParseError> {
+        let Some(Lexed { token, position }) = self.peek() else {
+            return Err(self.error("value"));
+        };
+        let position = *position;
+
+        let value = match token {
+            Token::Str(text) => SearchValue::String(text.clone()),
+            Token::Number(literal) => parse_number(literal, position)?,
+            Token::Word(word) if word.eq_ignore_ascii_case("TRUE") => SearchValue::Bool(true),
+            Token::Word(word) if word.eq_ignore_ascii_case("FALSE") => SearchValue::Bool(false),
+            Token::Word(word) if word.eq_ignore_ascii_case("NULL") => SearchValue::Null,
+            Token::Word(word) if word.eq_ignore_ascii_case("DATETIME") => {
+                self.index += 1;
+                return self.parse_datetime();
+            }
+            Token::Symbol("(") => {
+                self.index += 1;
+                return self.parse_list();
+            }
+            _ => return Err(self.error("value")),
+        };
+
+        self.index += 1;
+        Ok(value)
+    }
+
+    fn parse_datetime(&mut self) -> Result<SearchValue, QueryParseError> {
+        let datetime = match self.peek() {
+            Some(Lexed {
+                token: Token::Str(text),
+                position,
+            }) => bson::DateTime::parse_rfc3339_str(text).map_err(|_| {
+                QueryParseError::InvalidDateTime {
+                    position: *position,
+                    literal: text.clone(),
+                }
+            })?,
+            Some(Lexed {
+                token: Token::Number(literal),
+                position,
+            }) => {
+                let millis = literal.parse().map_err(|_| QueryParseError::InvalidDateTime {
+                    position: *position,
+                    literal: literal.clone(),
+                })?;
+                bson::DateTime::from_millis(millis)
+            }
+            _ => return Err(self.error("datetime string")),
+        };
+        self.index += 1;
+        Ok(SearchValue::DateTime(datetime))
+    }
+
+    fn parse_list(&mut self) -> Result<SearchValue, QueryParseError> {
+        let mut values = Vec::new();
+        if self.eat_symbol(")") {
+            return Ok(SearchValue::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            if self.eat_symbol(")") {
+                return Ok(SearchValue::Array(values));
+            }
+            if !self.eat_symbol(",") {
+                return Err(self.error("',' or ')'"));
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&Lexed> {
+        self.tokens.get(self.index)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek().map(|l| &l.token), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(keyword))
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek().map(|l| &l.token), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+
+    // error reports the current token, or the end of the query, as unexpected
+    fn error(&self, expected: &str) -> QueryParseError {
+        match self.peek() {
+            Some(lexed) => QueryParseError::UnexpectedToken {
+                position: lexed.position,
+                found: lexed.token.to_string(),
+                expected: expected.to_string(),
+            },
+            None => QueryParseError::UnexpectedEnd {
+                position: self.end,
+                expected: expected.to_string(),
+            },
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+// parse_number reads integers as Int, exponent notation as Float and other decimals as Decimal.
+// Floats overflowing to infinity are rejected, as Display could not print them back.
+fn parse_number(literal: &str, position: usize) -> Result<SearchValue, QueryParseError> {
+    let invalid = || QueryParseError::InvalidNumber {
+        position,
+        literal: literal.to_string(),
+    };
+
+    if literal.contains(['e', 'E']) {
+        match literal.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(SearchValue::Float(value)),
+            _ => Err(invalid()),
+        }
+    } else if literal.contains('.') {
+        Decimal::from_str(literal).map(SearchValue::Decimal).map_err(|_| invalid())
+    } else {
+        literal.parse().map(SearchValue::Int).map_err(|_| invalid())
+    }
+}
+
+fn write_ident(f: &mut fmt::Formatter<'_>, ident: &str) -> fmt::Result {
+    let mut chars = ident.chars();
+    let plain = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(is_ident_char)
+        && !is_keyword(ident);
+    if plain {
+        write!(f, "{}", ident)
+    } else {
+        write!(f, "`{}`", ident.replace('`', "``"))
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for SearchValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchValue::String(text) => write_string(f, text),
+            // Decimals keep a fraction so they parse back as Decimal rather than Int
+            SearchValue::Decimal(value) if value.scale() == 0 => write!(f, "{}.0", value),
+            SearchValue::Decimal(value) => write!(f, "{}", value),
+            SearchValue::Int(value) => write!(f, "{}", value),
+            SearchValue::Float(value) => write!(f, "{:e}", value),
+            SearchValue::Bool(value) => write!(f, "{}", value),
+            SearchValue::DateTime(value) => match value.try_to_rfc3339_string() {
+                Ok(text) => write!(f, "DATETIME \"{}\"", text),
+                Err(_) => write!(f, "DATETIME {}", value.timestamp_millis()),
+            },
+            SearchValue::Null => write!(f, "null"),
+            SearchValue::Array(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for SearchCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_ident(f, &self.field)?;
+        match (self.operator, &self.value) {
+            (SearchOp::Between, SearchValue::Array(bounds)) if bounds.len() == 2 => {
+                write!(f, " BETWEEN {} AND {}", bounds[0], bounds[1])
+            }
+            (SearchOp::Exists, SearchValue::Bool(false)) => write!(f, " NOT EXISTS"),
+            (SearchOp::Exists, _) => write!(f, " EXISTS"),
+            (operator, value) => {
+                let keyword = match operator {
+                    SearchOp::Eq => "=",
+                    SearchOp::Ne => "!=",
+                    SearchOp::Gt => ">",
+                    SearchOp::Gte => ">=",
+                    SearchOp::Lt => "<",
+                    SearchOp::Lte => "<=",
+                    SearchOp::Between => "BETWEEN",
+                    SearchOp::In => "IN",
+                    SearchOp::NotIn => "NOT IN",
+                    SearchOp::Exists => "EXISTS",
+                    SearchOp::Contains => "CONTAINS",
+                    SearchOp::StartsWith => "STARTSWITH",
+                    SearchOp::Regex => "MATCHES",
+                    SearchOp::EqIgnoreCase => "IEQUALS",
+                    SearchOp::ContainsIgnoreCase => "ICONTAINS",
+                    SearchOp::StartsWithIgnoreCase => "ISTARTSWITH",
+                };
+                write!(f, " {} {}", keyword, value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_ident(f, &self.field)?;
+        if !self.ascending {
+            write!(f, " DESC")?;
+        }
+        if self.nulls != NullsOrder::default() {
+            write!(f, " NULLS FIRST")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SearchCriteria {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+
+        for (i, condition) in self.conditions.iter().enumerate() {
+            write!(f, "{}{}", if i > 0 { " AND " } else { "" }, condition)?;
+            separator = " ";
+        }
+
+        if let Some(sort_fields) = self.sort_fields.as_ref().filter(|s| !s.is_empty()) {
+            write!(f, "{}ORDER BY ", separator)?;
+            for (i, sort_field) in sort_fields.iter().enumerate() {
+                write!(f, "{}{}", if i > 0 { ", " } else { "" }, sort_field)?;
+            }
+            separator = " ";
+        }
+
+        if let Some(limit) = self.limit {
+            write!(f, "{}LIMIT {}", separator, limit)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::fs::errors::QueryParseError;
+    use crate::fs::query::parse_query;
+    use crate::fs::search::{NullsOrder, SearchCriteria, SearchOp, SearchValue};
+
+    #[test]
+    fn test_parse_query() {
+        let criteria =
+            parse_query(r#"status = "active" AND balance >= 100 ORDER BY name DESC LIMIT 20"#)
+                .unwrap();
+
+        assert_eq!(criteria.conditions.len(), 2);
+        assert_eq!(criteria.conditions[0].field, "status");
+        assert_eq!(criteria.conditions[0].operator, SearchOp::Eq);
+        assert!(matches!(&criteria.conditions[0].value, SearchValue::String(s) if s == "active"));
+        assert_eq!(criteria.conditions[1].operator, SearchOp::Gte);
+        assert!(matches!(criteria.conditions[1].value, SearchValue::Int(100)));
+
+        let sort_fields = criteria.sort_fields.unwrap();
+        assert_eq!(sort_fields.len(), 1);
+        assert_eq!(sort_fields[0].field, "name");
+        assert!(!sort_fields[0].ascending);
+        assert_eq!(criteria.limit, Some(20));
+    }
+
+    #[test]
+    fn test_parse_operators_and_values() {
+        let criteria = parse_query(
+            "a BETWEEN 1.50 AND 2.5e0 and b not in (1, \"x\", null) AND c NOT EXISTS \
+             AND d EXISTS AND e MATCHES \"^a\\\\d\" AND f ICONTAINS \"Ab\" \
+             AND g = DATETIME \"2024-01-02T03:04:05Z\" AND `order` != false \
+             order by a, b asc nulls first",
+        )
+        .unwrap();
+
+        let ops: Vec<SearchOp> = criteria.conditions.iter().map(|c| c.operator).collect();
+        assert_eq!(
+            ops,
+            vec![
+                SearchOp::Between,
+                SearchOp::NotIn,
+                SearchOp::Exists,
+                SearchOp::Exists,
+                SearchOp::Regex,
+                SearchOp::ContainsIgnoreCase,
+                SearchOp::Eq,
+                SearchOp::Ne,
+            ]
+        );
+        match &criteria.conditions[0].value {
+            SearchValue::Array(bounds) => {
+                assert!(matches!(bounds[0], SearchValue::Decimal(d) if d == Decimal::new(150, 2)));
+                assert!(matches!(bounds[1], SearchValue::Float(f) if f == 2.5));
+            }
+            other => panic!("unexpected value {:?}", other),
+        }
+        assert!(matches!(criteria.conditions[2].value, SearchValue::Bool(false)));
+        assert!(matches!(&criteria.conditions[4].value, SearchValue::String(s) if s == "^a\\d"));
+        assert!(matches!(
+            criteria.conditions[6].value,
+            SearchValue::DateTime(d) if d.timestamp_millis() == 1_704_164_645_000
+        ));
+        assert_eq!(criteria.conditions[7].field, "order");
+
+        let sort_fields = criteria.sort_fields.unwrap();
+        assert!(sort_fields[0].ascending);
+        assert_eq!(sort_fields[0].nulls, NullsOrder::Last);
+        assert_eq!(sort_fields[1].nulls, NullsOrder::First);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let queries = [
+            r#"status = "active" AND balance >= 100 ORDER BY name DESC LIMIT 20"#,
+            r#"a BETWEEN 1.50 AND 2.5e0 AND b NOT IN (1, "x\"y", null) AND c NOT EXISTS"#,
+            r#"d EXISTS AND e MATCHES "^a\\d" AND f ISTARTSWITH "Ab" AND g IEQUALS "x""#,
+            r#"h = DATETIME "2024-01-02T03:04:05Z" AND `order` != false AND i = 10.0"#,
+            "j CONTAINS \"a\\nb\" AND k STARTSWITH \"\" AND l IN () ORDER BY a, b NULLS FIRST",
+            "m = 1.7976931348623157e308 AND n = -5e-324 AND o = 0e0",
+            "LIMIT 5",
+            "",
+        ];
+
+        for query in queries {
+            let criteria: SearchCriteria = query.parse().unwrap();
+            let printed = criteria.to_string();
+            assert_eq!(printed, query);
+            let reparsed = parse_query(&printed).unwrap();
+            assert_eq!(reparsed.to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = parse_query(r#"status = "active" AND balance >> 100"#).unwrap_err();
+        assert_eq!(error.position(), 31);
+        assert!(matches!(error, QueryParseError::UnexpectedToken { .. }));
+
+        let error = parse_query(r#"status = "active"#).unwrap_err();
+        assert_eq!(error, QueryParseError::UnterminatedString { position: 9 });
+
+        let error = parse_query("status =").unwrap_err();
+        assert_eq!(
+            error,
+            QueryParseError::UnexpectedEnd {
+                position: 8,
+                expected: "value".to_string()
+            }
+        );
+
+        let error = parse_query("limit = 5").unwrap_err();
+        assert_eq!(error.position(), 6);
+
+        let error = parse_query("a = 1 LIMIT -1").unwrap_err();
+        assert!(matches!(error, QueryParseError::InvalidNumber { position: 12, .. }));
+
+        let error = parse_query("a = 1.2.3").unwrap_err();
+        assert!(matches!(error, QueryParseError::InvalidNumber { position: 4, .. }));
+
+        // floats overflowing to infinity would print as inf, which does not parse
+        let error = parse_query("a = 1e999").unwrap_err();
+        assert!(matches!(error, QueryParseError::InvalidNumber { position: 4, .. }));
+
+        let error = parse_query("a = DATETIME \"yesterday\"").unwrap_err();
+        assert!(matches!(error, QueryParseError::InvalidDateTime { position: 13, .. }));
+
+        let error = parse_query("a = 1 b = 2").unwrap_err();
+        assert_eq!(error.position(), 6);
+
+        let error = parse_query("a = 1 # 2").unwrap_err();
+        assert_eq!(
+            error,
+            QueryParseError::UnexpectedCharacter {
+                position: 6,
+                character: '#'
+            }
+        );
+    }
+}
@@ -1,19 +1,40 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use std::any::Any;
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
 use std::io::{Seek, SeekFrom};
 use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
 use std::{fmt::Debug, path::PathBuf};
-use tracing::{debug, info};
+use rayon::prelude::*;
+use tracing::{debug, info, warn};
 
 use crate::core::{
-    Searchable, Initializable, RepoKey, RepoModel, Repository, VectorEmbedding
+    Embeddable, Searchable, Initializable, MultiVectorEmbedding, RepoKey, RepoModel, Repository, SortValue, VectorEmbedding
 };
+use crate::fs::aggregate::{AggregateQuery, AggregateRow, Aggregator};
+use crate::fs::errors::{FsRepositoryError, VectorValidationError};
+use crate::fs::index::{IndexDefinition, SecondaryIndex};
 use crate::fs::file::{RECORD_TYPE_ACTIVE, RECORD_TYPE_DELETED, read_record, write_active_record};
-use crate::fs::search::{SearchCriteria, apply_sort};
-use crate::vector::search::vector_search;
+use crate::fs::planner::{AccessPath, QueryExplain, QueryPlan, plan_query};
+use crate::fs::search::{NullsOrder, SearchCriteria, SortField, apply_sort};
+use crate::fs::text::{TextIndex, TextIndexDefinition};
+use crate::fs::vectors::VectorStore;
+use crate::vector::config::VectorConfig;
+use crate::vector::embed::{Embedder, ModelEmbedder};
+use crate::vector::storage::{OwnedValues, QueryVector, StoredValues, VectorStorage};
+use crate::vector::hybrid::{Fusion, fuse};
+use crate::vector::index::{EmbeddingIndex, VectorExtractor, VectorIndexDefinition};
+use crate::vector::metric::DistanceMetric;
+use crate::vector::mmr::{Mmr, mmr_select};
+use crate::vector::multi::{
+    ChunkExtractor, ChunkIndex, DocumentMatch, MultiVectorOptions, aggregate_chunks, matched_chunks, multi_vector_search,
+};
+use crate::vector::search::{VectorSearchOptions, select_top_k_by_position, vector_search_batch};
+use crate::vector::similarity::cosine_similarity;
 
 #[derive(Debug)]
 pub struct FsRepository<K, M>
@@ -25,6 +46,21 @@ where
     collection_path: PathBuf,
     file: File,
     offsetm: HashMap<K, u64>,
+    indexes: Vec<SecondaryIndex<K, M>>,
+    text_index: Option<TextIndex<K, M>>,
+    vector_index: Option<EmbeddingIndex<K, M>>,
+    chunk_index: Option<ChunkIndex<K, M>>,
+    vector_store: Option<VectorStore<K, M>>,
+    // format of the vectors in the vector store
+    vector_storage: VectorStorage,
+    // embeds the text of written records, with the accessors of their text and vector
+    embedder: Option<ModelEmbedder<M>>,
+    // declared embeddings, with the extractors of the vector and chunk vectors of written
+    // records to check them
+    vector_config: Option<VectorConfig>,
+    vector_extract: Option<VectorExtractor<M>>,
+    chunk_extract: Option<ChunkExtractor<M>>,
+    metric: DistanceMetric,
     _phantom: PhantomData<(K, M)>,
 }
 
@@ -34,6 +70,13 @@ where
     M: RepoModel<K>,
 {
     pub fn new(name: String, collection_path: PathBuf) -> Result<Self> {
+        // Create the directories if they do not exist
+        fs::create_dir_all(&collection_path).with_context(|| {
+            FsRepositoryError::DirectoryCreation {
+                path: collection_path.clone(),
+            }
+        })?;
+
         let file = OpenOptions::new()
             .read(true)
             .create(true) // Create the file if it doesn't exist
@@ -45,233 +88,2554 @@ where
             collection_path,
             file,
             offsetm: HashMap::new(),
+            indexes: Vec::new(),
+            text_index: None,
+            vector_index: None,
+            chunk_index: None,
+            vector_store: None,
+            vector_storage: VectorStorage::default(),
+            embedder: None,
+            vector_config: None,
+            vector_extract: None,
+            chunk_extract: None,
+            metric: DistanceMetric::default(),
             _phantom: PhantomData,
         })
     }
 
-    fn file_path(name: &str, collection_path: &PathBuf) -> PathBuf {
+    fn file_path(name: &str, collection_path: &Path) -> PathBuf {
         collection_path.join(format!("{}.bin", &name))
     }
-}
 
-#[async_trait]
-impl<K, M> Initializable for FsRepository<K, M>
-where
-    K: RepoKey,
-    M: RepoModel<K>,
-{
-    async fn initialize(&mut self) -> Result<()> {
-        let mut offset = self.file.seek(SeekFrom::Start(0))?;
-        info!("Initializing repo: {}...", self.name);
-        loop {
-            let (header, model) = match read_record::<M>(&mut self.file, offset) {
-                Ok((header, model)) => (header, model),
-                Err(_e) => {
-                    // warn!("Read error: {}", e);
-                    break;
-                }
-            };
+    fn text_index_path(&self) -> PathBuf {
+        self.collection_path.join(format!("{}.fts", &self.name))
+    }
 
-            debug!("Record Type: {:?}", header.record_type);
-            match header.record_type {
-                RECORD_TYPE_ACTIVE => {
-                    self.offsetm.insert(model.id(), offset);
-                }
-                RECORD_TYPE_DELETED => {
-                    self.offsetm.remove(&model.id());
-                }
-                _ => {
-                    break;
-                }
+    // create_index registers a secondary index on a field and builds it from the
+    // current records. Indexes created before initialize are built by initialize.
+    // A unique index fails to build when two records hold the same value.
+    pub fn create_index(&mut self, definition: IndexDefinition) -> Result<()>
+    where
+        M: Searchable,
+    {
+        if self.indexes.iter().any(|index| *index.definition() == definition) {
+            return Ok(());
+        }
+
+        let mut index = SecondaryIndex::new(definition, M::get_field_value);
+        let mut conflict = None;
+        self.for_each_record(|model| {
+            if conflict.is_none() {
+                conflict = index.conflict(&model.id(), &model);
             }
-            offset = self.file.stream_position()?;
+            index.insert(model.id(), &model);
+        });
+        if let Some(value) = conflict {
+            return Err(self.unique_violation(index.field(), &value));
         }
-        info!("Initializing done.");
+
+        self.indexes.retain(|existing| existing.field() != index.field());
+        debug!("Created index on {} with {} records", index.field(), index.len());
+        self.indexes.push(index);
         Ok(())
     }
 
-    async fn shutdown(&mut self) -> Result<()> {
-        Ok(())
+    // create_text_index registers the full-text index of the collection, replacing any
+    // previous one. It starts from the snapshot in the .fts file when it was built with
+    // the same definition, and applies the records written after it.
+    pub fn create_text_index(&mut self, definition: TextIndexDefinition) -> Result<()>
+    where
+        M: Searchable,
+    {
+        if self
+            .text_index
+            .as_ref()
+            .is_some_and(|index| *index.definition() == definition)
+        {
+            return Ok(());
+        }
+
+        self.text_index = Some(TextIndex::new(definition, M::get_field_value));
+        self.load_text_index()
     }
-    fn as_any(&mut self) -> &dyn Any {
-        self
+
+    // text_index returns the definition of the full-text index
+    pub fn text_index(&self) -> Option<&TextIndexDefinition> {
+        self.text_index.as_ref().map(|index| index.definition())
     }
 
-    fn as_any_mut(&mut self) -> &mut dyn Any {
-        self
+    // load_text_index loads the snapshot of the text index, or clears the index when
+    // the snapshot cannot be used, then catches up with the log
+    fn load_text_index(&mut self) -> Result<()> {
+        let path = self.text_index_path();
+        let log_length = self.file.seek(SeekFrom::End(0))?;
+        let Some(index) = self.text_index.as_mut() else {
+            return Ok(());
+        };
+
+        if let Err(e) = index.load(&path, log_length) {
+            warn!("Ignoring text index snapshot {:?}: {}", path, e);
+            index.clear();
+        }
+        self.catch_up_text_index()
     }
-}
 
-#[async_trait]
-impl<K, M> Repository<K, M> for FsRepository<K, M>
-where
-    K: RepoKey,
-    M: RepoModel<K>,
-{
-    // insert creates a new json file for the chat id. creates the directory structure too.
-    async fn insert(&mut self, model: M) -> Result<()> {
-        // // Create the directories if they do not exist
-        // if !self.collection_path.exists() {
-        //     let _ = fs::create_dir_all(&self.collection_path)
-        //         .await
-        //         .with_context(|| FsRepositoryError::DirectoryCreation {
-        //             path: (self.collection_path.clone()),
-        //         })?;
-        // }
-        let offset = write_active_record(&mut self.file, RECORD_TYPE_ACTIVE, &model, false)?;
-        self.offsetm.insert(model.id(), offset);
-        debug!("Insert id:{} at offset:{}", model.id(), offset);
+    // catch_up_text_index applies the records past the watermark of the text index and
+    // saves a new snapshot when there were any
+    fn catch_up_text_index(&mut self) -> Result<()> {
+        let path = self.text_index_path();
+        let Some(index) = self.text_index.as_mut() else {
+            return Ok(());
+        };
+
+        let (offset, applied) = replay_records::<M>(&mut self.file, index.watermark(), |record_type, model| {
+            match record_type {
+                RECORD_TYPE_DELETED => index.remove(&model.id()),
+                _ => index.insert(model.id(), &model),
+            }
+        })?;
+
+        if applied > 0 {
+            index.set_watermark(offset);
+            index.save(&path)?;
+            debug!("Text index of {} caught up with {} records", self.name, applied);
+        }
         Ok(())
     }
 
-    // delete appends the delete record
-    async fn delete(&mut self, model: M) -> Result<()> {
-        let _ = write_active_record(&mut self.file, RECORD_TYPE_DELETED, &model, false)?;
-        self.offsetm.remove(&model.id());
-        Ok(())
+    fn vector_index_path(&self) -> PathBuf {
+        self.collection_path.join(format!("{}.vidx", &self.name))
     }
 
-    // find_by_id finds the json file for the id, marshalls that into the object
-    async fn find_by_id(&mut self, id: K) -> Option<M> {
-        let offset = self.offsetm.get(&id)?;
-        debug!("Find_by_id Id:{} offset:{}", id, offset);
-        let (_, model) = read_record::<M>(&mut self.file, *offset).ok()?;
-        Some(model)
+    // create_vector_index registers the vector index used by semantic_search, replacing
+    // any previous one. It starts from the snapshot in the .vidx file when it was built
+    // with the same parameters, and applies the records written after it.
+    pub fn create_vector_index(&mut self, definition: VectorIndexDefinition) -> Result<()>
+    where
+        M: VectorEmbedding,
+    {
+        if self
+            .vector_index
+            .as_ref()
+            .is_some_and(|index| *index.definition() == definition)
+        {
+            return Ok(());
+        }
+
+        self.vector_extract = Some(M::vector);
+        self.vector_index = Some(EmbeddingIndex::new(definition, M::vector));
+        self.load_vector_index()
     }
 
-    // find_all returns all values from offset map
-    async fn find_all(&mut self) -> Vec<M> {
-        let mut values = Vec::<M>::new();
-        debug!("Find_all Offset map length: {}", self.offsetm.len());
-        for offset in self.offsetm.values() {
-            if let Some((_, model)) = read_record::<M>(&mut self.file, *offset).ok() {
-                values.push(model);
-            };
+    // create_chunk_index registers the vector index of the chunk embeddings used by
+    // multi_vector_search, replacing any previous one, and builds it from the current
+    // records. Like secondary indexes it is rebuilt by initialize, not saved. The chunk
+    // vectors of written records are checked against the vector config from now on.
+    pub fn create_chunk_index(&mut self, definition: VectorIndexDefinition)
+    where
+        M: MultiVectorEmbedding,
+    {
+        if self
+            .chunk_index
+            .as_ref()
+            .is_some_and(|index| *index.definition() == definition)
+        {
+            return;
         }
-        values
+
+        self.chunk_extract = Some(M::vectors);
+        let mut index = ChunkIndex::new(definition, M::vectors);
+        self.for_each_record(|model| index.insert(model.id(), &model));
+        debug!("Chunk index of {} built with {} chunks", self.name, index.index().len());
+        self.chunk_index = Some(index);
     }
 
-    // find_finds filtered values
-    async fn find(&mut self, criteria: Option<SearchCriteria>) -> Vec<M> 
-        where M: Searchable{
-        let mut items = self.find_all().await;
-        if let Some(f) = criteria {
-            // Apply conditions
-            items = items.into_iter().filter(|item| item.matches_filter(&f)).collect();
+    // chunk_index returns the definition of the chunk index
+    pub fn chunk_index(&self) -> Option<&VectorIndexDefinition> {
+        self.chunk_index.as_ref().map(|index| index.definition())
+    }
 
-            // Apply sort
-            if let Some(sort_fields) = f.sort_fields {
-                items = apply_sort(items, &sort_fields);
-            }
+    fn vector_store_path(&self) -> PathBuf {
+        self.collection_path.join(format!("{}.vec", &self.name))
+    }
 
-            // Apply limit
-            if let Some(limit) = f.limit {
-                items.truncate(limit);
-            }
+    // create_vector_store keeps the vectors of the records in the .vec sidecar file, so
+    // unfiltered semantic searches scan them without deserializing the records. Vectors
+    // of records written before are appended to the file.
+    pub fn create_vector_store(&mut self) -> Result<()>
+    where
+        M: VectorEmbedding,
+    {
+        if self.vector_store.is_some() {
+            return Ok(());
         }
-        items
+        self.vector_extract = Some(M::vector);
+        let mut store = VectorStore::open(&self.vector_store_path(), M::vector)?;
+        store.set_storage(self.vector_storage);
+        store.sync(&mut self.file, &self.offsetm)?;
+        self.vector_store = Some(store);
+        Ok(())
     }
 
+    pub fn has_vector_store(&self) -> bool {
+        self.vector_store.is_some()
+    }
 
-    async fn semantic_search(
+    // set_vector_storage sets the format of the vectors appended to the vector store. Vectors
+    // already in the store keep their format until their record is written again.
+    pub fn set_vector_storage(&mut self, storage: VectorStorage) {
+        self.vector_storage = storage;
+        if let Some(store) = self.vector_store.as_mut() {
+            store.set_storage(storage);
+        }
+    }
+
+    pub fn vector_storage(&self) -> VectorStorage {
+        self.vector_storage
+    }
+
+    // write_record appends the record to the log, and its vector to the vector store
+    fn write_record(&mut self, model: &M) -> Result<u64> {
+        let has_vector = self.vector_store.is_some();
+        let offset = write_active_record(&mut self.file, RECORD_TYPE_ACTIVE, model, has_vector)?;
+        if let Some(store) = self.vector_store.as_mut() {
+            store.append(model.id(), model, offset)?;
+        }
+        Ok(offset)
+    }
+
+    // filtered_semantic_search ranks the records matching the conditions of the criteria,
+    // keeping only their ids and scores, and reads the top_k records to return them
+    fn filtered_semantic_search(
         &mut self,
         query_vector: &[f32],
         top_k: usize,
-        criteria: Option<SearchCriteria>,
-    ) -> Vec<(M, f32)>
+        metric: DistanceMetric,
+        min_score: Option<f32>,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<(M, f32)>>
     where
-        M: VectorEmbedding + Searchable + RepoModel<K>,
+        M: VectorEmbedding + Searchable,
     {
-        let items = self.find(criteria).await;
+        let query = QueryVector::new(query_vector);
+        let mut scores: Vec<(K, f32, u64)> = Vec::new();
+        self.for_each_candidate_vector(criteria, |id, offset, vector| {
+            scores.push((id.clone(), query.score(metric, &vector), offset))
+        })?;
+        let scores = select_top_k_by_position(scores, top_k, metric, min_score);
+        self.read_scored(scores)
+    }
 
-        // create vector with tuple
-        let candidates: Vec<(K, Vec<f32>)> = items
-            .iter()
-            .map(|entry| (entry.id().clone(), entry.vector().to_vec()))
-            .collect();
+    // read_scored reads the records of the ranked ids, keeping their scores
+    fn read_scored(&mut self, scores: Vec<(K, f32)>) -> Result<Vec<(M, f32)>> {
+        let mut results = Vec::with_capacity(scores.len());
+        for (id, score) in scores {
+            if let Some(offset) = self.offsetm.get(&id) {
+                let (_, model) = read_record::<M>(&mut self.file, *offset)?;
+                results.push((model, score));
+            }
+        }
+        Ok(results)
+    }
 
-        let results = vector_search(query_vector, &candidates, top_k);
+    // for_each_candidate_vector passes the vector and offset of every record matching the
+    // criteria to f, from the vector store when the indexes answer every condition
+    fn for_each_candidate_vector(
+        &mut self,
+        criteria: &SearchCriteria,
+        f: impl FnMut(&K, u64, StoredValues),
+    ) -> Result<()>
+    where
+        M: VectorEmbedding + Searchable,
+    {
+        let plan = self.plan(criteria);
+        let candidates = self.lookup_candidates(criteria, &plan);
+        self.for_each_vector_of(criteria, candidates.as_ref(), f)
+    }
 
-        // iterator through result and return vector of (M, f32)
-        let final_results: Vec<(M, f32)> = results
+    // for_each_vector_of is for_each_candidate_vector over the given candidates, every
+    // record when None
+    fn for_each_vector_of(
+        &mut self,
+        criteria: &SearchCriteria,
+        candidates: Option<&HashSet<K>>,
+        mut f: impl FnMut(&K, u64, StoredValues),
+    ) -> Result<()>
+    where
+        M: VectorEmbedding + Searchable,
+    {
+        let indexed = criteria
+            .conditions
             .iter()
-            .filter_map(|(id, score)| {
-                items
-                    .iter()
-                    .find(|item| item.id() == *id)
-                    .cloned()
-                    .map(|item| (item, *score))
-            })
-            .collect();
+            .all(|condition| self.indexes.iter().any(|index| index.field() == condition.field));
+        let indexes = &self.indexes;
+        let matches_indexes = |id: &K| {
+            criteria
+                .conditions
+                .iter()
+                .all(|condition| condition.matches(index_key(indexes, id, &condition.field)))
+        };
 
-        final_results
+        // the indexes answer the conditions only when matches_filter checks nothing else
+        let indexed = indexed && M::MATCHES_BY_FIELDS;
+        match self.vector_store.as_mut() {
+            Some(store) if indexed => {
+                let keep = |id: &K| candidates.is_none_or(|ids| ids.contains(id)) && matches_indexes(id);
+                store.scan_where(keep, f)?;
+            }
+            _ => {
+                let ids: Vec<&K> = match candidates {
+                    Some(ids) => ids.iter().collect(),
+                    None => self.offsetm.keys().collect(),
+                };
+                for id in ids {
+                    if indexed && !matches_indexes(id) {
+                        continue;
+                    }
+                    let Some(offset) = self.offsetm.get(id) else {
+                        continue;
+                    };
+                    let (_, model) = read_record::<M>(&mut self.file, *offset)?;
+                    if model.matches_filter(criteria) {
+                        f(id, *offset, StoredValues::F32(model.vector()));
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
-    // update appends the udpated record
-    async fn update(&mut self, model: M) -> Result<()> {
-        let offset = write_active_record(&mut self.file, RECORD_TYPE_ACTIVE, &model, false)?;
-        self.offsetm.insert(model.id(), offset);
-        debug!("Update id:{} at offset:{}", model.id(), offset);
+    // set_distance_metric sets the metric semantic_search and hybrid_search compare vectors with
+    pub fn set_distance_metric(&mut self, metric: DistanceMetric) {
+        self.metric = metric;
+    }
+
+    // set_vector_config declares the dimension and metric of the embeddings. Inserts and
+    // updates with vectors of another dimension or with non-finite values are rejected, as
+    // are query vectors of another dimension. Records written before are not checked.
+    pub fn set_vector_config(&mut self, config: VectorConfig)
+    where
+        M: VectorEmbedding,
+    {
+        self.declare_vector_config(config);
+        self.vector_extract = Some(M::vector);
+    }
+
+    // declare_vector_config applies the config without a VectorEmbedding model: queries are
+    // checked, and writes once the vector index or store is created or set_vector_config called
+    pub fn declare_vector_config(&mut self, config: VectorConfig) {
+        self.metric = config.metric;
+        self.set_vector_storage(config.storage);
+        self.vector_config = Some(config);
+    }
+
+    // set_chunk_vector_config declares the dimension of the chunk embeddings of multi-vector
+    // models, checked like the vector of set_vector_config. Chunks are compared by cosine
+    // similarity, so other metrics are rejected. A model with both kinds of embeddings has a
+    // single config for both.
+    pub fn set_chunk_vector_config(&mut self, config: VectorConfig) -> Result<()>
+    where
+        M: MultiVectorEmbedding,
+    {
+        if config.metric != DistanceMetric::Cosine {
+            return Err(anyhow::anyhow!(VectorValidationError::UnsupportedChunkMetric {
+                collection: self.name.clone(),
+                metric: config.metric,
+            }));
+        }
+        self.vector_config = Some(config);
+        self.chunk_extract = Some(M::vectors);
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
+    // set_embedder embeds the text of every record written from now on, replacing its vector,
+    // and the query texts of semantic_search_text. Records written before keep their vectors.
+    pub fn set_embedder(&mut self, embedder: Arc<dyn Embedder>)
+    where
+        M: Embeddable,
+    {
+        self.embedder = Some((embedder, M::embedding_text, M::set_vector));
+    }
 
-    use super::*;
-    use once_cell::sync::Lazy;
-    use serde::{Deserialize, Serialize};
+    pub fn embedder(&self) -> Option<&dyn Embedder> {
+        self.embedder.as_ref().map(|(embedder, _, _)| embedder.as_ref())
+    }
 
-    #[derive(Serialize, Deserialize, Clone, Debug)]
-    struct TestUser {
-        id: String,
-        name: String,
+    // embed replaces the vector of a record with the embedding of its text
+    fn embed(&self, model: &mut M) -> Result<()> {
+        if let Some((embedder, text, set_vector)) = &self.embedder {
+            let vector = embedder.embed(&text(model))?;
+            set_vector(model, vector);
+        }
+        Ok(())
     }
 
-    impl RepoModel<String> for TestUser {
-        fn id(&self) -> String {
-            self.id.clone()
+    pub fn vector_config(&self) -> Option<&VectorConfig> {
+        self.vector_config.as_ref()
+    }
+
+    // check_vector validates the vector and the chunk vectors of a record against the
+    // vector config
+    fn check_vector(&self, model: &M) -> Result<()> {
+        let Some(config) = &self.vector_config else {
+            return Ok(());
+        };
+        if let Some(extract) = self.vector_extract {
+            config.check_vector(&self.name, extract(model))?;
         }
-        fn collection(&self) -> &'static str {
-            "user"
+        if let Some(extract) = self.chunk_extract {
+            for vector in extract(model) {
+                config.check_vector(&self.name, vector)?;
+            }
         }
+        Ok(())
     }
 
-    static USER1: Lazy<TestUser> = Lazy::new(|| TestUser {
-        id: "1".to_string(),
-        name: "Test1".to_string(),
-    });
-    static USER2: Lazy<TestUser> = Lazy::new(|| TestUser {
-        id: "2".to_string(),
-        name: "Test1".to_string(),
-    });
+    // check_query validates a query vector against the vector config
+    fn check_query(&self, query_vector: &[f32]) -> Result<()> {
+        if let Some(config) = &self.vector_config {
+            config.check_query(&self.name, query_vector)?;
+        }
+        Ok(())
+    }
 
-    #[tokio::test]
-    async fn test_insert_1() -> Result<()> {
-        let pb = PathBuf::from("data/tests/users");
-        let mut repo = FsRepository::<String, TestUser>::new("users".to_string(), pb)?;
-        let user1 = &*USER1;
-        repo.insert(user1.clone())
-            .await
-            .expect("Failed to create user");
+    pub fn distance_metric(&self) -> DistanceMetric {
+        self.metric
+    }
 
-        let user2 = &*USER2;
-        repo.insert(user2.clone())
-            .await
-            .expect("Failed to create user");
+    // vector_index returns the definition of the vector index
+    pub fn vector_index(&self) -> Option<&VectorIndexDefinition> {
+        self.vector_index.as_ref().map(|index| index.definition())
+    }
 
+    // load_vector_index loads the snapshot of the vector index, or clears the index when
+    // the snapshot cannot be used, then catches up with the log
+    fn load_vector_index(&mut self) -> Result<()> {
+        let path = self.vector_index_path();
+        let log_length = self.file.seek(SeekFrom::End(0))?;
+        let Some(index) = self.vector_index.as_mut() else {
+            return Ok(());
+        };
+
+        if let Err(e) = index.load(&path, log_length) {
+            warn!("Ignoring vector index snapshot {:?}: {}", path, e);
+            index.clear();
+        }
+        self.catch_up_vector_index()?;
+        self.rebuild_vector_indexes();
         Ok(())
     }
 
-    #[tokio::test]
-    async fn test_find_all() -> Result<()> {
-        let pb = PathBuf::from("data/tests/users");
-        let mut repo = FsRepository::<String, TestUser>::new("users".to_string(), pb)?;
-        repo.initialize().await?;
-        let values = repo.find_all().await;
-        println!("{}", values.len());
-        // assert_eq!(values.len(), 2);
+    // catch_up_vector_index applies the records past the watermark of the vector index
+    // and saves a new snapshot when there were any
+    fn catch_up_vector_index(&mut self) -> Result<()> {
+        let path = self.vector_index_path();
+        let Some(index) = self.vector_index.as_mut() else {
+            return Ok(());
+        };
+
+        let (offset, applied) = replay_records::<M>(&mut self.file, index.watermark(), |record_type, model| {
+            match record_type {
+                RECORD_TYPE_DELETED => index.remove(&model.id()),
+                _ => index.insert(model.id(), &model),
+            }
+        })?;
+
+        if applied > 0 {
+            index.set_watermark(offset);
+            index.save(&path)?;
+            debug!("Vector index of {} caught up with {} records", self.name, applied);
+        }
+        Ok(())
+    }
+
+    // indexed_semantic_search searches the vector index for each query and keeps the hits
+    // matching the criteria, re-ranked by cosine similarity. A search is widened until top_k
+    // hits match, the index has no more or the hits fall below min_score.
+    fn indexed_semantic_search(
+        &mut self,
+        query_vectors: &[&[f32]],
+        top_k: usize,
+        min_score: Option<f32>,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<Vec<(M, f32)>>>
+    where
+        M: VectorEmbedding + Searchable,
+    {
+        let plan = self.plan(criteria);
+        let allowed = self.lookup_candidates(criteria, &plan);
+        let mut ranked: Vec<Option<Vec<(K, f32)>>> = vec![None; query_vectors.len()];
+        let mut k = top_k;
+        loop {
+            let pending: Vec<usize> = (0..query_vectors.len()).filter(|q| ranked[*q].is_none()).collect();
+            if pending.is_empty() {
+                break;
+            }
+            let Some(index) = self.vector_index.as_ref() else {
+                return Ok(vec![Vec::new(); query_vectors.len()]);
+            };
+            let depth = index.index().rerank_depth(k);
+            let searches: Vec<(Vec<(K, f32)>, bool)> = pending
+                .par_iter()
+                .map(|q| {
+                    let hits = index.index().search(query_vectors[*q], depth, allowed.as_ref());
+                    let exhausted = hits.len() < depth
+                        || hits.last().is_some_and(|(_, score)| min_score.is_some_and(|min| *score < min));
+                    (hits, exhausted)
+                })
+                .collect();
+
+            let hits: HashSet<K> = searches.iter().flat_map(|(hits, _)| hits.iter().map(|(id, _)| id.clone())).collect();
+            let vectors = self.hit_vectors(criteria, &hits)?;
+            let scored: Vec<Vec<(K, f32)>> = pending
+                .par_iter()
+                .zip(searches.par_iter())
+                .map(|(q, (hits, _))| {
+                    let scores = hits.iter().filter_map(|(id, _)| {
+                        let (offset, vector) = vectors.get(id)?;
+                        Some((id.clone(), cosine_similarity(query_vectors[*q], vector), *offset))
+                    });
+                    select_top_k_by_position(scores, top_k, DistanceMetric::Cosine, min_score)
+                })
+                .collect();
+            for ((q, (_, exhausted)), scores) in pending.into_iter().zip(searches).zip(scored) {
+                if scores.len() >= top_k || exhausted {
+                    ranked[q] = Some(scores);
+                }
+            }
+            k = k.saturating_mul(4);
+        }
+
+        // each record is read once, and cloned only for the results of more than one query
+        let mut items: HashMap<K, (M, usize)> = HashMap::new();
+        for (id, _) in ranked.iter().flatten().flatten() {
+            if let Some((_, uses)) = items.get_mut(id) {
+                *uses += 1;
+            } else if let Some(offset) = self.offsetm.get(id) {
+                let (_, model) = read_record::<M>(&mut self.file, *offset)?;
+                items.insert(id.clone(), (model, 1));
+            }
+        }
+        Ok(ranked
+            .into_iter()
+            .map(|scores| {
+                scores
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|(id, score)| {
+                        let (_, uses) = items.get_mut(&id)?;
+                        *uses -= 1;
+                        let model = match uses {
+                            0 => items.remove(&id)?.0,
+                            _ => items.get(&id)?.0.clone(),
+                        };
+                        Some((model, score))
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    // hit_vectors reads the vectors of the index hits matching the criteria, with the offsets
+    // of their records, like for_each_candidate_vector and without keeping the records. Binary
+    // vectors of the store are replaced by those of the records.
+    fn hit_vectors(&mut self, criteria: &SearchCriteria, hits: &HashSet<K>) -> Result<HashMap<K, (u64, Vec<f32>)>>
+    where
+        M: VectorEmbedding + Searchable,
+    {
+        let mut vectors = HashMap::with_capacity(hits.len());
+        let mut binary = Vec::new();
+        self.for_each_vector_of(criteria, Some(hits), |id, offset, vector| match vector {
+            StoredValues::Binary { .. } => binary.push((id.clone(), offset)),
+            vector => {
+                vectors.insert(id.clone(), (offset, vector.to_f32()));
+            }
+        })?;
+        for (id, offset) in binary {
+            let (_, model) = read_record::<M>(&mut self.file, offset)?;
+            vectors.insert(id, (offset, model.vector().to_vec()));
+        }
+        Ok(vectors)
+    }
+
+    // indexed_multi_vector_search searches the chunk index like indexed_semantic_search,
+    // re-ranking the chunk hits with the full precision vectors of their records before
+    // aggregating them per document
+    fn indexed_multi_vector_search(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        options: &MultiVectorOptions,
+        criteria: &SearchCriteria,
+    ) -> Result<Vec<DocumentMatch<M>>>
+    where
+        M: MultiVectorEmbedding + Searchable,
+    {
+        let plan = self.plan(criteria);
+        let allowed = self.lookup_candidates(criteria, &plan);
+        let Some(index) = self.chunk_index.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let allowed = allowed.map(|ids| index.chunk_ids(&ids));
+        let chunk_k = options.chunks(top_k);
+        let mut k = chunk_k;
+        loop {
+            let Some(index) = self.chunk_index.as_ref() else {
+                return Ok(Vec::new());
+            };
+            let depth = index.index().rerank_depth(k);
+            let hits = index.index().search(query_vector, depth, allowed.as_ref());
+            let exhausted = hits.len() < depth
+                || hits.last().is_some_and(|(_, score)| options.min_score.is_some_and(|min| *score < min));
+
+            // records not matching the criteria are kept as None
+            let mut models: HashMap<K, Option<M>> = HashMap::new();
+            let mut scores = Vec::new();
+            for (chunk_id, _) in hits {
+                if !models.contains_key(&chunk_id.id) {
+                    let model = match self.offsetm.get(&chunk_id.id) {
+                        Some(offset) => Some(read_record::<M>(&mut self.file, *offset)?.1),
+                        None => None,
+                    };
+                    let model = model.filter(|model| model.matches_filter(criteria));
+                    models.insert(chunk_id.id.clone(), model);
+                }
+                if let Some(Some(model)) = models.get(&chunk_id.id)
+                    && let Some(vector) = model.vectors().get(chunk_id.chunk as usize)
+                    && let Some(offset) = self.offsetm.get(&chunk_id.id)
+                {
+                    let score = cosine_similarity(query_vector, vector);
+                    let position = (*offset, chunk_id.chunk);
+                    scores.push((chunk_id, score, position));
+                }
+            }
+            // equal scores keep the write order of the records and the order of their chunks
+            let ranked = select_top_k_by_position(scores, k, DistanceMetric::Cosine, options.min_score);
+            let matched = matched_chunks(&ranked, top_k, chunk_k);
+            let documents = aggregate_chunks(&ranked[..matched], top_k, options.aggregation);
+            if documents.len() >= top_k || (exhausted && ranked.len() < k) {
+                return Ok(documents
+                    .into_iter()
+                    .filter_map(|document| {
+                        let model = models.get_mut(&document.item)?.take()?;
+                        Some(document.map(|_| model))
+                    })
+                    .collect());
+            }
+            k = k.saturating_mul(4);
+        }
+    }
+
+    // vector_index_memory returns the approximate size in bytes of the vector index
+    pub fn vector_index_memory(&self) -> Option<usize> {
+        self.vector_index.as_ref().map(|index| index.index().memory_usage())
+    }
+
+    // indexes returns the definitions of the registered secondary indexes
+    pub fn indexes(&self) -> Vec<&IndexDefinition> {
+        self.indexes.iter().map(|index| index.definition()).collect()
+    }
+
+    // for_each_record reads every live record and passes it to f without collecting them.
+    // Records that cannot be read are logged and skipped.
+    fn for_each_record(&mut self, mut f: impl FnMut(M)) {
+        for offset in self.offsetm.values() {
+            if let Some(model) = read_or_warn::<M>(&mut self.file, &self.name, *offset) {
+                f(model);
+            }
+        }
+    }
+
+    // plan chooses how the records matching the criteria are read
+    fn plan(&self, criteria: &SearchCriteria) -> QueryPlan {
+        plan_query(&self.indexes, criteria, self.offsetm.len())
+    }
+
+    // lookup_candidates returns the ids of the plan's index lookup, None for a full scan
+    fn lookup_candidates(&self, criteria: &SearchCriteria, plan: &QueryPlan) -> Option<HashSet<K>> {
+        match &plan.access {
+            AccessPath::FullScan => None,
+            AccessPath::IndexLookup { condition, .. } => {
+                let condition = &criteria.conditions[*condition];
+                self.indexes.iter().find_map(|index| index.lookup(condition))
+            }
+        }
+    }
+
+    // ordered_runs groups the ids in the order of the index on the first sort field.
+    // Ids in a run share the same value; ids without a value form the null run.
+    fn ordered_runs(&self, sort_field: &SortField, candidates: Option<HashSet<K>>) -> Vec<Vec<K>> {
+        let Some(index) = self.indexes.iter().find(|index| index.field() == sort_field.field) else {
+            return vec![candidates.map_or_else(|| self.offsetm.keys().cloned().collect(), |ids| ids.into_iter().collect())];
+        };
+
+        let (mut runs, nulls): (Vec<Vec<K>>, Vec<K>) = match candidates {
+            Some(ids) => {
+                let mut grouped: BTreeMap<&SortValue, Vec<K>> = BTreeMap::new();
+                let mut nulls = Vec::new();
+                for id in ids {
+                    match index.key(&id) {
+                        Some(value) => grouped.entry(value).or_default().push(id),
+                        None => nulls.push(id),
+                    }
+                }
+                (grouped.into_values().collect(), nulls)
+            }
+            None => (
+                index.runs().map(|(_, ids)| ids.iter().cloned().collect()).collect(),
+                self.offsetm.keys().filter(|id| index.key(id).is_none()).cloned().collect(),
+            ),
+        };
+
+        if !sort_field.ascending {
+            runs.reverse();
+        }
+        if !nulls.is_empty() {
+            match sort_field.nulls {
+                NullsOrder::First => runs.insert(0, nulls),
+                NullsOrder::Last => runs.push(nulls),
+            }
+        }
+        runs
+    }
+
+    // execute reads the records of the plan that match the criteria, sorts and limits
+    // them, and returns them with the number of records read
+    fn execute(&mut self, criteria: &SearchCriteria, plan: &QueryPlan) -> (Vec<M>, usize)
+    where
+        M: Searchable,
+    {
+        let candidates = self.lookup_candidates(criteria, plan);
+        let sort_fields = criteria.sort_fields.as_deref().unwrap_or(&[]);
+        let runs = match (sort_fields.first(), &plan.order_index) {
+            (Some(sort_field), Some(_)) => self.ordered_runs(sort_field, candidates),
+            _ => vec![candidates.map_or_else(|| self.offsetm.keys().cloned().collect(), |ids| ids.into_iter().collect())],
+        };
+        let limit = criteria.limit.filter(|_| plan.limit_pushed_down);
+
+        let mut items = Vec::new();
+        let mut scanned = 0;
+        'runs: for run in runs {
+            for id in run {
+                let Some(offset) = self.offsetm.get(&id) else {
+                    continue;
+                };
+                let Some(model) = read_or_warn::<M>(&mut self.file, &self.name, *offset) else {
+                    continue;
+                };
+                scanned += 1;
+                if model.matches_filter(criteria) {
+                    items.push(model);
+                }
+                // Unsorted results can stop at any record
+                if sort_fields.is_empty() && limit.is_some_and(|limit| items.len() >= limit) {
+                    break 'runs;
+                }
+            }
+            // Sorted results stop at the end of a run, so ties of the last value are kept
+            if limit.is_some_and(|limit| items.len() >= limit) {
+                break;
+            }
+        }
+
+        // Apply sort
+        if plan.needs_sort {
+            items = apply_sort(items, sort_fields);
+        }
+
+        // Apply limit
+        if let Some(limit) = criteria.limit {
+            items.truncate(limit);
+        }
+        (items, scanned)
+    }
+
+    // for_each_match passes every record matching the conditions of the criteria to f,
+    // reading only the candidates of an index lookup when the plan has one
+    fn for_each_match(&mut self, criteria: &SearchCriteria, mut f: impl FnMut(M))
+    where
+        M: Searchable,
+    {
+        let plan = self.plan(criteria);
+        match self.lookup_candidates(criteria, &plan) {
+            Some(ids) => {
+                debug!("Find using index, {} candidates", ids.len());
+                for id in ids {
+                    let Some(offset) = self.offsetm.get(&id) else {
+                        continue;
+                    };
+                    if let Some(model) = read_or_warn::<M>(&mut self.file, &self.name, *offset)
+                        && model.matches_filter(criteria)
+                    {
+                        f(model);
+                    }
+                }
+            }
+            None => self.for_each_record(|model| {
+                if model.matches_filter(criteria) {
+                    f(model);
+                }
+            }),
+        }
+    }
+
+    // check_unique fails when another live record holds a value of a unique index
+    fn check_unique(&self, model: &M) -> Result<()> {
+        let id = model.id();
+        for index in &self.indexes {
+            if let Some(value) = index.conflict(&id, model) {
+                return Err(self.unique_violation(index.field(), &value));
+            }
+        }
+        Ok(())
+    }
+
+    fn unique_violation(&self, field: &str, value: &SortValue) -> anyhow::Error {
+        anyhow::anyhow!(FsRepositoryError::UniqueConstraintViolation {
+            collection: self.name.clone(),
+            field: field.to_string(),
+            value: format!("{:?}", value),
+        })
+    }
+
+    fn index_insert(&mut self, model: &M) {
+        for index in self.indexes.iter_mut() {
+            index.insert(model.id(), model);
+        }
+        if let Some(index) = self.text_index.as_mut() {
+            index.insert(model.id(), model);
+        }
+        if let Some(index) = self.vector_index.as_mut() {
+            index.insert(model.id(), model);
+        }
+        if let Some(index) = self.chunk_index.as_mut() {
+            index.insert(model.id(), model);
+        }
+    }
+
+    // rebuild_vector_indexes rebuilds the vector and chunk indexes that need it from the
+    // full precision vectors of the records
+    fn rebuild_vector_indexes(&mut self) {
+        if let Some(mut index) = self.vector_index.take_if(|index| index.index().needs_rebuild()) {
+            let mut vectors = Vec::with_capacity(self.offsetm.len());
+            self.for_each_record(|model| vectors.push((model.id(), index.vector(&model).to_vec())));
+            index.rebuild(vectors);
+            debug!("Vector index of {} rebuilt with {} vectors", self.name, index.index().len());
+            self.vector_index = Some(index);
+        }
+        if let Some(mut index) = self.chunk_index.take_if(|index| index.index().needs_rebuild()) {
+            let mut documents = Vec::with_capacity(self.offsetm.len());
+            self.for_each_record(|model| documents.push((model.id(), index.vectors(&model).to_vec())));
+            index.rebuild(documents);
+            debug!("Chunk index of {} rebuilt with {} chunks", self.name, index.index().len());
+            self.chunk_index = Some(index);
+        }
+    }
+
+    fn index_remove(&mut self, id: &K) {
+        for index in self.indexes.iter_mut() {
+            index.remove(id);
+        }
+        if let Some(index) = self.text_index.as_mut() {
+            index.remove(id);
+        }
+        if let Some(index) = self.vector_index.as_mut() {
+            index.remove(id);
+        }
+        if let Some(index) = self.chunk_index.as_mut() {
+            index.remove(id);
+        }
+    }
+}
+
+// index_key returns the value a secondary index on field holds for id
+fn index_key<'a, K: RepoKey, M>(indexes: &'a [SecondaryIndex<K, M>], id: &K, field: &str) -> Option<&'a SortValue> {
+    indexes.iter().find(|index| index.field() == field).and_then(|index| index.key(id))
+}
+
+// read_or_warn reads the live record at offset for the callers that cannot return an error,
+// logging and skipping it when it cannot be read
+fn read_or_warn<M: DeserializeOwned>(file: &mut File, collection: &str, offset: u64) -> Option<M> {
+    match read_record::<M>(file, offset) {
+        Ok((_, model)) => Some(model),
+        Err(e) => {
+            warn!("Skipping record of {} at offset {}: {}", collection, offset, e);
+            None
+        }
+    }
+}
+
+// replay_records passes the active and deleted records from offset to the end of the log
+// to f, and returns the offset after the last record with the number of records read
+fn replay_records<M: DeserializeOwned>(
+    file: &mut File,
+    mut offset: u64,
+    mut f: impl FnMut(u8, M),
+) -> Result<(u64, usize)> {
+    let mut count = 0;
+    while let Ok((header, model)) = read_record::<M>(file, offset) {
+        match header.record_type {
+            RECORD_TYPE_ACTIVE | RECORD_TYPE_DELETED => f(header.record_type, model),
+            _ => break,
+        }
+        count += 1;
+        offset = file.stream_position()?;
+    }
+    Ok((offset, count))
+}
+
+#[async_trait]
+impl<K, M> Initializable for FsRepository<K, M>
+where
+    K: RepoKey,
+    M: RepoModel<K>,
+{
+    async fn initialize(&mut self) -> Result<()> {
+        let mut offset = self.file.seek(SeekFrom::Start(0))?;
+        info!("Initializing repo: {}...", self.name);
+        self.offsetm.clear();
+        for index in self.indexes.iter_mut() {
+            index.clear();
+        }
+        if let Some(index) = self.chunk_index.as_mut() {
+            index.clear();
+        }
+        // the text and vector indexes are loaded from their snapshots and catch up
+        // with the log instead of being rebuilt by the scan below
+        let text_index = self.text_index.take();
+        let vector_index = self.vector_index.take();
+        loop {
+            let (header, model) = match read_record::<M>(&mut self.file, offset) {
+                Ok((header, model)) => (header, model),
+                Err(_e) => {
+                    // warn!("Read error: {}", e);
+                    break;
+                }
+            };
+
+            debug!("Record Type: {:?}", header.record_type);
+            match header.record_type {
+                RECORD_TYPE_ACTIVE => {
+                    if let Err(e) = self.check_unique(&model) {
+                        warn!("Initializing {}: {}", self.name, e);
+                    }
+                    self.offsetm.insert(model.id(), offset);
+                    self.index_insert(&model);
+                }
+                RECORD_TYPE_DELETED => {
+                    self.offsetm.remove(&model.id());
+                    self.index_remove(&model.id());
+                }
+                _ => {
+                    break;
+                }
+            }
+            offset = self.file.stream_position()?;
+        }
+        self.text_index = text_index;
+        self.vector_index = vector_index;
+        self.catch_up_text_index()?;
+        self.catch_up_vector_index()?;
+        self.rebuild_vector_indexes();
+        if let Some(store) = self.vector_store.as_mut() {
+            store.sync(&mut self.file, &self.offsetm)?;
+        }
+        info!("Initializing done.");
+        Ok(())
+    }
+
+    // shutdown saves the snapshots of the text and vector indexes
+    async fn shutdown(&mut self) -> Result<()> {
+        let log_length = self.file.seek(SeekFrom::End(0))?;
+        let path = self.text_index_path();
+        if let Some(index) = self.text_index.as_mut() {
+            index.set_watermark(log_length);
+            index.save(&path)?;
+        }
+        let path = self.vector_index_path();
+        if let Some(index) = self.vector_index.as_mut() {
+            index.set_watermark(log_length);
+            index.save(&path)?;
+        }
+        Ok(())
+    }
+    fn as_any(&mut self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl<K, M> Repository<K, M> for FsRepository<K, M>
+where
+    K: RepoKey,
+    M: RepoModel<K>,
+{
+    // insert appends the record, after checking the unique indexes
+    async fn insert(&mut self, mut model: M) -> Result<()> {
+        self.check_unique(&model)?;
+        self.embed(&mut model)?;
+        self.check_vector(&model)?;
+        let offset = self.write_record(&model)?;
+        self.offsetm.insert(model.id(), offset);
+        self.index_insert(&model);
+        self.rebuild_vector_indexes();
+        debug!("Insert id:{} at offset:{}", model.id(), offset);
+        Ok(())
+    }
+
+    // delete appends the delete record
+    async fn delete(&mut self, model: M) -> Result<()> {
+        let _ = write_active_record(&mut self.file, RECORD_TYPE_DELETED, &model, false)?;
+        self.offsetm.remove(&model.id());
+        self.index_remove(&model.id());
+        self.rebuild_vector_indexes();
+        if let Some(store) = self.vector_store.as_mut() {
+            store.remove(&model.id())?;
+        }
+        Ok(())
+    }
+
+    // find_by_id finds the json file for the id, marshalls that into the object
+    async fn find_by_id(&mut self, id: K) -> Option<M> {
+        let offset = self.offsetm.get(&id)?;
+        debug!("Find_by_id Id:{} offset:{}", id, offset);
+        read_or_warn::<M>(&mut self.file, &self.name, *offset)
+    }
+
+    // find_all returns all values from offset map
+    async fn find_all(&mut self) -> Vec<M> {
+        let mut values = Vec::<M>::new();
+        debug!("Find_all Offset map length: {}", self.offsetm.len());
+        for offset in self.offsetm.values() {
+            if let Some(model) = read_or_warn::<M>(&mut self.file, &self.name, *offset) {
+                values.push(model);
+            };
+        }
+        values
+    }
+
+    // find_finds filtered values, reading records as chosen by the query planner
+    async fn find(&mut self, criteria: Option<SearchCriteria>) -> Vec<M> 
+        where M: Searchable{
+        let Some(f) = criteria else {
+            return self.find_all().await;
+        };
+
+        let plan = self.plan(&f);
+        debug!("Find plan: {}", plan);
+        let (items, _) = self.execute(&f, &plan);
+        items
+    }
+
+    // explain runs the query and reports the plan with the records it read
+    async fn explain(&mut self, criteria: SearchCriteria) -> QueryExplain
+        where M: Searchable {
+        let plan = self.plan(&criteria);
+        let (items, records_scanned) = self.execute(&criteria, &plan);
+        QueryExplain {
+            plan,
+            records_scanned,
+            records_returned: items.len(),
+        }
+    }
+
+    // count counts matching records without collecting them; sort and limit are ignored
+    async fn count(&mut self, criteria: Option<SearchCriteria>) -> usize
+        where M: Searchable {
+        let Some(criteria) = criteria.filter(|c| !c.conditions.is_empty()) else {
+            return self.offsetm.len();
+        };
+
+        let mut count = 0;
+        self.for_each_match(&criteria, |_| count += 1);
+        count
+    }
+
+    // aggregate groups matching records; sort and limit are ignored
+    async fn aggregate(
+        &mut self,
+        criteria: Option<SearchCriteria>,
+        query: AggregateQuery,
+    ) -> Vec<AggregateRow>
+    where
+        M: Searchable,
+    {
+        let mut aggregator = Aggregator::new(&query);
+        match criteria {
+            Some(criteria) => self.for_each_match(&criteria, |model| aggregator.add(&model)),
+            None => self.for_each_record(|model| aggregator.add(&model)),
+        }
+        aggregator.finish()
+    }
+
+    // text_search ranks the records matching the criteria conditions by the BM25 score
+    // of the query on the text index; sort and limit are ignored
+    async fn text_search(
+        &mut self,
+        query: &str,
+        top_k: usize,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: Searchable,
+    {
+        let criteria = criteria.unwrap_or_default();
+        let plan = self.plan(&criteria);
+        let candidates = self.lookup_candidates(&criteria, &plan);
+        let Some(index) = self.text_index.as_ref() else {
+            return Err(anyhow::anyhow!(FsRepositoryError::TextIndexMissing {
+                collection: self.name.clone(),
+            }));
+        };
+        let scored = index.search(query, candidates.as_ref(), |id| self.offsetm.get(id).copied());
+
+        let mut results = Vec::new();
+        for (id, score) in scored {
+            if results.len() >= top_k {
+                break;
+            }
+            let Some(offset) = self.offsetm.get(&id) else {
+                continue;
+            };
+            let (_, model) = read_record::<M>(&mut self.file, *offset)?;
+            if model.matches_filter(&criteria) {
+                results.push((model, score));
+            }
+        }
+        Ok(results)
+    }
+
+    // hybrid_search ranks the records matching the criteria conditions by fusing their
+    // vector score for the collection metric with their BM25 score for the query text.
+    // Records without query terms only have a vector rank.
+    async fn hybrid_search(
+        &mut self,
+        query_vector: &[f32],
+        query_text: &str,
+        top_k: usize,
+        fusion: Fusion,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable,
+    {
+        if self.text_index.is_none() {
+            return Err(anyhow::anyhow!(FsRepositoryError::TextIndexMissing {
+                collection: self.name.clone(),
+            }));
+        }
+        self.check_query(query_vector)?;
+
+        // only the ids and scores of the candidates are kept, the fused top_k are read at the end
+        let criteria = criteria.unwrap_or_default();
+        let metric = self.metric;
+        let query = QueryVector::new(query_vector);
+        let mut scores: Vec<(K, f32, u64)> = Vec::new();
+        self.for_each_candidate_vector(&criteria, |id, offset, vector| {
+            scores.push((id.clone(), query.score(metric, &vector), offset))
+        })?;
+        let count = scores.len();
+        let vector_results: Vec<(K, f32)> = select_top_k_by_position(scores, count, metric, None)
+            .into_iter()
+            .map(|(id, score)| (id, metric.similarity(score)))
+            .collect();
+        let allowed: HashSet<K> = vector_results.iter().map(|(id, _)| id.clone()).collect();
+        let text_results = self
+            .text_index
+            .as_ref()
+            .map(|index| index.search(query_text, Some(&allowed), |id| self.offsetm.get(id).copied()))
+            .unwrap_or_default();
+
+        let mut fused = fuse(&vector_results, &text_results, fusion);
+        fused.truncate(top_k);
+        self.read_scored(fused)
+    }
+
+    // semantic_search ranks records by the metric of the collection, cosine similarity
+    // unless set otherwise
+    async fn semantic_search(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>,
+    {
+        let metric = self.metric;
+        self.semantic_search_with_metric(query_vector, top_k, metric, criteria).await
+    }
+
+    // semantic_search_with_metric ranks records by the metric, best first: highest
+    // similarities or lowest distances
+    async fn semantic_search_with_metric(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        metric: DistanceMetric,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>,
+    {
+        let options = VectorSearchOptions::new().with_metric(metric);
+        self.semantic_search_with_options(query_vector, top_k, options, criteria).await
+    }
+
+    // semantic_search_with_options ranks records by the metric of the options, or of the
+    // collection, dropping NaN scores and scores below min_score; ties keep the write order,
+    // and mmr re-ranks the best results. The vector index answers cosine queries, ignoring
+    // sort and limit; otherwise see filtered_semantic_search, or find when the criteria sort
+    // or limit the records.
+    async fn semantic_search_with_options(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        options: VectorSearchOptions,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>,
+    {
+        self.check_query(query_vector)?;
+        let metric = options.metric.unwrap_or(self.metric);
+        let fetch_k = options.mmr.map_or(top_k, |mmr| mmr.candidates(top_k));
+        if self.vector_index.is_some() && metric == DistanceMetric::Cosine {
+            let criteria = criteria.unwrap_or_default();
+            let results = self.indexed_semantic_search(&[query_vector], fetch_k, options.min_score, &criteria)?;
+            let results = results.into_iter().next().unwrap_or_default();
+            return Ok(diversify(query_vector, results, top_k, options.mmr));
+        }
+        let criteria = criteria.unwrap_or_default();
+        if criteria.sort_fields.is_none() && criteria.limit.is_none() {
+            let results = self.filtered_semantic_search(query_vector, fetch_k, metric, options.min_score, &criteria)?;
+            return Ok(diversify(query_vector, results, top_k, options.mmr));
+        }
+
+        // sort and limit pick the candidates among the matching records
+        let items = self.find(Some(criteria)).await;
+        let offsetm = &self.offsetm;
+        let scores = items.iter().map(|item| {
+            let offset = offsetm.get(&item.id()).copied();
+            (item.id(), metric.score(query_vector, item.vector()), offset)
+        });
+        let scores = select_top_k_by_position(scores, fetch_k, metric, options.min_score);
+        let mut items: HashMap<K, M> = items.into_iter().map(|item| (item.id(), item)).collect();
+        let results = scores
+            .into_iter()
+            .filter_map(|(id, score)| items.remove(&id).map(|item| (item, score)))
+            .collect();
+        Ok(diversify(query_vector, results, top_k, options.mmr))
+    }
+
+    // semantic_search_text embeds the query text with the embedder of the collection and
+    // runs semantic_search with its embedding
+    async fn semantic_search_text(
+        &mut self,
+        query_text: &str,
+        top_k: usize,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<(M, f32)>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>,
+    {
+        let Some((embedder, _, _)) = &self.embedder else {
+            return Err(anyhow::anyhow!(FsRepositoryError::EmbedderMissing {
+                collection: self.name.clone(),
+            }));
+        };
+        let query_vector = embedder.embed(query_text)?;
+        self.semantic_search(&query_vector, top_k, criteria).await
+    }
+
+    // semantic_search_batch runs semantic_search_with_options for each query vector, reading
+    // the candidate vectors and the result records once for all the queries
+    async fn semantic_search_batch(
+        &mut self,
+        query_vectors: &[Vec<f32>],
+        top_k: usize,
+        options: VectorSearchOptions,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<Vec<(M, f32)>>>
+    where
+        M: VectorEmbedding + Searchable + RepoModel<K>,
+    {
+        for query_vector in query_vectors {
+            self.check_query(query_vector)?;
+        }
+        let metric = options.metric.unwrap_or(self.metric);
+        if self.vector_index.is_some() && metric == DistanceMetric::Cosine {
+            let fetch_k = options.mmr.map_or(top_k, |mmr| mmr.candidates(top_k));
+            let criteria = criteria.unwrap_or_default();
+            let queries: Vec<&[f32]> = query_vectors.iter().map(Vec::as_slice).collect();
+            let results = self.indexed_semantic_search(&queries, fetch_k, options.min_score, &criteria)?;
+            return Ok(queries
+                .into_iter()
+                .zip(results)
+                .map(|(query_vector, results)| diversify(query_vector, results, top_k, options.mmr))
+                .collect());
+        }
+
+        let criteria = criteria.unwrap_or_default();
+        let mut items: HashMap<K, M> = HashMap::new();
+        let mut candidates: Vec<(K, OwnedValues)> = Vec::new();
+        if criteria.sort_fields.is_none() && criteria.limit.is_none() {
+            // the vectors keep the format of the store, so they are scored as by semantic_search
+            let mut positioned = Vec::new();
+            self.for_each_candidate_vector(&criteria, |id, offset, vector| {
+                positioned.push((offset, id.clone(), vector.to_owned_values()))
+            })?;
+            // in the order of the log, so equal scores are ordered as by semantic_search
+            positioned.sort_unstable_by_key(|(offset, _, _)| *offset);
+            candidates = positioned.into_iter().map(|(_, id, vector)| (id, vector)).collect();
+        } else {
+            // sort and limit pick the candidates among the matching records
+            let mut found = self.find(Some(criteria)).await;
+            found.sort_by_cached_key(|item| self.offsetm.get(&item.id()).copied());
+            for item in found {
+                candidates.push((item.id(), OwnedValues::F32(item.vector().to_vec())));
+                items.insert(item.id(), item);
+            }
+        }
+        let ranked = vector_search_batch(query_vectors, &candidates, top_k, &options.with_metric(metric));
+        drop(candidates);
+
+        for (id, _) in ranked.iter().flatten() {
+            if items.contains_key(id) {
+                continue;
+            }
+            if let Some(offset) = self.offsetm.get(id) {
+                let (_, model) = read_record::<M>(&mut self.file, *offset)?;
+                items.insert(id.clone(), model);
+            }
+        }
+        Ok(ranked
+            .into_iter()
+            .map(|results| {
+                results
+                    .into_iter()
+                    .filter_map(|(id, score)| items.get(&id).map(|item| (item.clone(), score)))
+                    .collect()
+            })
+            .collect())
+    }
+
+    // multi_vector_search ranks records by the cosine similarity of their chunks to the
+    // query, aggregated per record, see MultiVectorOptions. The chunk index answers it when
+    // there is one, sort and limit of the criteria being ignored; otherwise every chunk of
+    // the records found by the criteria is compared.
+    async fn multi_vector_search(
+        &mut self,
+        query_vector: &[f32],
+        top_k: usize,
+        options: MultiVectorOptions,
+        criteria: Option<SearchCriteria>,
+    ) -> Result<Vec<DocumentMatch<M>>>
+    where
+        M: MultiVectorEmbedding + Searchable + RepoModel<K>,
+    {
+        self.check_query(query_vector)?;
+        if self.chunk_index.is_some() {
+            let criteria = criteria.unwrap_or_default();
+            return self.indexed_multi_vector_search(query_vector, top_k, &options, &criteria);
+        }
+
+        let items = self.find(criteria).await;
+        let candidates: Vec<(K, Vec<Vec<f32>>)> = items.iter().map(|item| (item.id(), item.vectors().to_vec())).collect();
+        let documents = multi_vector_search(query_vector, &candidates, top_k, &options);
+
+        let mut items: HashMap<K, M> = items.into_iter().map(|item| (item.id(), item)).collect();
+        Ok(documents
+            .into_iter()
+            .filter_map(|document| {
+                let item = items.remove(&document.item)?;
+                Some(document.map(|_| item))
+            })
+            .collect())
+    }
+
+    // update appends the udpated record, after checking the unique indexes
+    async fn update(&mut self, mut model: M) -> Result<()> {
+        self.check_unique(&model)?;
+        self.embed(&mut model)?;
+        self.check_vector(&model)?;
+        let offset = self.write_record(&model)?;
+        self.offsetm.insert(model.id(), offset);
+        self.index_insert(&model);
+        self.rebuild_vector_indexes();
+        debug!("Update id:{} at offset:{}", model.id(), offset);
+        Ok(())
+    }
+}
+
+// diversify picks top_k of the results, ordered best first, by maximal marginal relevance
+// when mmr is set
+fn diversify<M: VectorEmbedding>(query_vector: &[f32], results: Vec<(M, f32)>, top_k: usize, mmr: Option<Mmr>) -> Vec<(M, f32)> {
+    let Some(mmr) = mmr else {
+        return results;
+    };
+    let vectors: Vec<&[f32]> = results.iter().map(|(model, _)| model.vector()).collect();
+    let order = mmr_select(query_vector, &vectors, top_k, mmr.lambda);
+    let mut results: Vec<Option<(M, f32)>> = results.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| results[i].take()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::fs::aggregate::Aggregate;
+    use crate::fs::errors::VectorValidationError;
+    use crate::fs::search::{SearchOp, SearchValue};
+    use crate::vector::hnsw::HnswParams;
+    use crate::vector::ivf::IvfParams;
+    use crate::vector::embed::{CachedEmbedder, HashingEmbedder};
+    use crate::vector::multi::ChunkAggregation;
+    use crate::vector::similarity::sign_distance;
+    use crate::vector::quantization::Quantization;
+    use once_cell::sync::Lazy;
+    use rust_decimal::Decimal;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct TestUser {
+        id: String,
+        name: String,
+    }
+
+    impl RepoModel<String> for TestUser {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+        fn collection(&self) -> &'static str {
+            "user"
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct TestAccount {
+        id: String,
+        user_id: String,
+        balance: Decimal,
+    }
+
+    impl RepoModel<String> for TestAccount {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+        fn collection(&self) -> &'static str {
+            "account"
+        }
+    }
+
+    impl Searchable for TestAccount {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "id" => Some(SortValue::String(self.id.clone())),
+                "user_id" => Some(SortValue::String(self.user_id.clone())),
+                "balance" => Some(SortValue::Decimal(self.balance)),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct TestNote {
+        id: String,
+        user_id: String,
+        text: String,
+        vector: Vec<f32>,
+    }
+
+    impl VectorEmbedding for TestNote {
+        fn vector(&self) -> &[f32] {
+            &self.vector
+        }
+    }
+
+    impl Embeddable for TestNote {
+        fn embedding_text(&self) -> String {
+            self.text.clone()
+        }
+        fn set_vector(&mut self, vector: Vec<f32>) {
+            self.vector = vector;
+        }
+    }
+
+    impl RepoModel<String> for TestNote {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+        fn collection(&self) -> &'static str {
+            "note"
+        }
+    }
+
+    impl Searchable for TestNote {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "user_id" => Some(SortValue::String(self.user_id.clone())),
+                "text" => Some(SortValue::String(self.text.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    fn note(id: &str, user_id: &str, text: &str) -> TestNote {
+        TestNote {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            text: text.to_string(),
+            vector: Vec::new(),
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct TestDocument {
+        id: String,
+        user_id: String,
+        chunks: Vec<Vec<f32>>,
+    }
+
+    impl MultiVectorEmbedding for TestDocument {
+        fn vectors(&self) -> &[Vec<f32>] {
+            &self.chunks
+        }
+    }
+
+    impl RepoModel<String> for TestDocument {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+        fn collection(&self) -> &'static str {
+            "document"
+        }
+    }
+
+    impl Searchable for TestDocument {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "user_id" => Some(SortValue::String(self.user_id.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    fn document(id: &str, user_id: &str, chunks: &[[f32; 2]]) -> TestDocument {
+        TestDocument {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+        }
+    }
+
+    // test_repository creates an empty repository in its own directory
+    fn test_repository<M: RepoModel<String>>(name: &str) -> Result<FsRepository<String, M>> {
+        let pb = PathBuf::from("data/tests").join(name);
+        let _ = fs::remove_dir_all(&pb);
+        FsRepository::<String, M>::new(name.to_string(), pb)
+    }
+
+    async fn insert_accounts(repo: &mut FsRepository<String, TestAccount>) -> Result<()> {
+        for (id, user_id, balance) in [("1", "a", 1000), ("2", "a", 250), ("3", "b", 75), ("4", "b", -25)] {
+            repo.insert(TestAccount {
+                id: id.to_string(),
+                user_id: user_id.to_string(),
+                balance: Decimal::new(balance, 2),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    static USER1: Lazy<TestUser> = Lazy::new(|| TestUser {
+        id: "1".to_string(),
+        name: "Test1".to_string(),
+    });
+    static USER2: Lazy<TestUser> = Lazy::new(|| TestUser {
+        id: "2".to_string(),
+        name: "Test1".to_string(),
+    });
+
+    #[tokio::test]
+    async fn test_insert_1() -> Result<()> {
+        let pb = PathBuf::from("data/tests/users");
+        let mut repo = FsRepository::<String, TestUser>::new("users".to_string(), pb)?;
+        let user1 = &*USER1;
+        repo.insert(user1.clone())
+            .await
+            .expect("Failed to create user");
+
+        let user2 = &*USER2;
+        repo.insert(user2.clone())
+            .await
+            .expect("Failed to create user");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_all() -> Result<()> {
+        let pb = PathBuf::from("data/tests/users");
+        let mut repo = FsRepository::<String, TestUser>::new("users".to_string(), pb)?;
+        repo.initialize().await?;
+        let values = repo.find_all().await;
+        println!("{}", values.len());
+        // assert_eq!(values.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_count_and_aggregate() -> Result<()> {
+        let mut repo = test_repository::<TestAccount>("accounts_aggregate")?;
+        insert_accounts(&mut repo).await?;
+
+        assert_eq!(repo.count(None).await, 4);
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("balance", SearchOp::Gt, SearchValue::Int(0));
+        assert_eq!(repo.count(Some(criteria.clone())).await, 3);
+
+        let mut query = AggregateQuery::new();
+        query.add_group_by("user_id");
+        query.add_aggregate(Aggregate::Count);
+        query.add_aggregate(Aggregate::Sum("balance".to_string()));
+        let rows = repo.aggregate(Some(criteria), query).await;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].group, vec![SortValue::String("a".to_string())]);
+        assert_eq!(rows[0].values, vec![SortValue::Int(2), SortValue::Decimal(Decimal::new(1250, 2))]);
+        assert_eq!(rows[1].values, vec![SortValue::Int(1), SortValue::Decimal(Decimal::new(75, 2))]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_with_index() -> Result<()> {
+        let mut repo = test_repository::<TestAccount>("accounts_index")?;
+        repo.create_index(IndexDefinition::new("user_id"))?;
+        insert_accounts(&mut repo).await?;
+
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("a".into()));
+        criteria.add_sort("id", true);
+        assert_eq!(repo.explain(criteria.clone()).await.records_scanned, 2);
+        let ids: Vec<String> = repo.find(Some(criteria.clone())).await.into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+
+        // update and delete maintain the index
+        let mut moved = repo.find_by_id("3".to_string()).await.unwrap();
+        moved.user_id = "a".to_string();
+        repo.update(moved).await?;
+        let deleted = repo.find_by_id("1".to_string()).await.unwrap();
+        repo.delete(deleted).await?;
+        let ids: Vec<String> = repo.find(Some(criteria.clone())).await.into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["2", "3"]);
+
+        // indexes are rebuilt by initialize, and combine with non-indexed conditions
+        repo.initialize().await?;
+        criteria.add_condition("balance", SearchOp::Lt, SearchValue::Int(1));
+        assert_eq!(repo.explain(criteria.clone()).await.records_scanned, 2);
+        let ids: Vec<String> = repo.find(Some(criteria.clone())).await.into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["3"]);
+        assert_eq!(repo.count(Some(criteria)).await, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unique_index() -> Result<()> {
+        let mut repo = test_repository::<TestAccount>("accounts_unique")?;
+        insert_accounts(&mut repo).await?;
+
+        // user_id "a" is held by two accounts
+        assert!(repo.create_index(IndexDefinition::unique("user_id")).is_err());
+        assert!(repo.indexes().is_empty());
+        repo.create_index(IndexDefinition::unique("balance"))?;
+
+        let duplicate = TestAccount {
+            id: "5".to_string(),
+            user_id: "c".to_string(),
+            balance: Decimal::new(75, 2),
+        };
+        let error = repo.insert(duplicate.clone()).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FsRepositoryError>(),
+            Some(FsRepositoryError::UniqueConstraintViolation { field, .. }) if field == "balance"
+        ));
+        assert!(repo.find_by_id("5".to_string()).await.is_none());
+
+        // updating a record keeps its own value, but cannot take another record's value
+        let mut account = repo.find_by_id("3".to_string()).await.unwrap();
+        account.user_id = "c".to_string();
+        repo.update(account.clone()).await?;
+        account.balance = Decimal::new(1000, 2);
+        assert!(repo.update(account).await.is_err());
+
+        // deleting the holder frees the value
+        let holder = repo.find_by_id("3".to_string()).await.unwrap();
+        repo.delete(holder).await?;
+        repo.insert(duplicate).await?;
+        assert_eq!(repo.count(None).await, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_explain_ordered_index_with_limit() -> Result<()> {
+        let mut repo = test_repository::<TestAccount>("accounts_explain")?;
+        repo.create_index(IndexDefinition::new("balance"))?;
+        repo.create_index(IndexDefinition::new("user_id"))?;
+        insert_accounts(&mut repo).await?;
+        repo.insert(TestAccount {
+            id: "5".to_string(),
+            user_id: "b".to_string(),
+            balance: Decimal::new(75, 2),
+        })
+        .await?;
+
+        // ordered by the balance index, reading stops after the run holding the limit
+        let mut criteria = SearchCriteria::new();
+        criteria.add_sort("balance", false);
+        criteria.add_limit(2);
+        let explain = repo.explain(criteria.clone()).await;
+        assert_eq!(explain.plan.access, AccessPath::FullScan);
+        assert_eq!(explain.plan.order_index.as_deref(), Some("balance"));
+        assert!(explain.plan.limit_pushed_down);
+        assert!(!explain.plan.needs_sort);
+        assert_eq!(explain.records_scanned, 2);
+        let ids: Vec<String> = repo.find(Some(criteria.clone())).await.into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+
+        // ties of the last value are read, then sorted by the second sort field
+        criteria.limit = Some(3);
+        criteria.add_sort("id", false);
+        let explain = repo.explain(criteria.clone()).await;
+        assert!(explain.plan.needs_sort);
+        assert_eq!(explain.records_scanned, 4);
+        assert_eq!(explain.records_returned, 3);
+        let ids: Vec<String> = repo.find(Some(criteria)).await.into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec!["1", "2", "5"]);
+
+        // an index lookup is ordered by the sort index without reading other records
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("b".into()));
+        criteria.add_sort("balance", true);
+        let explain = repo.explain(criteria.clone()).await;
+        assert!(matches!(explain.plan.access, AccessPath::IndexLookup { ref field, .. } if field == "user_id"));
+        assert_eq!(explain.plan.estimated_records, 3);
+        assert_eq!(explain.records_scanned, 3);
+        let ids: Vec<String> = repo.find(Some(criteria)).await.into_iter().map(|a| a.id).collect();
+        assert_eq!(ids[0], "4");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_text_search() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_text")?;
+        let definition = TextIndexDefinition::new(&["text"]).with_stemming();
+        repo.create_text_index(definition.clone())?;
+        repo.insert(note("1", "a", "Indexes speed up queries")).await?;
+        repo.insert(note("2", "a", "The planner picks an index for the query")).await?;
+        repo.insert(note("3", "b", "Bread recipes")).await?;
+        repo.insert(note("4", "b", "Query the index")).await?;
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        assert_eq!(ids(repo.text_search("index query", 10, None).await?), vec!["4", "1", "2"]);
+        assert_eq!(ids(repo.text_search("index query", 1, None).await?), vec!["4"]);
+
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("a".into()));
+        assert_eq!(ids(repo.text_search("index", 10, Some(criteria)).await?), vec!["1", "2"]);
+
+        // writes update the index
+        repo.update(note("4", "b", "Sourdough bread")).await?;
+        repo.delete(note("1", "a", "")).await?;
+        assert_eq!(ids(repo.text_search("index query", 10, None).await?), vec!["2"]);
+        // equal scores are ordered by write order, not by the ids as strings
+        repo.insert(note("10", "b", "Rye bread")).await?;
+        assert_eq!(ids(repo.text_search("bread", 10, None).await?), vec!["3", "4", "10"]);
+
+        // a reopened repository loads the snapshot and replays the records written after it
+        repo.shutdown().await?;
+        repo.insert(note("5", "c", "Indexing notes")).await?;
+        let mut reopened = FsRepository::<String, TestNote>::new(
+            "notes_text".to_string(),
+            PathBuf::from("data/tests/notes_text"),
+        )?;
+        reopened.create_text_index(definition)?;
+        reopened.initialize().await?;
+        assert_eq!(ids(reopened.text_search("index", 10, None).await?), vec!["5", "2"]);
+
+        let mut plain = test_repository::<TestNote>("notes_plain")?;
+        assert!(plain.text_search("index", 10, None).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_search() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_hybrid")?;
+        repo.create_text_index(TextIndexDefinition::new(&["text"]))?;
+        for (id, text, vector) in [
+            ("1", "rust storage engine", [1.0, 0.0]),
+            ("2", "vector search in rust", [0.8, 0.6]),
+            ("3", "bread recipes", [0.0, 1.0]),
+        ] {
+            repo.insert(TestNote {
+                vector: vector.to_vec(),
+                ..note(id, "a", text)
+            })
+            .await?;
+        }
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        let query = [0.0, 1.0];
+        // 2 is second for both the vector and the text, 3 is first for the vector only
+        let results = repo.hybrid_search(&query, "vector search", 3, Fusion::default(), None).await?;
+        assert_eq!(ids(results), vec!["2", "3", "1"]);
+
+        let weighted = |vector_weight| Fusion::Weighted { vector_weight };
+        assert_eq!(ids(repo.hybrid_search(&query, "vector search", 1, weighted(1.0), None).await?), vec!["3"]);
+        assert_eq!(ids(repo.hybrid_search(&query, "vector search", 1, weighted(0.0), None).await?), vec!["2"]);
+
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("text", SearchOp::Contains, SearchValue::String("rust".into()));
+        let results = repo.hybrid_search(&query, "engine", 3, Fusion::default(), Some(criteria)).await?;
+        assert_eq!(ids(results), vec!["1", "2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_with_metrics() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_metrics")?;
+        repo.create_text_index(TextIndexDefinition::new(&["text"]))?;
+        for (id, vector) in [("1", [10.0, 10.0]), ("2", [1.0, 0.5]), ("3", [0.0, 2.0])] {
+            repo.insert(TestNote {
+                vector: vector.to_vec(),
+                ..note(id, "a", "")
+            })
+            .await?;
+        }
+
+        let ids = |results: &[(TestNote, f32)]| -> Vec<String> {
+            results.iter().map(|(note, _)| note.id.clone()).collect()
+        };
+        let query = [1.0, 1.0];
+        // 1 points the same way as the query but lies far from it, 2 is the closest
+        assert_eq!(ids(&repo.semantic_search(&query, 3, None).await?), vec!["1", "2", "3"]);
+        let results = repo.semantic_search_with_metric(&query, 3, DistanceMetric::Euclidean, None).await?;
+        assert_eq!(ids(&results), vec!["2", "3", "1"]);
+        assert!((results[0].1 - 0.5).abs() < 1e-6);
+        let results = repo.semantic_search_with_metric(&query, 1, DistanceMetric::DotProduct, None).await?;
+        assert_eq!(ids(&results), vec!["1"]);
+
+        // the collection metric applies to semantic and hybrid search; the vector index
+        // only answers cosine queries
+        repo.set_distance_metric(DistanceMetric::Manhattan);
+        repo.create_vector_index(VectorIndexDefinition::Hnsw(HnswParams::default()))?;
+        assert_eq!(ids(&repo.semantic_search(&query, 3, None).await?), vec!["2", "3", "1"]);
+        let results = repo.semantic_search_with_metric(&query, 1, DistanceMetric::Cosine, None).await?;
+        assert_eq!(ids(&results), vec!["1"]);
+        let results = repo.hybrid_search(&query, "", 1, Fusion::Weighted { vector_weight: 1.0 }, None).await?;
+        assert_eq!(ids(&results), vec!["2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vector_config_validation() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_vector_config")?;
+        repo.set_vector_config(VectorConfig::new(2, DistanceMetric::Euclidean));
+        assert_eq!(repo.distance_metric(), DistanceMetric::Euclidean);
+
+        let error = |result: Result<()>| result.unwrap_err().downcast::<VectorValidationError>().unwrap();
+        let e = error(repo.insert(TestNote { vector: vec![1.0, 0.0, 0.0], ..note("1", "a", "") }).await);
+        assert_eq!(
+            e,
+            VectorValidationError::DimensionMismatch {
+                collection: "notes_vector_config".to_string(),
+                expected: 2,
+                actual: 3,
+            }
+        );
+        let e = error(repo.insert(TestNote { vector: vec![f32::NAN, 0.0], ..note("1", "a", "") }).await);
+        assert!(matches!(e, VectorValidationError::NonFiniteValue { position: 0, .. }));
+        assert_eq!(repo.count(None).await, 0);
+
+        repo.insert(TestNote { vector: vec![1.0, 0.0], ..note("1", "a", "") }).await?;
+        repo.insert(TestNote { vector: vec![4.0, 4.0], ..note("2", "a", "") }).await?;
+        let e = error(repo.update(TestNote { vector: vec![], ..note("2", "a", "") }).await);
+        assert!(matches!(e, VectorValidationError::DimensionMismatch { actual: 0, .. }));
+
+        // the declared metric ranks the records; 2 is more similar but farther
+        let results = repo.semantic_search(&[2.0, 2.0], 2, None).await?;
+        let ids: Vec<String> = results.into_iter().map(|(note, _)| note.id).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+
+        let e = repo.semantic_search(&[1.0, 0.0, 0.0], 1, None).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<VectorValidationError>(),
+            Some(VectorValidationError::QueryDimensionMismatch { expected: 2, actual: 3, .. })
+        ));
+        let e = repo.semantic_search(&[f32::INFINITY, 0.0], 1, None).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<VectorValidationError>(),
+            Some(VectorValidationError::NonFiniteQuery { position: 0, .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_with_vector_store() -> Result<()> {
+        let name = "notes_vector_store";
+        let mut repo = test_repository::<TestNote>(name)?;
+        // written before the store is enabled, appended to it when it is created
+        repo.insert(TestNote { vector: vec![1.0, 0.0], ..note("1", "a", "") }).await?;
+        repo.create_vector_store()?;
+        repo.insert(TestNote { vector: vec![0.0, 1.0], ..note("2", "a", "") }).await?;
+        repo.insert(TestNote { vector: vec![0.6, 0.8], ..note("3", "b", "") }).await?;
+        repo.update(TestNote { vector: vec![0.8, 0.6], ..note("2", "a", "") }).await?;
+        repo.insert(TestNote { vector: vec![1.0, 0.1], ..note("4", "b", "") }).await?;
+        repo.delete(note("4", "b", "")).await?;
+
+        let offset = repo.offsetm["3"];
+        let (header, _) = read_record::<TestNote>(&mut repo.file, offset)?;
+        assert!(header.has_flag(crate::fs::file::FLAG_HAS_VECTOR));
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        let query = [1.0, 0.0];
+        assert_eq!(ids(repo.semantic_search(&query, 3, None).await?), vec!["1", "2", "3"]);
+        let results = repo.semantic_search_with_metric(&query, 1, DistanceMetric::Euclidean, None).await?;
+        assert_eq!(ids(results), vec!["1"]);
+        // conditions are checked on the records
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("b".into()));
+        assert_eq!(ids(repo.semantic_search(&query, 3, Some(criteria)).await?), vec!["3"]);
+
+        // a reopened repository attaches the stored vectors to the live records
+        let mut reopened = FsRepository::<String, TestNote>::new(
+            name.to_string(),
+            PathBuf::from("data/tests").join(name),
+        )?;
+        reopened.create_vector_store()?;
+        reopened.initialize().await?;
+        let length = fs::metadata(reopened.vector_store_path())?.len();
+        assert_eq!(reopened.vector_store.as_ref().map(|store| store.len()), Some(3));
+        assert_eq!(ids(reopened.semantic_search(&query, 3, None).await?), vec!["1", "2", "3"]);
+        assert_eq!(fs::metadata(reopened.vector_store_path())?.len(), length);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_with_compact_storage() -> Result<()> {
+        let name = "notes_compact_storage";
+        let mut repo = test_repository::<TestNote>(name)?;
+        let vector = |i: usize| -> Vec<f32> { (0..64).map(|j| ((i * 7 + j * 3) % 11) as f32 / 5.0 - 1.0).collect() };
+        repo.set_vector_config(VectorConfig::new(64, DistanceMetric::Cosine).with_storage(VectorStorage::F16));
+        repo.create_vector_store()?;
+        for i in 0..5 {
+            repo.insert(TestNote { vector: vector(i), ..note(&i.to_string(), "a", "") }).await?;
+        }
+        // 16 bytes of header and 2 bytes per value
+        assert_eq!(fs::metadata(repo.vector_store_path())?.len(), 5 * 144);
+
+        let query = vector(2);
+        let results = repo.semantic_search(&query, 5, None).await?;
+        assert_eq!(results[0].0.id, "2");
+        for (note, score) in &results {
+            assert!((score - cosine_similarity(&query, &note.vector)).abs() < 1e-3);
+        }
+
+        // binary storage keeps the signs, compared by their Hamming distance; the vectors
+        // written before keep their format
+        repo.set_vector_config(VectorConfig::new(64, DistanceMetric::Hamming).with_storage(VectorStorage::Binary));
+        repo.update(TestNote { vector: vector(3), ..note("3", "a", "") }).await?;
+        assert_eq!(fs::metadata(repo.vector_store_path())?.len(), 5 * 144 + 32);
+        let mut expected: Vec<(String, f32)> =
+            (0..5).map(|i| (i.to_string(), sign_distance(&query, &vector(i)))).collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        let scores = |results: Vec<(TestNote, f32)>| -> Vec<(String, f32)> {
+            results.into_iter().map(|(note, score)| (note.id, score)).collect()
+        };
+        assert_eq!(scores(repo.semantic_search(&query, 5, None).await?), expected);
+
+        // a reopened repository reads the entries of both formats
+        let mut reopened = FsRepository::<String, TestNote>::new(
+            name.to_string(),
+            PathBuf::from("data/tests").join(name),
+        )?;
+        reopened.set_vector_config(VectorConfig::new(64, DistanceMetric::Hamming).with_storage(VectorStorage::Binary));
+        reopened.create_vector_store()?;
+        reopened.initialize().await?;
+        assert_eq!(reopened.vector_storage(), VectorStorage::Binary);
+        assert_eq!(scores(reopened.semantic_search(&query, 5, None).await?), expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_min_score() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_min_score")?;
+        for (id, vector) in [
+            ("1", vec![1.0, 0.0]),
+            ("6", vec![0.8, 0.6]),
+            ("2", vec![0.8, 0.6]),
+            ("3", vec![0.6, 0.8]),
+            ("4", vec![0.0, 1.0]),
+            ("5", vec![f32::NAN, 0.0]),
+        ] {
+            repo.insert(TestNote { vector, ..note(id, "a", "") }).await?;
+        }
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        let query = [1.0, 0.0];
+        let cosine = VectorSearchOptions::new().with_min_score(0.7);
+        let euclidean = VectorSearchOptions::new().with_metric(DistanceMetric::Euclidean).with_min_score(0.7);
+        // NaN scores are dropped and equal scores keep the order the records were written,
+        // whether the records or the vector store are scanned
+        let mut criteria = SearchCriteria::new();
+        criteria.add_limit(10);
+        let results = repo.semantic_search(&query, 6, Some(criteria.clone())).await?;
+        assert_eq!(ids(results), vec!["1", "6", "2", "3", "4"]);
+        let results = repo.semantic_search_with_options(&query, 6, cosine, Some(criteria.clone())).await?;
+        assert_eq!(ids(results), vec!["1", "6", "2"]);
+        let results = repo.semantic_search_with_options(&query, 6, euclidean, Some(criteria)).await?;
+        assert_eq!(ids(results), vec!["1", "6", "2"]);
+        repo.create_vector_store()?;
+        assert_eq!(ids(repo.semantic_search(&query, 6, None).await?), vec!["1", "6", "2", "3", "4"]);
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 6, cosine, None).await?), vec!["1", "6", "2"]);
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 6, euclidean, None).await?), vec!["1", "6", "2"]);
+
+        repo.delete(note("5", "a", "")).await?;
+        repo.create_vector_index(VectorIndexDefinition::Hnsw(HnswParams::default()))?;
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 6, cosine, None).await?), vec!["1", "6", "2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_mmr() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_mmr")?;
+        for (id, vector) in [
+            ("1", vec![1.0, 0.05]),
+            ("2", vec![1.0, 0.06]),
+            ("3", vec![1.0, 0.07]),
+            ("4", vec![0.7, 0.7]),
+            ("5", vec![0.7, -0.7]),
+        ] {
+            repo.insert(TestNote { vector, ..note(id, "a", "") }).await?;
+        }
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        let query = [1.0, 0.0];
+        let diverse = VectorSearchOptions::new().with_mmr(Mmr::new(0.3));
+        assert_eq!(ids(repo.semantic_search(&query, 3, None).await?), vec!["1", "2", "3"]);
+        // the near duplicates of 1 give way to the other directions, keeping their scores
+        let results = repo.semantic_search_with_options(&query, 3, diverse, None).await?;
+        assert!((results[1].1 - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(ids(results), vec!["1", "5", "4"]);
+        // pure relevance
+        let relevant = VectorSearchOptions::new().with_mmr(Mmr::new(1.0));
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 3, relevant, None).await?), vec!["1", "2", "3"]);
+        // the candidates are limited to the best fetch_k
+        let narrow = VectorSearchOptions::new().with_mmr(Mmr::new(0.3).with_fetch_k(4));
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 3, narrow, None).await?), vec!["1", "4", "2"]);
+
+        repo.create_vector_store()?;
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 3, diverse, None).await?), vec!["1", "5", "4"]);
+        repo.create_vector_index(VectorIndexDefinition::Hnsw(HnswParams::default()))?;
+        assert_eq!(ids(repo.semantic_search_with_options(&query, 3, diverse, None).await?), vec!["1", "5", "4"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_vector_search() -> Result<()> {
+        let name = "documents_chunks";
+        let mut repo = test_repository::<TestDocument>(name)?;
+        repo.insert(document("1", "a", &[[1.0, 0.0], [0.9, 0.1], [0.0, 1.0]])).await?;
+        repo.insert(document("2", "b", &[[0.95, 0.05]])).await?;
+        repo.insert(document("3", "a", &[[0.7, 0.7], [0.8, 0.6], [0.6, 0.8]])).await?;
+        repo.insert(document("4", "b", &[])).await?;
+
+        check_multi_vector_search(&mut repo).await?;
+        repo.create_chunk_index(VectorIndexDefinition::Hnsw(HnswParams::default()));
+        check_multi_vector_search(&mut repo).await?;
+
+        // writes maintain the index, and initialize rebuilds it
+        repo.update(document("2", "b", &[[0.0, 1.0]])).await?;
+        repo.delete(document("1", "a", &[])).await?;
+        let ids = |documents: Vec<DocumentMatch<TestDocument>>| -> Vec<String> {
+            documents.into_iter().map(|document| document.item.id).collect()
+        };
+        let options = MultiVectorOptions::default();
+        assert_eq!(ids(repo.multi_vector_search(&[1.0, 0.0], 2, options, None).await?), vec!["3", "2"]);
+        repo.initialize().await?;
+        assert_eq!(repo.chunk_index.as_ref().map(|index| index.index().len()), Some(4));
+        assert_eq!(ids(repo.multi_vector_search(&[1.0, 0.0], 2, options, None).await?), vec!["3", "2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_declared_vector_config() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_declared_config")?;
+        repo.declare_vector_config(VectorConfig::new(2, DistanceMetric::Euclidean));
+        assert_eq!(repo.distance_metric(), DistanceMetric::Euclidean);
+
+        // queries are checked at once, writes once the vector store binds the model's vector
+        let e = repo.semantic_search(&[1.0, 0.0, 0.0], 1, None).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<VectorValidationError>(),
+            Some(VectorValidationError::QueryDimensionMismatch { actual: 3, .. })
+        ));
+        repo.create_vector_store()?;
+        let e = repo.insert(TestNote { vector: vec![1.0, 0.0, 0.0], ..note("1", "a", "") }).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<VectorValidationError>(),
+            Some(VectorValidationError::DimensionMismatch { actual: 3, .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunk_vector_config_validation() -> Result<()> {
+        let mut repo = test_repository::<TestDocument>("documents_chunk_config")?;
+        repo.create_chunk_index(VectorIndexDefinition::Hnsw(HnswParams::default()));
+        let e = repo.set_chunk_vector_config(VectorConfig::new(2, DistanceMetric::Euclidean)).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<VectorValidationError>(),
+            Some(VectorValidationError::UnsupportedChunkMetric { .. })
+        ));
+        assert_eq!(repo.distance_metric(), DistanceMetric::Cosine);
+        repo.set_chunk_vector_config(VectorConfig::new(2, DistanceMetric::Cosine))?;
+
+        let error = |result: Result<()>| result.unwrap_err().downcast::<VectorValidationError>().unwrap();
+        let mut wrong = document("1", "a", &[[1.0, 0.0]]);
+        wrong.chunks.push(vec![0.0, 1.0, 0.0]);
+        let e = error(repo.insert(wrong).await);
+        assert!(matches!(e, VectorValidationError::DimensionMismatch { expected: 2, actual: 3, .. }));
+        let e = error(repo.insert(document("1", "a", &[[1.0, 0.0], [0.0, f32::NAN]])).await);
+        assert!(matches!(e, VectorValidationError::NonFiniteValue { position: 1, .. }));
+        assert!(repo.find_by_id("1".to_string()).await.is_none());
+        assert_eq!(repo.chunk_index.as_ref().map(|index| index.index().len()), Some(0));
+
+        repo.insert(document("1", "a", &[[1.0, 0.0], [0.0, 1.0]])).await?;
+        assert_eq!(repo.chunk_index.as_ref().map(|index| index.index().len()), Some(2));
+        Ok(())
+    }
+
+    async fn check_multi_vector_search(repo: &mut FsRepository<String, TestDocument>) -> Result<()> {
+        let ids = |documents: &[DocumentMatch<TestDocument>]| -> Vec<String> {
+            documents.iter().map(|document| document.item.id.clone()).collect()
+        };
+        let query = [1.0, 0.0];
+        let documents = repo.multi_vector_search(&query, 2, MultiVectorOptions::default(), None).await?;
+        assert_eq!(ids(&documents), vec!["1", "2"]);
+        assert_eq!(documents[0].score, 1.0);
+        let chunks: Vec<usize> = documents[0].chunks.iter().map(|(chunk, _)| *chunk).collect();
+        assert_eq!(chunks, vec![0, 1, 2]);
+
+        let options = MultiVectorOptions::default().with_min_score(0.5);
+        let documents = repo.multi_vector_search(&query, 2, options, None).await?;
+        assert_eq!(documents[0].chunks.len(), 2);
+
+        // the sum favors the document with many similar chunks, among the matched ones
+        let sum = MultiVectorOptions::new(ChunkAggregation::Sum);
+        let documents = repo.multi_vector_search(&query, 2, sum, None).await?;
+        assert_eq!(ids(&documents), vec!["3", "1"]);
+        assert!((documents[0].score - 2.1071).abs() < 1e-3);
+        let documents = repo.multi_vector_search(&query, 2, sum.with_chunk_k(3), None).await?;
+        assert_eq!(ids(&documents), vec!["1", "2"]);
+
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("b".into()));
+        let documents = repo.multi_vector_search(&query, 2, sum, Some(criteria)).await?;
+        assert_eq!(ids(&documents), vec!["2"]);
+        Ok(())
+    }
+
+    // HiddenNote overrides matches_filter to leave hidden notes out of every search
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct HiddenNote {
+        id: String,
+        user_id: String,
+        hidden: bool,
+        vector: Vec<f32>,
+    }
+
+    impl VectorEmbedding for HiddenNote {
+        fn vector(&self) -> &[f32] {
+            &self.vector
+        }
+    }
+
+    impl RepoModel<String> for HiddenNote {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+        fn collection(&self) -> &'static str {
+            "hidden_note"
+        }
+    }
+
+    impl Searchable for HiddenNote {
+        const MATCHES_BY_FIELDS: bool = false;
+
+        fn matches_filter(&self, criteria: &SearchCriteria) -> bool {
+            !self.hidden && criteria.matches(self)
+        }
+
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "user_id" => Some(SortValue::String(self.user_id.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_applies_overridden_filter() -> Result<()> {
+        let mut repo = test_repository::<HiddenNote>("notes_hidden")?;
+        repo.create_index(IndexDefinition::new("user_id"))?;
+        for (id, hidden, vector) in [("1", true, [1.0, 0.0]), ("2", false, [0.8, 0.6]), ("3", false, [0.0, 1.0])] {
+            let note = HiddenNote { id: id.to_string(), user_id: "a".to_string(), hidden, vector: vector.to_vec() };
+            repo.insert(note).await?;
+        }
+
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("a".into()));
+        let ids = |results: Vec<(HiddenNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        for step in 0..2 {
+            if step == 1 {
+                repo.create_vector_store()?;
+            }
+            let results = repo.semantic_search(&[1.0, 0.0], 3, Some(criteria.clone())).await?;
+            assert_eq!(ids(results), vec!["2", "3"]);
+            assert_eq!(repo.find(Some(criteria.clone())).await.len(), 2);
+        }
+        Ok(())
+    }
+
+    // DESERIALIZED counts the CountedNote records read from the log
+    static DESERIALIZED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct CountedNote {
+        #[serde(deserialize_with = "counted")]
+        id: String,
+        user_id: String,
+        tag: String,
+        vector: Vec<f32>,
+    }
+
+    fn counted<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        DESERIALIZED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        String::deserialize(deserializer)
+    }
+
+    impl VectorEmbedding for CountedNote {
+        fn vector(&self) -> &[f32] {
+            &self.vector
+        }
+    }
+
+    impl RepoModel<String> for CountedNote {
+        fn id(&self) -> String {
+            self.id.clone()
+        }
+        fn collection(&self) -> &'static str {
+            "counted_note"
+        }
+    }
+
+    impl Searchable for CountedNote {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "user_id" => Some(SortValue::String(self.user_id.clone())),
+                "tag" => Some(SortValue::String(self.tag.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filtered_semantic_search_reads_top_k() -> Result<()> {
+        let mut repo = test_repository::<CountedNote>("notes_filtered")?;
+        repo.create_index(IndexDefinition::new("user_id"))?;
+        for i in 0..20 {
+            let angle = i as f32 * std::f32::consts::PI / 40.0;
+            repo.insert(CountedNote {
+                id: format!("{:02}", i),
+                user_id: if i % 2 == 0 { "even" } else { "odd" }.to_string(),
+                tag: if i % 4 < 2 { "x" } else { "y" }.to_string(),
+                vector: vec![angle.cos(), angle.sin()],
+            })
+            .await?;
+        }
+
+        let ids = |results: Vec<(CountedNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        let reads = || DESERIALIZED.swap(0, std::sync::atomic::Ordering::SeqCst);
+        let query = [1.0, 0.0];
+        let mut odd = SearchCriteria::new();
+        odd.add_condition("user_id", SearchOp::Eq, SearchValue::String("odd".into()));
+        let mut odd_y = odd.clone();
+        odd_y.add_condition("tag", SearchOp::Eq, SearchValue::String("y".into()));
+
+        // the records hold the vectors: the candidates of the index lookup are read once
+        reads();
+        assert_eq!(ids(repo.semantic_search(&query, 2, Some(odd.clone())).await?), vec!["01", "03"]);
+        assert_eq!(reads(), 10 + 2);
+
+        // with the vector store, the index answers the condition and only the results are read
+        repo.create_vector_store()?;
+        reads();
+        assert_eq!(ids(repo.semantic_search(&query, 2, Some(odd.clone())).await?), vec!["01", "03"]);
+        assert_eq!(reads(), 2);
+        assert_eq!(ids(repo.semantic_search(&query, 3, None).await?), vec!["00", "01", "02"]);
+        assert_eq!(reads(), 3);
+
+        // a condition on a field without index is checked on the candidates
+        assert_eq!(ids(repo.semantic_search(&query, 2, Some(odd_y.clone())).await?), vec!["03", "07"]);
+        assert_eq!(reads(), 10 + 2);
+
+        // the hits of the vector index are re-ranked from the vector store, reading the results
+        repo.create_vector_index(VectorIndexDefinition::Hnsw(HnswParams::default()))?;
+        reads();
+        assert_eq!(ids(repo.semantic_search(&query, 2, Some(odd.clone())).await?), vec!["01", "03"]);
+        assert_eq!(reads(), 2);
+        assert_eq!(ids(repo.semantic_search(&query, 2, Some(odd_y)).await?), vec!["03", "07"]);
+        assert!(reads() < 20);
+        // a batch reads the records shared by the results of its queries once
+        let queries = vec![query.to_vec(), vec![1.0, 0.1]];
+        let batch = repo.semantic_search_batch(&queries, 2, VectorSearchOptions::new(), Some(odd)).await?;
+        assert_eq!(batch.into_iter().map(ids).collect::<Vec<_>>(), vec![vec!["01", "03"], vec!["01", "03"]]);
+        assert_eq!(reads(), 2);
+
+        // hybrid search scores the vectors of the store and reads the fused results
+        repo.create_text_index(TextIndexDefinition::new(&["tag"]))?;
+        reads();
+        let results = repo.hybrid_search(&query, "y", 2, Fusion::ReciprocalRank { k: 60.0 }, None).await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(reads(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_records_are_reported() -> Result<()> {
+        use std::io::Write;
+
+        let name = "notes_unreadable";
+        let mut repo = test_repository::<TestNote>(name)?;
+        repo.insert(TestNote { vector: vec![1.0, 0.0], ..note("1", "a", "") }).await?;
+        repo.insert(TestNote { vector: vec![0.0, 1.0], ..note("2", "a", "") }).await?;
+
+        // flip the last byte of the first record, so its checksum no longer matches
+        let path = FsRepository::<String, TestNote>::file_path(name, &PathBuf::from("data/tests").join(name));
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(repo.offsetm["2"] - 1))?;
+        file.write_all(&[0xff])?;
+
+        // reads that cannot fail skip the record, searches return the error
+        assert_eq!(repo.find_all().await.len(), 1);
+        assert!(repo.find_by_id("1".to_string()).await.is_none());
+        assert!(repo.semantic_search(&[1.0, 0.0], 1, None).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_batch() -> Result<()> {
+        let mut repo = test_repository::<TestNote>("notes_batch")?;
+        repo.create_index(IndexDefinition::new("user_id"))?;
+        for i in 0..30 {
+            let angle = i as f32 * std::f32::consts::PI / 30.0;
+            repo.insert(TestNote {
+                vector: vec![angle.cos(), angle.sin()],
+                ..note(&format!("{:02}", i), if i % 3 == 0 { "a" } else { "b" }, &format!("{:02}", i))
+            })
+            .await?;
+        }
+
+        let ids = |results: &[(TestNote, f32)]| -> Vec<String> {
+            results.iter().map(|(note, _)| note.id.clone()).collect()
+        };
+        let queries = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![-1.0, 0.2]];
+        let mut a = SearchCriteria::new();
+        a.add_condition("user_id", SearchOp::Eq, SearchValue::String("a".into()));
+        let mut limited = a.clone();
+        limited.add_sort("text", false);
+        limited.add_limit(4);
+        let searches = [
+            (VectorSearchOptions::new(), None),
+            (VectorSearchOptions::new().with_metric(DistanceMetric::Euclidean), Some(a)),
+            (VectorSearchOptions::new().with_min_score(0.9), Some(limited)),
+        ];
+        for step in 0..3 {
+            match step {
+                1 => repo.create_vector_store()?,
+                2 => repo.create_vector_index(VectorIndexDefinition::Hnsw(HnswParams::default()))?,
+                _ => {}
+            }
+            for (options, criteria) in &searches {
+                let batch = repo.semantic_search_batch(&queries, 3, *options, criteria.clone()).await?;
+                assert_eq!(batch.len(), queries.len());
+                for (query, results) in queries.iter().zip(&batch) {
+                    let single = repo.semantic_search_with_options(query, 3, *options, criteria.clone()).await?;
+                    assert_eq!(ids(results), ids(&single));
+                }
+            }
+        }
+        let batch = repo.semantic_search_batch(&queries, 2, VectorSearchOptions::new(), None).await?;
+        assert_eq!(ids(&batch[0]), vec!["00", "01"]);
+        assert_eq!(ids(&batch[1]), vec!["15", "14"]);
+
+        // every query is checked before any is run
+        repo.set_vector_config(VectorConfig::new(2, DistanceMetric::Cosine));
+        let queries = vec![vec![1.0, 0.0], vec![1.0, 0.0, 0.0]];
+        let e = repo.semantic_search_batch(&queries, 1, VectorSearchOptions::new(), None).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<VectorValidationError>(),
+            Some(VectorValidationError::QueryDimensionMismatch { actual: 3, .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_batch_on_compact_storage() -> Result<()> {
+        for (name, storage) in [("notes_batch_f16", VectorStorage::F16), ("notes_batch_binary", VectorStorage::Binary)] {
+            let mut repo = test_repository::<TestNote>(name)?;
+            repo.set_vector_storage(storage);
+            repo.create_vector_store()?;
+            for i in 0..30 {
+                let angle = i as f32 * std::f32::consts::PI / 15.0;
+                repo.insert(TestNote {
+                    vector: vec![angle.cos(), angle.sin(), (i % 7) as f32 / 7.0 - 0.4],
+                    ..note(&format!("{:02}", i), "a", "")
+                })
+                .await?;
+            }
+
+            let queries = vec![vec![1.0, 0.0, 0.0], vec![-0.3, 0.9, 0.2], vec![0.5, -0.5, -0.7]];
+            for options in [
+                VectorSearchOptions::new(),
+                VectorSearchOptions::new().with_min_score(0.3),
+                VectorSearchOptions::new().with_metric(DistanceMetric::Euclidean),
+            ] {
+                let batch = repo.semantic_search_batch(&queries, 5, options, None).await?;
+                for (query, results) in queries.iter().zip(batch) {
+                    let single = repo.semantic_search_with_options(query, 5, options, None).await?;
+                    let scored = |results: Vec<(TestNote, f32)>| -> Vec<(String, f32)> {
+                        results.into_iter().map(|(note, score)| (note.id, score)).collect()
+                    };
+                    assert_eq!(scored(results), scored(single), "{:?} {:?}", storage, options);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search_with_vector_index() -> Result<()> {
+        check_semantic_search_with_index("notes_hnsw", VectorIndexDefinition::Hnsw(HnswParams::default())).await?;
+        check_semantic_search_with_index("notes_ivf", VectorIndexDefinition::IvfFlat(IvfParams::new(4, 1))).await?;
+        let scalar = IvfParams::new(4, 1).with_quantization(Quantization::Scalar);
+        check_semantic_search_with_index("notes_ivf_sq", VectorIndexDefinition::IvfFlat(scalar)).await?;
+        let product = IvfParams::new(4, 1).with_quantization(Quantization::Product { subspaces: 2 });
+        check_semantic_search_with_index("notes_ivf_pq", VectorIndexDefinition::IvfFlat(product)).await
+    }
+
+    async fn check_semantic_search_with_index(name: &str, definition: VectorIndexDefinition) -> Result<()> {
+        let mut repo = test_repository::<TestNote>(name)?;
+        repo.create_vector_index(definition.clone())?;
+        // a stride coprime with 40 spreads the angles, so the quantizers train on the whole arc
+        for i in (0..40).map(|n| n * 7 % 40) {
+            let angle = i as f32 * std::f32::consts::PI / 80.0;
+            repo.insert(TestNote {
+                vector: vec![angle.cos(), angle.sin()],
+                ..note(&i.to_string(), if i % 2 == 0 { "even" } else { "odd" }, "")
+            })
+            .await?;
+        }
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        assert_eq!(ids(repo.semantic_search(&[1.0, 0.0], 3, None).await?), vec!["0", "1", "2"]);
+
+        // conditions are applied to the hits, widening the search until top_k match
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("odd".into()));
+        assert_eq!(ids(repo.semantic_search(&[1.0, 0.0], 3, Some(criteria)).await?), vec!["1", "3", "5"]);
+
+        // writes maintain the index
+        repo.delete(note("0", "even", "")).await?;
+        repo.update(TestNote {
+            vector: vec![0.0, 1.0],
+            ..note("1", "odd", "")
+        })
+        .await?;
+        assert_eq!(ids(repo.semantic_search(&[1.0, 0.0], 2, None).await?), vec!["2", "3"]);
+        assert_eq!(ids(repo.semantic_search(&[0.0, 1.0], 1, None).await?), vec!["1"]);
+
+        // a reopened repository loads the snapshot and replays the records written after it
+        repo.shutdown().await?;
+        repo.delete(note("2", "even", "")).await?;
+        let mut reopened = FsRepository::<String, TestNote>::new(
+            name.to_string(),
+            PathBuf::from("data/tests").join(name),
+        )?;
+        reopened.initialize().await?;
+        reopened.create_vector_index(definition)?;
+        assert_eq!(ids(reopened.semantic_search(&[1.0, 0.0], 2, None).await?), vec!["3", "4"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embedder_vectorizes_writes_and_queries() -> Result<()> {
+        let name = "notes_embedder";
+        let mut repo = test_repository::<TestNote>(name)?;
+        let no_embedder = repo.semantic_search_text("rust", 1, None).await.unwrap_err();
+        assert!(matches!(
+            no_embedder.downcast_ref::<FsRepositoryError>(),
+            Some(FsRepositoryError::EmbedderMissing { .. })
+        ));
+
+        let embedder = Arc::new(CachedEmbedder::new(HashingEmbedder::new(256)));
+        repo.set_embedder(embedder.clone());
+        repo.insert(note("1", "a", "storage engines in rust")).await?;
+        repo.insert(note("2", "a", "baking sourdough bread")).await?;
+        repo.insert(note("3", "b", "rust compiler internals")).await?;
+
+        // the vectors are computed on write
+        let stored = repo.find_by_id("1".to_string()).await.map(|note| note.vector);
+        assert_eq!(stored, Some(HashingEmbedder::new(256).embed("storage engines in rust")?));
+
+        let ids = |results: Vec<(TestNote, f32)>| -> Vec<String> {
+            results.into_iter().map(|(note, _)| note.id).collect()
+        };
+        let results = repo.semantic_search_text("sourdough bread", 1, None).await?;
+        assert_eq!(ids(results), vec!["2"]);
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("user_id", SearchOp::Eq, SearchValue::String("a".into()));
+        let results = repo.semantic_search_text("rust", 1, Some(criteria)).await?;
+        assert_eq!(ids(results), vec!["1"]);
+
+        // rewriting unchanged text is served from the cache, a changed text is embedded
+        let misses = embedder.misses();
+        repo.update(note("1", "a", "storage engines in rust")).await?;
+        assert_eq!(embedder.misses(), misses);
+        repo.update(note("2", "a", "rust borrow checker")).await?;
+        assert_eq!(embedder.misses(), misses + 1);
+        let results = repo.semantic_search_text("borrow checker", 1, None).await?;
+        assert_eq!(ids(results), vec!["2"]);
         Ok(())
     }
 }
@@ -1,29 +1,72 @@
-use crate::core::Searchable;
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap};
 
-#[derive(Debug, Clone)]
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::core::{Searchable, SortValue};
+use crate::fs::errors::SearchValidationError;
+
+// SearchCriteria serializes to the JSON query format described in fs::search_json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SearchCriteria {
+    #[serde(
+        rename = "filter",
+        with = "crate::fs::search_json::filter",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub conditions: Vec<SearchCondition>,
+    #[serde(rename = "sort", default, skip_serializing_if = "Option::is_none")]
     pub sort_fields: Option<Vec<SortField>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub limit: Option<usize>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SearchCondition {
     pub field: String,
+    #[serde(rename = "op")]
     pub operator: SearchOp,
     pub value: SearchValue,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SearchOp {
+    #[serde(rename = "$eq")]
     Eq,           // Equality
+    #[serde(rename = "$ne")]
+    Ne,           // Not equal, also matches missing values
+    #[serde(rename = "$gte")]
     Gte,          // Greater than or equal
+    #[serde(rename = "$lte")]
     Lte,          // Less than or equal
+    #[serde(rename = "$gt")]
     Gt,           // Greater than
+    #[serde(rename = "$lt")]
     Lt,           // Less than
+    #[serde(rename = "$between")]
+    Between,      // Inclusive range, value is a two element array
+    #[serde(rename = "$in")]
     In,           // Value in array
+    #[serde(rename = "$nin")]
+    NotIn,        // Value not in array, also matches missing values
+    #[serde(rename = "$exists")]
+    Exists,       // Field is present and not null, Bool(false) inverts
+    #[serde(rename = "$contains")]
     Contains,     // String contains
+    #[serde(rename = "$startsWith")]
     StartsWith,   // String starts with
+    #[serde(rename = "$regex")]
+    Regex,        // String matches regular expression
+    #[serde(rename = "$ieq")]
+    EqIgnoreCase,         // Case-insensitive string equality
+    #[serde(rename = "$icontains")]
+    ContainsIgnoreCase,   // Case-insensitive string contains
+    #[serde(rename = "$istartsWith")]
+    StartsWithIgnoreCase, // Case-insensitive string starts with
 }
 
 #[derive(Debug, Clone)]
@@ -31,14 +74,77 @@ pub enum SearchValue {
     String(String),
     Decimal(rust_decimal::Decimal),
     Int(i64),
+    Float(f64),
     Bool(bool),
-    Array(Vec<String>),  // For "In" operator
+    DateTime(bson::DateTime),
+    Null,
+    Array(Vec<SearchValue>),  // For "In" operator
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SortField {
     pub field: String,
+    #[serde(
+        rename = "order",
+        with = "crate::fs::search_json::sort_order",
+        default = "crate::fs::search_json::sort_order::ascending"
+    )]
     pub ascending: bool,  // true = ascending, false = descending
+    #[serde(default)]
+    pub nulls: NullsOrder,
+}
+
+// NullsOrder places null or missing values before or after all other values,
+// regardless of the sort direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NullsOrder {
+    First,
+    #[default]
+    Last,
+}
+
+impl SearchValue {
+    // to_sort_value converts a scalar value for comparison with a field value. Arrays have no sort value.
+    pub fn to_sort_value(&self) -> Option<SortValue> {
+        match self {
+            SearchValue::String(v) => Some(SortValue::String(v.clone())),
+            SearchValue::Decimal(v) => Some(SortValue::Decimal(*v)),
+            SearchValue::Int(v) => Some(SortValue::Int(*v)),
+            SearchValue::Float(v) => Some(SortValue::Float(*v)),
+            SearchValue::Bool(v) => Some(SortValue::Bool(*v)),
+            SearchValue::DateTime(v) => Some(SortValue::DateTime(*v)),
+            SearchValue::Null => Some(SortValue::Null),
+            SearchValue::Array(_) => None,
+        }
+    }
+}
+
+impl SortField {
+    // compare orders two field values; missing values are treated as null
+    pub fn compare(&self, a: Option<&SortValue>, b: Option<&SortValue>) -> Ordering {
+        let a = a.filter(|v| !v.is_null());
+        let b = b.filter(|v| !v.is_null());
+        match (a, b) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => match self.nulls {
+                NullsOrder::First => Ordering::Less,
+                NullsOrder::Last => Ordering::Greater,
+            },
+            (Some(_), None) => match self.nulls {
+                NullsOrder::First => Ordering::Greater,
+                NullsOrder::Last => Ordering::Less,
+            },
+            (Some(a), Some(b)) => {
+                if self.ascending {
+                    a.cmp(b)
+                } else {
+                    b.cmp(a)
+                }
+            }
+        }
+    }
 }
 
 impl SearchCriteria {
@@ -58,10 +164,16 @@ impl SearchCriteria {
 
     // add sort
     pub fn add_sort(&mut self, field: &str, ascending: bool) {
+        self.add_sort_with_nulls(field, ascending, NullsOrder::default());
+    }
+
+    // add sort with explicit placement of null values
+    pub fn add_sort_with_nulls(&mut self, field: &str, ascending: bool, nulls: NullsOrder) {
 
         let sort_field = SortField {
             field: field.to_string(),
             ascending,
+            nulls,
         };        
 
         self.sort_fields.get_or_insert(Vec::new()).push(sort_field);
@@ -71,38 +183,441 @@ impl SearchCriteria {
     pub fn add_limit(&mut self, limit: usize) {
         self.limit.get_or_insert(limit);
     }
-    
 
-}
+    // validate checks that every condition has a value of the shape its operator expects
+    pub fn validate(&self) -> Result<(), SearchValidationError> {
+        self.conditions.iter().try_for_each(SearchCondition::validate)
+    }
 
+    // matches returns true when the item satisfies all conditions
+    pub fn matches<M: Searchable + ?Sized>(&self, item: &M) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(item.get_field_value(&condition.field).as_ref()))
+    }
 
-pub fn apply_sort<M: Searchable>(mut items: Vec<M>, sort_fields: &[SortField]) -> Vec<M>{
+}
 
-    items.sort_by(|a, b| {
-        for sort_field in sort_fields {
+impl SearchCondition {
+    // validate checks the value against the operator, that floats are finite and that regex
+    // patterns compile
+    pub fn validate(&self) -> Result<(), SearchValidationError> {
+        let is_finite = |v: &SearchValue| !matches!(v, SearchValue::Float(value) if !value.is_finite());
+        let finite = match &self.value {
+            SearchValue::Array(values) => values.iter().all(is_finite),
+            value => is_finite(value),
+        };
+        if !finite {
+            return Err(SearchValidationError::InvalidValue {
+                field: self.field.clone(),
+                operator: format!("{:?}", self.operator),
+                expected: "finite numbers",
+            });
+        }
 
-            let val_a = a.get_field_value(&sort_field.field);
-            let val_b = b.get_field_value(&sort_field.field);
-            
-            match (val_a, val_b) {
-                (Some(a), Some(b)) => {
-                    let ordering = if sort_field.ascending {
-                        a.cmp(&b)
-                    } else {
-                        b.cmp(&a)
-                    };
-                    
-                    if ordering != std::cmp::Ordering::Equal {
-                        return ordering;
+        let is_scalar = |v: &SearchValue| !matches!(v, SearchValue::Array(_));
+        let is_comparable = |v: &SearchValue| !matches!(v, SearchValue::Array(_) | SearchValue::Null);
+
+        let (valid, expected) = match (self.operator, &self.value) {
+            (SearchOp::Eq | SearchOp::Ne, value) => (is_scalar(value), "a scalar value"),
+            (SearchOp::Gt | SearchOp::Gte | SearchOp::Lt | SearchOp::Lte, value) => {
+                (is_comparable(value), "a non-null scalar value")
+            }
+            (SearchOp::Between, SearchValue::Array(bounds)) => (
+                bounds.len() == 2 && bounds.iter().all(is_comparable),
+                "an array of two non-null scalar values",
+            ),
+            (SearchOp::Between, _) => (false, "an array of two non-null scalar values"),
+            (SearchOp::In | SearchOp::NotIn, SearchValue::Array(values)) => {
+                (values.iter().all(is_scalar), "an array of scalar values")
+            }
+            (SearchOp::In | SearchOp::NotIn, _) => (false, "an array of scalar values"),
+            (SearchOp::Exists, value) => (matches!(value, SearchValue::Bool(_)), "a boolean"),
+            (SearchOp::Regex, SearchValue::String(pattern)) => {
+                return Regex::new(pattern).map(|_| ()).map_err(|e| {
+                    SearchValidationError::InvalidRegex {
+                        field: self.field.clone(),
+                        message: e.to_string(),
                     }
-                    // Continue to next sort field if equal
+                });
+            }
+            (_, value) => (matches!(value, SearchValue::String(_)), "a string"),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(SearchValidationError::InvalidValue {
+                field: self.field.clone(),
+                operator: format!("{:?}", self.operator),
+                expected,
+            })
+        }
+    }
+
+    // matches evaluates the condition against a field value; missing values are treated as null.
+    // Ordering operators only match values of the same type class (numbers, strings, ...).
+    pub fn matches(&self, value: Option<&SortValue>) -> bool {
+        let value = value.filter(|v| !v.is_null());
+        match self.operator {
+            SearchOp::Eq => equals(value, &self.value),
+            SearchOp::Ne => !equals(value, &self.value),
+            SearchOp::Gt => compare(value, &self.value) == Some(Ordering::Greater),
+            SearchOp::Gte => compare(value, &self.value).is_some_and(|o| o != Ordering::Less),
+            SearchOp::Lt => compare(value, &self.value) == Some(Ordering::Less),
+            SearchOp::Lte => compare(value, &self.value).is_some_and(|o| o != Ordering::Greater),
+            SearchOp::Between => match &self.value {
+                SearchValue::Array(bounds) if bounds.len() == 2 => {
+                    compare(value, &bounds[0]).is_some_and(|o| o != Ordering::Less)
+                        && compare(value, &bounds[1]).is_some_and(|o| o != Ordering::Greater)
                 }
-                _ => {} // S
+                _ => false,
+            },
+            SearchOp::In => contained_in(value, &self.value),
+            SearchOp::NotIn => !contained_in(value, &self.value),
+            SearchOp::Exists => value.is_some() != matches!(self.value, SearchValue::Bool(false)),
+            SearchOp::Contains => string_pair(value, &self.value).is_some_and(|(v, p)| v.contains(p)),
+            SearchOp::StartsWith => {
+                string_pair(value, &self.value).is_some_and(|(v, p)| v.starts_with(p))
+            }
+            SearchOp::Regex => {
+                string_pair(value, &self.value).is_some_and(|(v, p)| regex_is_match(p, v))
+            }
+            SearchOp::EqIgnoreCase => string_pair(value, &self.value)
+                .is_some_and(|(v, p)| v.to_lowercase() == p.to_lowercase()),
+            SearchOp::ContainsIgnoreCase => string_pair(value, &self.value)
+                .is_some_and(|(v, p)| v.to_lowercase().contains(&p.to_lowercase())),
+            SearchOp::StartsWithIgnoreCase => string_pair(value, &self.value)
+                .is_some_and(|(v, p)| v.to_lowercase().starts_with(&p.to_lowercase())),
+        }
+    }
+}
+
+// equals compares a field value with a scalar; Null matches missing values
+fn equals(value: Option<&SortValue>, expected: &SearchValue) -> bool {
+    match (value, expected.to_sort_value()) {
+        (None, Some(SortValue::Null)) => true,
+        (Some(v), Some(e)) => v.comparable_with(&e) && *v == e,
+        _ => false,
+    }
+}
+
+// compare orders a present field value against a non-null scalar of the same type class
+fn compare(value: Option<&SortValue>, expected: &SearchValue) -> Option<Ordering> {
+    let value = value?;
+    let expected = expected.to_sort_value().filter(|e| !e.is_null())?;
+    value
+        .comparable_with(&expected)
+        .then(|| value.cmp(&expected))
+}
+
+// contained_in checks membership in an array value; a scalar behaves like Eq
+fn contained_in(value: Option<&SortValue>, expected: &SearchValue) -> bool {
+    match expected {
+        SearchValue::Array(values) => values.iter().any(|e| equals(value, e)),
+        _ => equals(value, expected),
+    }
+}
+
+fn string_pair<'a>(value: Option<&'a SortValue>, expected: &'a SearchValue) -> Option<(&'a str, &'a str)> {
+    match (value, expected) {
+        (Some(SortValue::String(v)), SearchValue::String(p)) => Some((v, p)),
+        _ => None,
+    }
+}
+
+// regex_is_match caches compiled patterns per thread; invalid patterns never match
+fn regex_is_match(pattern: &str, value: &str) -> bool {
+    const REGEX_CACHE_SIZE: usize = 64;
+    thread_local! {
+        static REGEX_CACHE: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+    }
+
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if !cache.contains_key(pattern) {
+            if cache.len() >= REGEX_CACHE_SIZE {
+                cache.clear();
             }
+            let regex = Regex::new(pattern)
+                .inspect_err(|e| warn!("Invalid regex {}: {}", pattern, e))
+                .ok();
+            cache.insert(pattern.to_string(), regex);
         }
-        std::cmp::Ordering::Equal
+        cache[pattern].as_ref().is_some_and(|regex| regex.is_match(value))
+    })
+}
+
+
+pub fn apply_sort<M: Searchable>(items: Vec<M>, sort_fields: &[SortField]) -> Vec<M>{
+
+    // Read the sort keys once per item instead of on every comparison
+    let mut keyed: Vec<(Vec<Option<SortValue>>, M)> = items
+        .into_iter()
+        .map(|item| {
+            let keys = sort_fields
+                .iter()
+                .map(|sort_field| item.get_field_value(&sort_field.field))
+                .collect();
+            (keys, item)
+        })
+        .collect();
 
+    keyed.sort_by(|(keys_a, _), (keys_b, _)| {
+        for (i, sort_field) in sort_fields.iter().enumerate() {
+            let ordering = sort_field.compare(keys_a[i].as_ref(), keys_b[i].as_ref());
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            // Continue to next sort field if equal
+        }
+        Ordering::Equal
     });
 
-    items
-}
\ No newline at end of file
+    keyed.into_iter().map(|(_, item)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::core::{Searchable, SortValue};
+    use crate::fs::errors::SearchValidationError;
+    use crate::fs::search::{
+        NullsOrder, SearchCondition, SearchCriteria, SearchOp, SearchValue, apply_sort,
+    };
+
+    #[derive(Debug, Clone)]
+    struct Item {
+        id: i64,
+        score: Option<f64>,
+    }
+
+    impl Searchable for Item {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "id" => Some(SortValue::Int(self.id)),
+                "score" => self.score.map(SortValue::Float),
+                _ => None,
+            }
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        vec![
+            Item { id: 1, score: Some(2.5) },
+            Item { id: 2, score: None },
+            Item { id: 3, score: Some(-1.0) },
+            Item { id: 4, score: Some(2.5) },
+        ]
+    }
+
+    fn ids(items: &[Item]) -> Vec<i64> {
+        items.iter().map(|item| item.id).collect()
+    }
+
+    #[test]
+    fn test_apply_sort_nulls_last() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_sort("score", true);
+        criteria.add_sort("id", false);
+        let sorted = apply_sort(items(), criteria.sort_fields.as_ref().unwrap());
+        assert_eq!(ids(&sorted), vec![3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn test_apply_sort_nulls_first_descending() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_sort_with_nulls("score", false, NullsOrder::First);
+        criteria.add_sort("id", true);
+        let sorted = apply_sort(items(), criteria.sort_fields.as_ref().unwrap());
+        assert_eq!(ids(&sorted), vec![2, 1, 4, 3]);
+    }
+
+    // Record has a field of every value type; "none" is always missing
+    struct Record;
+
+    impl Searchable for Record {
+        fn get_field_value(&self, field: &str) -> Option<SortValue> {
+            match field {
+                "string" => Some(SortValue::String("Hello World".to_string())),
+                "decimal" => Some(SortValue::Decimal(Decimal::new(1050, 2))),
+                "int" => Some(SortValue::Int(10)),
+                "float" => Some(SortValue::Float(2.5)),
+                "bool" => Some(SortValue::Bool(true)),
+                "datetime" => Some(SortValue::DateTime(bson::DateTime::from_millis(1_000))),
+                "null" => Some(SortValue::Null),
+                _ => None,
+            }
+        }
+    }
+
+    fn check(field: &str, operator: SearchOp, value: SearchValue) -> bool {
+        let condition = SearchCondition { field: field.to_string(), operator, value };
+        condition.matches(Record.get_field_value(field).as_ref())
+    }
+
+    fn array(values: Vec<SearchValue>) -> SearchValue {
+        SearchValue::Array(values)
+    }
+
+    fn datetime(millis: i64) -> SearchValue {
+        SearchValue::DateTime(bson::DateTime::from_millis(millis))
+    }
+
+    #[test]
+    fn test_eq_and_ne() {
+        assert!(check("string", SearchOp::Eq, SearchValue::String("Hello World".into())));
+        assert!(check("decimal", SearchOp::Eq, SearchValue::Decimal(Decimal::new(105, 1))));
+        assert!(check("int", SearchOp::Eq, SearchValue::Int(10)));
+        assert!(check("int", SearchOp::Eq, SearchValue::Decimal(Decimal::new(10, 0))));
+        assert!(check("float", SearchOp::Eq, SearchValue::Float(2.5)));
+        assert!(check("bool", SearchOp::Eq, SearchValue::Bool(true)));
+        assert!(check("datetime", SearchOp::Eq, datetime(1_000)));
+        assert!(check("null", SearchOp::Eq, SearchValue::Null));
+        assert!(check("none", SearchOp::Eq, SearchValue::Null));
+        assert!(!check("int", SearchOp::Eq, SearchValue::String("10".into())));
+        assert!(!check("bool", SearchOp::Eq, SearchValue::Int(1)));
+        assert!(!check("none", SearchOp::Eq, SearchValue::Int(10)));
+        assert!(!check("int", SearchOp::Eq, array(vec![SearchValue::Int(10)])));
+
+        assert!(check("string", SearchOp::Ne, SearchValue::String("hello world".into())));
+        assert!(check("float", SearchOp::Ne, SearchValue::Float(2.6)));
+        assert!(check("bool", SearchOp::Ne, SearchValue::Bool(false)));
+        assert!(check("datetime", SearchOp::Ne, datetime(2_000)));
+        assert!(check("int", SearchOp::Ne, SearchValue::Null));
+        assert!(check("none", SearchOp::Ne, SearchValue::Int(10)));
+        assert!(!check("none", SearchOp::Ne, SearchValue::Null));
+        assert!(!check("decimal", SearchOp::Ne, SearchValue::Float(10.5)));
+    }
+
+    #[test]
+    fn test_ordering_operators() {
+        assert!(check("string", SearchOp::Gt, SearchValue::String("Hello".into())));
+        assert!(check("decimal", SearchOp::Gte, SearchValue::Decimal(Decimal::new(1050, 2))));
+        assert!(check("int", SearchOp::Lt, SearchValue::Float(10.1)));
+        assert!(check("int", SearchOp::Lte, SearchValue::Int(10)));
+        assert!(check("float", SearchOp::Gt, SearchValue::Decimal(Decimal::new(24, 1))));
+        assert!(check("bool", SearchOp::Gt, SearchValue::Bool(false)));
+        assert!(check("datetime", SearchOp::Lt, datetime(1_001)));
+        assert!(!check("int", SearchOp::Gt, SearchValue::Int(10)));
+        assert!(!check("int", SearchOp::Lt, SearchValue::String("z".into())));
+        assert!(!check("string", SearchOp::Gt, SearchValue::Int(0)));
+        assert!(!check("none", SearchOp::Lt, SearchValue::Int(10)));
+        assert!(!check("null", SearchOp::Gte, SearchValue::Null));
+        assert!(!check("int", SearchOp::Gte, array(vec![SearchValue::Int(1)])));
+    }
+
+    #[test]
+    fn test_between() {
+        let range = |lo, hi| array(vec![lo, hi]);
+        assert!(check("int", SearchOp::Between, range(SearchValue::Int(10), SearchValue::Int(20))));
+        assert!(check("decimal", SearchOp::Between, range(SearchValue::Int(10), SearchValue::Float(10.5))));
+        assert!(check("float", SearchOp::Between, range(SearchValue::Float(2.0), SearchValue::Float(3.0))));
+        assert!(check("string", SearchOp::Between, range(SearchValue::String("H".into()), SearchValue::String("I".into()))));
+        assert!(check("bool", SearchOp::Between, range(SearchValue::Bool(false), SearchValue::Bool(true))));
+        assert!(check("datetime", SearchOp::Between, range(datetime(0), datetime(1_000))));
+        assert!(!check("int", SearchOp::Between, range(SearchValue::Int(11), SearchValue::Int(20))));
+        assert!(!check("int", SearchOp::Between, range(SearchValue::Int(0), SearchValue::String("z".into()))));
+        assert!(!check("none", SearchOp::Between, range(SearchValue::Null, SearchValue::Int(20))));
+        assert!(!check("int", SearchOp::Between, SearchValue::Int(10)));
+        assert!(!check("int", SearchOp::Between, array(vec![SearchValue::Int(10)])));
+    }
+
+    #[test]
+    fn test_in_and_not_in() {
+        let values = array(vec![
+            SearchValue::String("Hello World".into()),
+            SearchValue::Int(10),
+            SearchValue::Bool(true),
+            datetime(1_000),
+            SearchValue::Null,
+        ]);
+        assert!(check("string", SearchOp::In, values.clone()));
+        assert!(check("int", SearchOp::In, values.clone()));
+        assert!(check("bool", SearchOp::In, values.clone()));
+        assert!(check("datetime", SearchOp::In, values.clone()));
+        assert!(check("none", SearchOp::In, values.clone()));
+        assert!(!check("float", SearchOp::In, values.clone()));
+        assert!(!check("decimal", SearchOp::In, values.clone()));
+        assert!(check("decimal", SearchOp::In, SearchValue::Decimal(Decimal::new(105, 1))));
+
+        assert!(check("float", SearchOp::NotIn, values.clone()));
+        assert!(check("decimal", SearchOp::NotIn, values.clone()));
+        assert!(!check("int", SearchOp::NotIn, values.clone()));
+        assert!(!check("none", SearchOp::NotIn, values));
+        assert!(check("none", SearchOp::NotIn, array(vec![SearchValue::Int(1)])));
+    }
+
+    #[test]
+    fn test_exists() {
+        for field in ["string", "decimal", "int", "float", "bool", "datetime"] {
+            assert!(check(field, SearchOp::Exists, SearchValue::Bool(true)));
+            assert!(!check(field, SearchOp::Exists, SearchValue::Bool(false)));
+        }
+        assert!(!check("null", SearchOp::Exists, SearchValue::Bool(true)));
+        assert!(!check("none", SearchOp::Exists, SearchValue::Null));
+        assert!(check("none", SearchOp::Exists, SearchValue::Bool(false)));
+    }
+
+    #[test]
+    fn test_string_operators() {
+        let text = |v: &str| SearchValue::String(v.to_string());
+        assert!(check("string", SearchOp::Contains, text("lo Wo")));
+        assert!(!check("string", SearchOp::Contains, text("lo wo")));
+        assert!(check("string", SearchOp::ContainsIgnoreCase, text("LO WO")));
+        assert!(check("string", SearchOp::StartsWith, text("Hello")));
+        assert!(!check("string", SearchOp::StartsWith, text("hello")));
+        assert!(check("string", SearchOp::StartsWithIgnoreCase, text("hELLO")));
+        assert!(check("string", SearchOp::EqIgnoreCase, text("hello world")));
+        assert!(!check("string", SearchOp::EqIgnoreCase, text("hello")));
+        assert!(check("string", SearchOp::Regex, text("^H.*d$")));
+        assert!(check("string", SearchOp::Regex, text("(?i)^hello")));
+        assert!(!check("string", SearchOp::Regex, text("^World")));
+        assert!(!check("string", SearchOp::Regex, text("(unclosed")));
+
+        // string operators never match other value types
+        for op in [
+            SearchOp::Contains,
+            SearchOp::ContainsIgnoreCase,
+            SearchOp::StartsWith,
+            SearchOp::StartsWithIgnoreCase,
+            SearchOp::EqIgnoreCase,
+            SearchOp::Regex,
+        ] {
+            assert!(!check("int", op, text("1")));
+            assert!(!check("decimal", op, text("1")));
+            assert!(!check("float", op, text("2")));
+            assert!(!check("bool", op, text("true")));
+            assert!(!check("datetime", op, text("1")));
+            assert!(!check("none", op, text("")));
+            assert!(!check("string", op, SearchValue::Int(1)));
+            assert!(!check("string", op, SearchValue::Null));
+        }
+    }
+
+    #[test]
+    fn test_criteria_matches_all_conditions() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("int", SearchOp::Gte, SearchValue::Int(5));
+        criteria.add_condition("string", SearchOp::StartsWith, SearchValue::String("Hello".into()));
+        assert!(Record.matches_filter(&criteria));
+
+        criteria.add_condition("bool", SearchOp::Eq, SearchValue::Bool(false));
+        assert!(!Record.matches_filter(&criteria));
+    }
+
+    #[test]
+    fn test_validate_rejects_non_finite_floats() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("float", SearchOp::Lt, SearchValue::Float(f64::MAX));
+        assert!(criteria.validate().is_ok());
+
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let mut criteria = SearchCriteria::new();
+            criteria.add_condition("float", SearchOp::In, SearchValue::Array(vec![SearchValue::Float(value)]));
+            let error = criteria.validate().unwrap_err();
+            assert!(matches!(error, SearchValidationError::InvalidValue { expected: "finite numbers", .. }));
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use std::{fmt, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::de::{self, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::fs::search::{SearchCondition, SearchOp, SearchValue};
+
+// JSON query format for SearchCriteria, modelled on MongoDB filters:
+//
+//   {
+//     "filter": {
+//       "status": "active",
+//       "balance": { "$gte": 100, "$lt": { "$numberDecimal": "2500.00" } },
+//       "created": { "$gt": { "$date": "2024-01-01T00:00:00Z" } },
+//       "$and": [ { "name": { "$ne": "a" } }, { "name": { "$ne": "b" } } ]
+//     },
+//     "sort": [ { "field": "name", "order": "desc", "nulls": "first" } ],
+//     "limit": 20
+//   }
+//
+// A plain field value means $eq. Operators are $eq, $ne, $gt, $gte, $lt, $lte,
+// $between, $in, $nin, $exists, $contains, $startsWith, $regex, $ieq, $icontains
+// and $istartsWith. Integers are Int and other numbers Float; Decimal and DateTime
+// values use the extended forms above. "$and" holds more filters, which allows
+// the same operator twice on one field. Conditions are validated on deserialize.
+
+const DECIMAL_KEY: &str = "$numberDecimal";
+const DATE_KEY: &str = "$date";
+const AND_KEY: &str = "$and";
+
+impl Serialize for SearchValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SearchValue::String(value) => serializer.serialize_str(value),
+            SearchValue::Decimal(value) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(DECIMAL_KEY, &value.to_string())?;
+                map.end()
+            }
+            SearchValue::Int(value) => serializer.serialize_i64(*value),
+            SearchValue::Float(value) => serializer.serialize_f64(*value),
+            SearchValue::Bool(value) => serializer.serialize_bool(*value),
+            SearchValue::DateTime(value) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                match value.try_to_rfc3339_string() {
+                    Ok(text) => map.serialize_entry(DATE_KEY, &text)?,
+                    Err(_) => map.serialize_entry(DATE_KEY, &value.timestamp_millis())?,
+                }
+                map.end()
+            }
+            SearchValue::Null => serializer.serialize_unit(),
+            SearchValue::Array(values) => serializer.collect_seq(values),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SearchValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.deserialize_any(ValueVisitor { operators: false })? {
+            FieldFilter::Value(value) => Ok(value),
+            FieldFilter::Operators(_) => Err(de::Error::custom("unexpected operators")),
+        }
+    }
+}
+
+// FieldFilter is the right hand side of a field in a filter: a plain value or an operator object
+enum FieldFilter {
+    Value(SearchValue),
+    Operators(Vec<(SearchOp, SearchValue)>),
+}
+
+impl<'de> Deserialize<'de> for FieldFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor { operators: true })
+    }
+}
+
+struct ValueVisitor {
+    operators: bool,
+}
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = FieldFilter;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operators {
+            write!(f, "a value or an object of operators")
+        } else {
+            write!(f, "a string, number, boolean, null, array, $numberDecimal or $date")
+        }
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::Bool(v)))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::Int(v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<FieldFilter, E> {
+        i64::try_from(v)
+            .map(|v| FieldFilter::Value(SearchValue::Int(v)))
+            .map_err(|_| E::custom(format!("integer {} is out of range", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::Float(v)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::String(v.to_string())))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::String(v)))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::Null))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<FieldFilter, E> {
+        Ok(FieldFilter::Value(SearchValue::Null))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<FieldFilter, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FieldFilter, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element::<SearchValue>()? {
+            values.push(value);
+        }
+        Ok(FieldFilter::Value(SearchValue::Array(values)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FieldFilter, A::Error> {
+        let Some(key) = map.next_key::<String>()? else {
+            return Err(de::Error::custom("empty object"));
+        };
+
+        let value = match key.as_str() {
+            DECIMAL_KEY => {
+                let text = map.next_value::<String>()?;
+                Decimal::from_str(&text)
+                    .map(SearchValue::Decimal)
+                    .map_err(|_| de::Error::custom(format!("invalid decimal {}", text)))?
+            }
+            DATE_KEY => match map.next_value::<DateValue>()? {
+                DateValue::Text(text) => bson::DateTime::parse_rfc3339_str(&text)
+                    .map(SearchValue::DateTime)
+                    .map_err(|_| de::Error::custom(format!("invalid date {}", text)))?,
+                DateValue::Millis(millis) => {
+                    SearchValue::DateTime(bson::DateTime::from_millis(millis))
+                }
+            },
+            _ if self.operators => {
+                let mut operators = Vec::new();
+                let mut key = Some(key);
+                while let Some(name) = key {
+                    let operator = SearchOp::deserialize(name.as_str().into_deserializer())
+                        .map_err(|_: de::value::Error| {
+                            de::Error::custom(format!("unknown operator {}", name))
+                        })?;
+                    operators.push((operator, map.next_value::<SearchValue>()?));
+                    key = map.next_key::<String>()?;
+                }
+                return Ok(FieldFilter::Operators(operators));
+            }
+            _ => {
+                return Err(de::Error::custom(format!(
+                    "unexpected key {}, expected {} or {}",
+                    key, DECIMAL_KEY, DATE_KEY
+                )));
+            }
+        };
+
+        if let Some(extra) = map.next_key::<String>()? {
+            return Err(de::Error::custom(format!("unexpected key {}", extra)));
+        }
+        Ok(FieldFilter::Value(value))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DateValue {
+    Text(String),
+    Millis(i64),
+}
+
+// filter serializes SearchCriteria conditions as a filter document
+pub(crate) mod filter {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        conditions: &[SearchCondition],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let repeated = conditions.iter().enumerate().any(|(i, a)| {
+            conditions[..i]
+                .iter()
+                .any(|b| a.field == b.field && a.operator == b.operator)
+        });
+
+        // The same operator twice on a field cannot share an object, so each condition gets its own
+        if repeated {
+            let mut map = serializer.serialize_map(Some(1))?;
+            let filters: Vec<FieldConditions> = conditions
+                .iter()
+                .map(|c| FieldConditions(&c.field, vec![c]))
+                .collect();
+            map.serialize_entry(AND_KEY, &filters)?;
+            return map.end();
+        }
+
+        let mut fields: Vec<(&str, Vec<&SearchCondition>)> = Vec::new();
+        for condition in conditions {
+            match fields.iter_mut().find(|(field, _)| *field == condition.field) {
+                Some((_, grouped)) => grouped.push(condition),
+                None => fields.push((&condition.field, vec![condition])),
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(fields.len()))?;
+        for (field, grouped) in &fields {
+            map.serialize_entry(field, &Operators(grouped))?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<SearchCondition>, D::Error> {
+        let Filter(conditions) = Filter::deserialize(deserializer)?;
+        for condition in &conditions {
+            condition.validate().map_err(de::Error::custom)?;
+        }
+        Ok(conditions)
+    }
+
+    // FieldConditions serializes as a filter with a single field
+    struct FieldConditions<'a>(&'a str, Vec<&'a SearchCondition>);
+
+    impl Serialize for FieldConditions<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry(self.0, &Operators(&self.1))?;
+            map.end()
+        }
+    }
+
+    // Operators serializes a single equality as the plain value
+    struct Operators<'a>(&'a [&'a SearchCondition]);
+
+    impl Serialize for Operators<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0 {
+                [condition] if condition.operator == SearchOp::Eq => {
+                    condition.value.serialize(serializer)
+                }
+                conditions => {
+                    let mut map = serializer.serialize_map(Some(conditions.len()))?;
+                    for condition in conditions {
+                        map.serialize_entry(&condition.operator, &condition.value)?;
+                    }
+                    map.end()
+                }
+            }
+        }
+    }
+
+    struct Filter(Vec<SearchCondition>);
+
+    impl<'de> Deserialize<'de> for Filter {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_map(FilterVisitor)
+        }
+    }
+
+    struct FilterVisitor;
+
+    impl<'de> Visitor<'de> for FilterVisitor {
+        type Value = Filter;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a filter object")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Filter, A::Error> {
+            let mut conditions = Vec::new();
+            while let Some(field) = map.next_key::<String>()? {
+                if field == AND_KEY {
+                    for Filter(nested) in map.next_value::<Vec<Filter>>()? {
+                        conditions.extend(nested);
+                    }
+                    continue;
+                }
+                if field.starts_with('$') {
+                    return Err(de::Error::custom(format!("unknown filter operator {}", field)));
+                }
+
+                match map.next_value::<FieldFilter>()? {
+                    FieldFilter::Value(value) => conditions.push(SearchCondition {
+                        field,
+                        operator: SearchOp::Eq,
+                        value,
+                    }),
+                    FieldFilter::Operators(operators) => {
+                        for (operator, value) in operators {
+                            conditions.push(SearchCondition {
+                                field: field.clone(),
+                                operator,
+                                value,
+                            });
+                        }
+                    }
+                }
+            }
+            Ok(Filter(conditions))
+        }
+    }
+}
+
+// sort_order serializes SortField::ascending as "asc" or "desc", and also reads 1 or -1
+pub(crate) mod sort_order {
+    use super::*;
+
+    pub fn ascending() -> bool {
+        true
+    }
+
+    pub fn serialize<S: Serializer>(ascending: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(if *ascending { "asc" } else { "desc" })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        match SortOrder::deserialize(deserializer)? {
+            SortOrder::Text(text) if text.eq_ignore_ascii_case("asc") => Ok(true),
+            SortOrder::Text(text) if text.eq_ignore_ascii_case("desc") => Ok(false),
+            SortOrder::Number(1) => Ok(true),
+            SortOrder::Number(-1) => Ok(false),
+            _ => Err(de::Error::custom("sort order must be asc, desc, 1 or -1")),
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SortOrder {
+        Text(String),
+        Number(i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use serde_json::json;
+
+    use crate::fs::search::{NullsOrder, SearchCriteria, SearchOp, SearchValue};
+
+    #[test]
+    fn test_deserialize_filter_document() {
+        // Parsed from text, since json! values do not keep the key order
+        let criteria: SearchCriteria = serde_json::from_str(
+            r#"{
+                "filter": {
+                    "status": "active",
+                    "balance": { "$gte": 100, "$lt": { "$numberDecimal": "2500.50" } },
+                    "created": { "$gt": { "$date": "2024-01-01T00:00:00Z" } },
+                    "tags": { "$in": ["a", 1, null] },
+                    "name": { "$regex": "^a", "$exists": true }
+                },
+                "sort": [ { "field": "name", "order": "desc", "nulls": "first" }, { "field": "id" } ],
+                "limit": 20
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(criteria.conditions.len(), 7);
+        assert_eq!(criteria.conditions[0].operator, SearchOp::Eq);
+        assert!(matches!(&criteria.conditions[0].value, SearchValue::String(s) if s == "active"));
+        assert!(matches!(criteria.conditions[1].value, SearchValue::Int(100)));
+        assert!(matches!(
+            criteria.conditions[2].value,
+            SearchValue::Decimal(d) if d == Decimal::new(250050, 2)
+        ));
+        assert!(matches!(
+            criteria.conditions[3].value,
+            SearchValue::DateTime(d) if d.timestamp_millis() == 1_704_067_200_000
+        ));
+        assert_eq!(criteria.conditions[4].operator, SearchOp::In);
+        assert_eq!(criteria.conditions[5].operator, SearchOp::Regex);
+        assert_eq!(criteria.conditions[6].operator, SearchOp::Exists);
+
+        let sort_fields = criteria.sort_fields.unwrap();
+        assert!(!sort_fields[0].ascending);
+        assert_eq!(sort_fields[0].nulls, NullsOrder::First);
+        assert!(sort_fields[1].ascending);
+        assert_eq!(sort_fields[1].nulls, NullsOrder::Last);
+        assert_eq!(criteria.limit, Some(20));
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("status", SearchOp::Eq, SearchValue::String("active".into()));
+        criteria.add_condition("balance", SearchOp::Gte, SearchValue::Decimal(Decimal::new(1000, 2)));
+        criteria.add_condition("balance", SearchOp::Lt, SearchValue::Float(99.5));
+        criteria.add_condition(
+            "score",
+            SearchOp::Between,
+            SearchValue::Array(vec![SearchValue::Int(1), SearchValue::Int(5)]),
+        );
+        criteria.add_condition("deleted", SearchOp::Exists, SearchValue::Bool(false));
+        criteria.add_condition(
+            "created",
+            SearchOp::Lte,
+            SearchValue::DateTime(bson::DateTime::from_millis(1_704_067_200_000)),
+        );
+        criteria.add_condition("name", SearchOp::StartsWithIgnoreCase, SearchValue::String("ab".into()));
+        criteria.add_sort_with_nulls("name", false, NullsOrder::First);
+        criteria.add_limit(5);
+
+        let json = serde_json::to_value(&criteria).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "filter": {
+                    "status": "active",
+                    "balance": { "$gte": { "$numberDecimal": "10.00" }, "$lt": 99.5 },
+                    "score": { "$between": [1, 5] },
+                    "deleted": { "$exists": false },
+                    "created": { "$lte": { "$date": "2024-01-01T00:00:00Z" } },
+                    "name": { "$istartsWith": "ab" }
+                },
+                "sort": [ { "field": "name", "order": "desc", "nulls": "first" } ],
+                "limit": 5
+            })
+        );
+
+        let text = serde_json::to_string(&criteria).unwrap();
+        let parsed: SearchCriteria = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed.to_string(), criteria.to_string());
+    }
+
+    #[test]
+    fn test_repeated_operator_uses_and() {
+        let mut criteria = SearchCriteria::new();
+        criteria.add_condition("name", SearchOp::Ne, SearchValue::String("a".into()));
+        criteria.add_condition("name", SearchOp::Ne, SearchValue::String("b".into()));
+
+        let json = serde_json::to_value(&criteria).unwrap();
+        assert_eq!(
+            json,
+            json!({ "filter": { "$and": [ { "name": { "$ne": "a" } }, { "name": { "$ne": "b" } } ] } })
+        );
+
+        let parsed: SearchCriteria = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.to_string(), criteria.to_string());
+    }
+
+    #[test]
+    fn test_empty_criteria() {
+        let criteria = SearchCriteria::new();
+        assert_eq!(serde_json::to_value(&criteria).unwrap(), json!({}));
+        let parsed: SearchCriteria = serde_json::from_str("{}").unwrap();
+        assert!(parsed.conditions.is_empty());
+        assert!(parsed.sort_fields.is_none());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let invalid = [
+            json!({ "filter": { "a": { "$between": [1] } } }),
+            json!({ "filter": { "a": { "$between": [1, null] } } }),
+            json!({ "filter": { "a": { "$in": 5 } } }),
+            json!({ "filter": { "a": { "$exists": "yes" } } }),
+            json!({ "filter": { "a": { "$gt": null } } }),
+            json!({ "filter": { "a": { "$contains": 5 } } }),
+            json!({ "filter": { "a": { "$regex": "(" } } }),
+            json!({ "filter": { "a": { "$unknown": 1 } } }),
+            json!({ "filter": { "a": {} } }),
+            json!({ "filter": { "a": { "$numberDecimal": "abc" } } }),
+            json!({ "filter": { "a": { "$date": "yesterday" } } }),
+            json!({ "filter": { "$or": [] } }),
+            json!({ "filter": { "a": 1 }, "unknown": true }),
+            json!({ "sort": [ { "field": "a", "order": "up" } ] }),
+            json!({ "limit": -1 }),
+        ];
+
+        for value in invalid {
+            assert!(
+                serde_json::from_value::<SearchCriteria>(value.clone()).is_err(),
+                "expected error for {}",
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::hash::Hash;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::core::SortValue;
+use crate::fs::index::FieldExtractor;
+
+// BM25 term frequency saturation and document length normalization
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+
+// TextIndexDefinition declares a full-text index over string fields of a model.
+// With stemming, common English suffixes are stripped from the tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextIndexDefinition {
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub stemming: bool,
+}
+
+impl TextIndexDefinition {
+    pub fn new(fields: &[&str]) -> Self {
+        Self {
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            stemming: false,
+        }
+    }
+
+    pub fn with_stemming(mut self) -> Self {
+        self.stemming = true;
+        self
+    }
+}
+
+// tokenize splits the text on non-alphanumeric characters and lowercases the tokens
+pub fn tokenize(text: &str, stemming: bool) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| {
+            let token = token.to_lowercase();
+            if stemming { stem(&token) } else { token }
+        })
+        .collect()
+}
+
+// stem is a light suffix stripper for plurals, -ing, -ed and -ly. It keeps at least
+// three characters of the stem so short words are left alone.
+pub fn stem(token: &str) -> String {
+    if !token.is_ascii() {
+        return token.to_string();
+    }
+
+    if let Some(stem) = token.strip_suffix("sses") {
+        return format!("{}ss", stem);
+    }
+    if let Some(stem) = token.strip_suffix("ies").filter(|stem| stem.len() >= 2) {
+        return format!("{}y", stem);
+    }
+    if let Some(stem) = token
+        .strip_suffix("es")
+        .filter(|stem| stem.len() >= 3 && (stem.ends_with(['x', 'z']) || stem.ends_with("ch") || stem.ends_with("sh")))
+    {
+        return stem.to_string();
+    }
+    for suffix in ["ing", "ed"] {
+        if let Some(stem) = token.strip_suffix(suffix).filter(|stem| stem.len() >= 3) {
+            return undouble(stem);
+        }
+    }
+    if let Some(stem) = token.strip_suffix("ly").filter(|stem| stem.len() >= 3) {
+        return stem.to_string();
+    }
+    if let Some(stem) = token.strip_suffix('s')
+        && stem.len() >= 3
+        && !stem.ends_with(['s', 'u', 'i'])
+    {
+        return stem.to_string();
+    }
+    token.to_string()
+}
+
+// undouble removes a doubled final consonant left by a stripped suffix, "runn" -> "run"
+fn undouble(stem: &str) -> String {
+    let bytes = stem.as_bytes();
+    let last = bytes[bytes.len() - 1];
+    if bytes[bytes.len() - 2] == last && !b"aeioulsz".contains(&last) {
+        return stem[..stem.len() - 1].to_string();
+    }
+    stem.to_string()
+}
+
+// TextSnapshot is the persisted form of a text index: the term frequencies of every
+// document and the log offset up to which records were applied
+#[derive(Serialize, Deserialize)]
+struct TextSnapshot<K> {
+    definition: TextIndexDefinition,
+    watermark: u64,
+    documents: Vec<(K, Vec<(String, u32)>)>,
+}
+
+// TextIndex is an inverted index from terms to the documents holding them, scored
+// with BM25. The watermark is the offset of the log up to which records are applied.
+#[derive(Debug)]
+pub struct TextIndex<K, M> {
+    definition: TextIndexDefinition,
+    extract: FieldExtractor<M>,
+    postings: HashMap<String, HashMap<K, u32>>,
+    documents: HashMap<K, Vec<(String, u32)>>,
+    total_length: u64,
+    watermark: u64,
+}
+
+impl<K, M> TextIndex<K, M>
+where
+    K: Eq + Hash + Clone + Display + Serialize + DeserializeOwned,
+{
+    pub fn new(definition: TextIndexDefinition, extract: FieldExtractor<M>) -> Self {
+        Self {
+            definition,
+            extract,
+            postings: HashMap::new(),
+            documents: HashMap::new(),
+            total_length: 0,
+            watermark: 0,
+        }
+    }
+
+    pub fn definition(&self) -> &TextIndexDefinition {
+        &self.definition
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    pub fn set_watermark(&mut self, watermark: u64) {
+        self.watermark = watermark;
+    }
+
+    // insert indexes the string values of the model's fields, replacing the previous version
+    pub fn insert(&mut self, id: K, model: &M) {
+        self.remove(&id);
+
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for field in &self.definition.fields {
+            if let Some(SortValue::String(text)) = (self.extract)(model, field) {
+                for token in tokenize(&text, self.definition.stemming) {
+                    *frequencies.entry(token).or_default() += 1;
+                }
+            }
+        }
+        self.add_document(id, frequencies.into_iter().collect());
+    }
+
+    fn add_document(&mut self, id: K, terms: Vec<(String, u32)>) {
+        for (term, frequency) in &terms {
+            self.total_length += *frequency as u64;
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(id.clone(), *frequency);
+        }
+        self.documents.insert(id, terms);
+    }
+
+    pub fn remove(&mut self, id: &K) {
+        let Some(terms) = self.documents.remove(id) else {
+            return;
+        };
+        for (term, frequency) in terms {
+            self.total_length -= frequency as u64;
+            if let Some(documents) = self.postings.get_mut(&term) {
+                documents.remove(id);
+                if documents.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.postings.clear();
+        self.documents.clear();
+        self.total_length = 0;
+        self.watermark = 0;
+    }
+
+    // search scores the documents holding any query term with BM25, restricted to the
+    // allowed ids when given. Results are ordered by descending score, then by position.
+    pub fn search<P: Ord>(
+        &self,
+        query: &str,
+        allowed: Option<&HashSet<K>>,
+        position: impl Fn(&K) -> P,
+    ) -> Vec<(K, f32)> {
+        let mut terms = tokenize(query, self.definition.stemming);
+        terms.sort();
+        terms.dedup();
+
+        let total = self.documents.len() as f32;
+        let average_length = self.total_length as f32 / total.max(1.0);
+        let mut scores: HashMap<&K, f32> = HashMap::new();
+        for term in &terms {
+            let Some(documents) = self.postings.get(term) else {
+                continue;
+            };
+            let frequency = documents.len() as f32;
+            let idf = (1.0 + (total - frequency + 0.5) / (frequency + 0.5)).ln();
+            for (id, tf) in documents {
+                if allowed.is_some_and(|allowed| !allowed.contains(id)) {
+                    continue;
+                }
+                let tf = *tf as f32;
+                let length = self.length(id) as f32;
+                let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * length / average_length);
+                *scores.entry(id).or_default() += idf * tf * (BM25_K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut results: Vec<(P, &K, f32)> = scores
+            .into_iter()
+            .map(|(id, score)| (position(id), id, score))
+            .collect();
+        results.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        results.into_iter().map(|(_, id, score)| (id.clone(), score)).collect()
+    }
+
+    fn length(&self, id: &K) -> u32 {
+        self.documents
+            .get(id)
+            .map_or(0, |terms| terms.iter().map(|(_, frequency)| frequency).sum())
+    }
+
+    // save writes the snapshot of the index to path
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let snapshot = TextSnapshot {
+            definition: self.definition.clone(),
+            watermark: self.watermark,
+            documents: self
+                .documents
+                .iter()
+                .map(|(id, terms)| (id.clone(), terms.clone()))
+                .collect(),
+        };
+        fs::write(path, serde_json::to_vec(&snapshot)?)?;
+        Ok(())
+    }
+
+    // load replaces the index with the snapshot at path. It returns false, leaving the
+    // index cleared, when there is no snapshot, it was built with another definition,
+    // or its watermark is past the end of the log.
+    pub fn load(&mut self, path: &Path, log_length: u64) -> Result<bool> {
+        self.clear();
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        let snapshot: TextSnapshot<K> = serde_json::from_slice(&fs::read(path)?)?;
+        if snapshot.definition != self.definition || snapshot.watermark > log_length {
+            return Ok(false);
+        }
+        for (id, terms) in snapshot.documents {
+            self.add_document(id, terms);
+        }
+        self.watermark = snapshot.watermark;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::SortValue;
+    use crate::fs::text::{TextIndex, TextIndexDefinition, stem, tokenize};
+
+    type Doc = (u32, &'static str, &'static str);
+
+    fn field_value(doc: &Doc, field: &str) -> Option<SortValue> {
+        match field {
+            "title" => Some(SortValue::String(doc.1.to_string())),
+            "body" => Some(SortValue::String(doc.2.to_string())),
+            _ => None,
+        }
+    }
+
+    fn index(definition: TextIndexDefinition) -> TextIndex<u32, Doc> {
+        let docs: [Doc; 4] = [
+            (1, "Rust storage", "An append-only log with an in-memory offset map"),
+            (2, "Vector search", "Cosine similarity over embeddings stored in the log"),
+            (3, "Running queries", "Queries run over indexes; the planner runs them fast"),
+            (4, "Cooking", "A recipe for bread"),
+        ];
+        let mut index = TextIndex::new(definition, field_value);
+        for doc in &docs {
+            index.insert(doc.0, doc);
+        }
+        index
+    }
+
+    #[test]
+    fn test_tokenize_and_stem() {
+        assert_eq!(
+            tokenize("Append-only LOG, v2!", false),
+            vec!["append", "only", "log", "v2"]
+        );
+        assert_eq!(stem("running"), "run");
+        assert_eq!(stem("queries"), "query");
+        assert_eq!(stem("indexes"), "index");
+        assert_eq!(stem("matches"), "match");
+        assert_eq!(stem("stored"), "stor");
+        assert_eq!(stem("quickly"), "quick");
+        assert_eq!(stem("class"), "class");
+        assert_eq!(stem("bus"), "bus");
+        assert_eq!(stem("is"), "is");
+    }
+
+    #[test]
+    fn test_bm25_ranking() {
+        let index = index(TextIndexDefinition::new(&["title", "body"]));
+
+        let results = index.search("log", None, |id| *id);
+        let ids: Vec<u32> = results.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids.len(), 2);
+        // the shorter document holding the term ranks first
+        assert_eq!(ids[0], 2);
+        assert!(results[0].1 > results[1].1);
+
+        // documents matching more query terms rank higher
+        let results = index.search("rust log", None, |id| *id);
+        assert_eq!(results[0].0, 1);
+
+        assert!(index.search("unknown", None, |id| *id).is_empty());
+        let allowed = [2].into_iter().collect();
+        assert_eq!(index.search("log", Some(&allowed), |id| *id).len(), 1);
+    }
+
+    #[test]
+    fn test_stemming_and_remove() {
+        let mut index = index(TextIndexDefinition::new(&["title", "body"]).with_stemming());
+        let results = index.search("run query", None, |id| *id);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 3);
+
+        index.remove(&3);
+        assert!(index.search("run query", None, |id| *id).is_empty());
+        assert_eq!(index.len(), 3);
+
+        // reinserting replaces the previous terms
+        index.insert(4, &(4, "Sourdough", "bread"));
+        assert!(index.search("recipe", None, |id| *id).is_empty());
+        assert_eq!(index.search("sourdough", None, |id| *id)[0].0, 4);
+    }
+
+    #[test]
+    fn test_save_and_load() -> anyhow::Result<()> {
+        let dir = std::path::Path::new("data/tests/text_snapshot");
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("docs.fts");
+
+        let definition = TextIndexDefinition::new(&["title", "body"]);
+        let mut saved = index(definition.clone());
+        saved.set_watermark(100);
+        saved.save(&path)?;
+
+        let mut loaded = TextIndex::<u32, Doc>::new(definition, field_value);
+        assert!(loaded.load(&path, 100)?);
+        assert_eq!(loaded.watermark(), 100);
+        assert_eq!(loaded.search("vector log", None, |id| *id), saved.search("vector log", None, |id| *id));
+
+        // a log shorter than the watermark or another definition discards the snapshot
+        assert!(!loaded.load(&path, 99)?);
+        assert!(loaded.is_empty());
+        let mut other = TextIndex::<u32, Doc>::new(TextIndexDefinition::new(&["title"]), field_value);
+        assert!(!other.load(&path, 100)?);
+        Ok(())
+    }
+}
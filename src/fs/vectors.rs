@@ -0,0 +1,518 @@
+use anyhow::Result;
+use crc32fast::Hasher;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::core::RepoKey;
+use crate::fs::errors::RecordHeaderError;
+use crate::fs::file::{FLAG_HAS_VECTOR, read_record};
+use crate::vector::index::VectorExtractor;
+use crate::vector::storage::{StoredValues, VectorBuffers, VectorStorage};
+
+// Entries of the vector file start at multiples of ENTRY_ALIGN bytes:
+//   dimension u24 | format u8 | crc32 of the values u32 | offset of the record in the log u64
+//   | values encoded in the format | zero padding
+// All little endian. The values of an entry start 16 bytes after it, so they are
+// 16 byte aligned in the file. Format 0 is f32, see VectorStorage::code; every entry has
+// its own, so changing the storage of a collection only applies to the vectors written after.
+pub(super) const ENTRY_HEADER_SIZE: u64 = 16;
+pub(super) const ENTRY_ALIGN: u64 = 16;
+const MAX_DIMENSION: usize = 0xff_ffff;
+
+// Stale bytes, of vectors replaced or removed, past which the file is compacted once they
+// also exceed the live ones
+const COMPACT_MIN_STALE: u64 = 64 * 1024;
+// Largest run of adjacent entries read at once
+const MAX_READ_RUN: u64 = 1 << 20;
+
+// VectorStore keeps the embeddings of a collection in a sidecar file next to the log, so
+// they can be scanned or read without deserializing the records. The file is append
+// only like the log; every write of a record appends its vector. Once the entries of
+// replaced and removed vectors take more space than the live ones, the live entries are
+// copied to a new file that replaces it, so the file stays within twice the live vectors.
+#[derive(Debug)]
+pub struct VectorStore<K, M> {
+    path: PathBuf,
+    file: File,
+    extract: VectorExtractor<M>,
+    // format of the vectors appended
+    storage: VectorStorage,
+    // current entry of each record
+    entries: HashMap<K, Entry>,
+    // entry of each record offset in the log; after sync only those of live records
+    by_record: HashMap<u64, Entry>,
+    // size of the entries in by_record and of the file
+    live: u64,
+    length: u64,
+}
+
+// Entry locates an entry of the vector file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    offset: u64,
+    size: u64,
+    record_offset: u64,
+}
+
+impl<K, M> VectorStore<K, M>
+where
+    K: RepoKey,
+    M: DeserializeOwned,
+{
+    // open reads the entries of the vector file at path, creating it if needed. A torn
+    // entry at the end, left by a crash while appending, is cut off.
+    pub fn open(path: &Path, extract: VectorExtractor<M>) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).create(true).append(true).open(path)?;
+        let length = file.seek(SeekFrom::End(0))?;
+        let mut found = Vec::new();
+        let mut offset = 0u64;
+        let mut reader = BufReader::new(&mut file);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0u8; ENTRY_HEADER_SIZE as usize];
+        while offset + ENTRY_HEADER_SIZE <= length {
+            reader.read_exact(&mut header)?;
+            let Some((dimension, storage, record_offset)) = entry_header(&header) else {
+                break;
+            };
+            let size = entry_size(dimension, storage);
+            if offset + size > length {
+                break;
+            }
+            reader.seek_relative((size - ENTRY_HEADER_SIZE) as i64)?;
+            found.push(Entry { offset, size, record_offset });
+            offset += size;
+        }
+        if offset < length {
+            warn!("Truncating torn vector entry at {} of {:?}", offset, path);
+            file.set_len(offset)?;
+        }
+
+        let mut store = Self {
+            path: path.to_path_buf(),
+            file,
+            extract,
+            storage: VectorStorage::default(),
+            entries: HashMap::new(),
+            by_record: HashMap::new(),
+            live: 0,
+            length: offset,
+        };
+        for entry in found {
+            store.attach(entry);
+        }
+        Ok(store)
+    }
+
+    // sync attaches the entries to the live records of the log, given by id with their
+    // offset, and appends the vectors missing from the file. Entries of other records are
+    // dropped at the next compaction. It returns how many vectors were appended.
+    pub fn sync(&mut self, log: &mut File, records: &HashMap<K, u64>) -> Result<usize> {
+        self.entries.clear();
+        let mut appended = 0;
+        for (id, record_offset) in records {
+            if let Some(entry) = self.by_record.get(record_offset) {
+                self.entries.insert(id.clone(), *entry);
+                continue;
+            }
+            let (header, model) = read_record::<M>(log, *record_offset)?;
+            if header.has_flag(FLAG_HAS_VECTOR) {
+                warn!("Vector of record at {} missing, restoring it", record_offset);
+            }
+            self.write_entry(id.clone(), &model, *record_offset)?;
+            appended += 1;
+        }
+        if appended > 0 {
+            debug!("Appended {} missing vectors", appended);
+        }
+
+        let live: HashSet<u64> = self.entries.values().map(|entry| entry.record_offset).collect();
+        self.by_record.retain(|record_offset, _| live.contains(record_offset));
+        self.live = self.by_record.values().map(|entry| entry.size).sum();
+        self.compact_if_stale()?;
+        Ok(appended)
+    }
+
+    pub fn storage(&self) -> VectorStorage {
+        self.storage
+    }
+
+    // set_storage sets the format of the vectors appended from now on
+    pub fn set_storage(&mut self, storage: VectorStorage) {
+        self.storage = storage;
+    }
+
+    // append writes the vector of model, stored at record_offset in the log, converted to
+    // the format of the store
+    pub fn append(&mut self, id: K, model: &M, record_offset: u64) -> Result<()> {
+        self.write_entry(id, model, record_offset)?;
+        self.compact_if_stale()
+    }
+
+    fn write_entry(&mut self, id: K, model: &M, record_offset: u64) -> Result<()> {
+        let vector = (self.extract)(model);
+        if vector.len() > MAX_DIMENSION {
+            return Err(anyhow::anyhow!(RecordHeaderError::VectorTooLong {
+                dimension: vector.len(),
+            }));
+        }
+        let values = self.storage.encode(vector);
+        let size = entry_size(vector.len(), self.storage);
+
+        let mut data = Vec::with_capacity(size as usize);
+        data.extend_from_slice(&(vector.len() as u32 | (self.storage.code() as u32) << 24).to_le_bytes());
+        data.extend_from_slice(&compute_crc32(&values).to_le_bytes());
+        data.extend_from_slice(&record_offset.to_le_bytes());
+        data.extend_from_slice(&values);
+        data.resize(size as usize, 0);
+
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&data)?;
+        self.length = offset + size;
+        let entry = Entry { offset, size, record_offset };
+        self.attach(entry);
+        if let Some(previous) = self.entries.insert(id, entry) {
+            self.detach(previous.record_offset);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &K) -> Result<()> {
+        if let Some(entry) = self.entries.remove(id) {
+            self.detach(entry.record_offset);
+        }
+        self.compact_if_stale()
+    }
+
+    fn attach(&mut self, entry: Entry) {
+        if let Some(previous) = self.by_record.insert(entry.record_offset, entry) {
+            self.live -= previous.size;
+        }
+        self.live += entry.size;
+    }
+
+    fn detach(&mut self, record_offset: u64) {
+        if let Some(entry) = self.by_record.remove(&record_offset) {
+            self.live -= entry.size;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // file_size is the size of the vector file, with the entries not compacted yet
+    pub fn file_size(&self) -> u64 {
+        self.length
+    }
+
+    // get reads the vector of id, widened to f32
+    pub fn get(&mut self, id: &K) -> Result<Option<Vec<f32>>> {
+        let Some(entry) = self.entries.get(id).copied() else {
+            return Ok(None);
+        };
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = vec![0u8; entry.size as usize];
+        self.file.read_exact(&mut data)?;
+
+        let mut buffers = VectorBuffers::default();
+        Ok(Some(decode_entry(&data, entry.offset, &mut buffers)?.to_f32()))
+    }
+
+    // scan passes the current vector of every record, widened to f32, to f
+    pub fn scan(&mut self, mut f: impl FnMut(&K, &[f32])) -> Result<()> {
+        let mut vector = Vec::new();
+        self.scan_where(
+            |_| true,
+            |id, _, stored| {
+                stored.widen(&mut vector);
+                f(id, &vector)
+            },
+        )
+    }
+
+    // scan_where passes the current vector of the records kept by keep to f with the offset
+    // of the record, in its stored format, in the order of the file. Only their entries are
+    // read, adjacent ones at once.
+    pub fn scan_where(
+        &mut self,
+        keep: impl Fn(&K) -> bool,
+        mut f: impl FnMut(&K, u64, StoredValues),
+    ) -> Result<()> {
+        let entries: Vec<(&K, Entry)> =
+            self.entries.iter().filter(|(id, _)| keep(id)).map(|(id, entry)| (id, *entry)).collect();
+        let mut buffers = VectorBuffers::default();
+        read_entries(&mut self.file, entries, |id, entry, data| {
+            f(id, entry.record_offset, decode_entry(data, entry.offset, &mut buffers)?);
+            Ok(())
+        })
+    }
+
+    // compact_if_stale compacts the file once the stale entries exceed the live ones
+    fn compact_if_stale(&mut self) -> Result<()> {
+        let stale = self.length - self.live;
+        if stale >= COMPACT_MIN_STALE && stale > self.live {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    // compact copies the entries of by_record to a new file, in their order, and replaces
+    // the vector file with it
+    pub fn compact(&mut self) -> Result<()> {
+        let temporary = self.path.with_extension("vec.compact");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        let entries: Vec<(u64, Entry)> =
+            self.by_record.iter().map(|(record_offset, entry)| (*record_offset, *entry)).collect();
+        let mut moved = HashMap::with_capacity(entries.len());
+        let mut length = 0;
+        read_entries(&mut self.file, entries, |record_offset, entry, data| {
+            writer.write_all(data)?;
+            moved.insert(record_offset, Entry { offset: length, ..*entry });
+            length += entry.size;
+            Ok(())
+        })?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+
+        debug!("Compacted {:?} from {} to {} bytes", self.path, self.length, length);
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        for entry in self.entries.values_mut() {
+            if let Some(compacted) = moved.get(&entry.record_offset) {
+                *entry = *compacted;
+            }
+        }
+        self.by_record = moved;
+        self.length = length;
+        Ok(())
+    }
+}
+
+// read_entries reads the entries from the file in the order of their offsets and passes
+// the bytes of each to f. Adjacent entries are read in runs of up to MAX_READ_RUN bytes.
+fn read_entries<T>(
+    file: &mut File,
+    mut entries: Vec<(T, Entry)>,
+    mut f: impl FnMut(T, &Entry, &[u8]) -> Result<()>,
+) -> Result<()> {
+    entries.sort_by_key(|(_, entry)| entry.offset);
+    let mut data = Vec::new();
+    let mut entries = entries.into_iter().peekable();
+    while let Some(first) = entries.next() {
+        let mut run = vec![first];
+        let mut end = run[0].1.offset + run[0].1.size;
+        while let Some((_, next)) = entries.peek() {
+            if next.offset != end || end + next.size - run[0].1.offset > MAX_READ_RUN {
+                break;
+            }
+            end += next.size;
+            run.extend(entries.next());
+        }
+
+        let start = run[0].1.offset;
+        data.resize((end - start) as usize, 0);
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut data)?;
+        for (key, entry) in run {
+            let position = (entry.offset - start) as usize;
+            f(key, &entry, &data[position..position + entry.size as usize])?;
+        }
+    }
+    Ok(())
+}
+
+// entry_size is the size of an entry with its padding
+fn entry_size(dimension: usize, storage: VectorStorage) -> u64 {
+    (ENTRY_HEADER_SIZE + storage.encoded_size(dimension) as u64).div_ceil(ENTRY_ALIGN) * ENTRY_ALIGN
+}
+
+// entry_header returns the dimension, format and record offset of the entry starting data,
+// None when it is cut off or of an unknown format
+fn entry_header(data: &[u8]) -> Option<(usize, VectorStorage, u64)> {
+    let header = data.get(..ENTRY_HEADER_SIZE as usize)?;
+    let dimension = u32::from_le_bytes(header[0..4].try_into().ok()?);
+    let storage = VectorStorage::from_code((dimension >> 24) as u8)?;
+    let record_offset = u64::from_le_bytes(header[8..16].try_into().ok()?);
+    Some(((dimension & MAX_DIMENSION as u32) as usize, storage, record_offset))
+}
+
+// decode_entry reads the values of the entry in data into the buffers, checking their crc;
+// offset is the position of the entry in the file, for errors
+fn decode_entry<'a>(data: &[u8], offset: u64, buffers: &'a mut VectorBuffers) -> Result<StoredValues<'a>> {
+    let truncated = || anyhow::anyhow!(RecordHeaderError::TruncatedEntry { offset });
+    let (dimension, storage, _) = entry_header(data).ok_or_else(truncated)?;
+    let start = ENTRY_HEADER_SIZE as usize;
+    let values = data.get(start..start + storage.encoded_size(dimension)).ok_or_else(truncated)?;
+
+    let expected = u32::from_le_bytes(data[4..8].try_into()?);
+    let actual = compute_crc32(values);
+    if actual != expected {
+        return Err(anyhow::anyhow!(RecordHeaderError::CorruptedData {
+            offset,
+            expected,
+            actual,
+        }));
+    }
+
+    Ok(buffers.decode(storage, values, dimension))
+}
+
+fn compute_crc32(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use serde::Deserialize;
+
+    use crate::fs::vectors::{COMPACT_MIN_STALE, ENTRY_ALIGN, VectorStore};
+    use crate::vector::storage::{StoredValues, VectorStorage};
+
+    #[derive(Deserialize)]
+    struct Embedding {
+        vector: Vec<f32>,
+    }
+
+    fn vector(model: &Embedding) -> &[f32] {
+        &model.vector
+    }
+
+    fn embedding(vector: &[f32]) -> Embedding {
+        Embedding {
+            vector: vector.to_vec(),
+        }
+    }
+
+    fn test_path(name: &str) -> Result<PathBuf> {
+        let dir = PathBuf::from("data/tests").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join(format!("{}.vec", name)))
+    }
+
+    #[test]
+    fn test_append_get_and_scan() -> Result<()> {
+        let path = test_path("vector_store")?;
+        let mut store = VectorStore::<String, Embedding>::open(&path, vector)?;
+        store.append("a".into(), &embedding(&[1.0, 2.0, 3.0]), 0)?;
+        store.append("b".into(), &embedding(&[4.0; 5]), 100)?;
+        store.append("a".into(), &embedding(&[-1.0, 0.5, 0.0]), 200)?;
+        store.remove(&"b".to_string())?;
+
+        // entries are padded to the alignment
+        assert_eq!(fs::metadata(&path)?.len() % ENTRY_ALIGN, 0);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&"a".to_string())?, Some(vec![-1.0, 0.5, 0.0]));
+        assert_eq!(store.get(&"b".to_string())?, None);
+
+        let mut scanned = Vec::new();
+        store.scan(|id, vector| scanned.push((id.clone(), vector.to_vec())))?;
+        assert_eq!(scanned, vec![("a".to_string(), vec![-1.0, 0.5, 0.0])]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_attaches_entries_and_cuts_torn_tail() -> Result<()> {
+        let path = test_path("vector_store_reopen")?;
+        let mut store = VectorStore::<String, Embedding>::open(&path, vector)?;
+        store.append("a".into(), &embedding(&[1.0, 2.0]), 0)?;
+        store.append("b".into(), &embedding(&[3.0, 4.0]), 100)?;
+        store.append("a".into(), &embedding(&[5.0, 6.0]), 200)?;
+        let length = fs::metadata(&path)?.len();
+        drop(store);
+
+        // a crash in the middle of an append
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        std::io::Write::write_all(&mut file, &[2, 0, 0, 0, 1, 2])?;
+        drop(file);
+
+        let mut store = VectorStore::<String, Embedding>::open(&path, vector)?;
+        assert_eq!(fs::metadata(&path)?.len(), length);
+        let records: HashMap<String, u64> = [("a".to_string(), 200), ("b".to_string(), 100)].into_iter().collect();
+        let mut log = tempfile_log("vector_store_reopen_log")?;
+        assert_eq!(store.sync(&mut log, &records)?, 0);
+        assert_eq!(store.get(&"a".to_string())?, Some(vec![5.0, 6.0]));
+        assert_eq!(store.get(&"b".to_string())?, Some(vec![3.0, 4.0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_bounds_file_growth() -> Result<()> {
+        let path = test_path("vector_store_compaction")?;
+        let mut store = VectorStore::<String, Embedding>::open(&path, vector)?;
+        // 64 f32 values take 272 bytes with the header
+        let live = 2 * 272;
+        let mut record_offset = 0;
+        for i in 0..1000 {
+            for id in ["a", "b"] {
+                store.append(id.into(), &embedding(&[i as f32; 64]), record_offset)?;
+                record_offset += 100;
+            }
+            assert!(store.file_size() <= 2 * live + COMPACT_MIN_STALE + 272, "{} bytes", store.file_size());
+            assert_eq!(fs::metadata(&path)?.len(), store.file_size());
+        }
+        store.append("c".into(), &embedding(&[7.0; 64]), record_offset)?;
+        store.remove(&"c".to_string())?;
+        store.compact()?;
+        assert_eq!(fs::metadata(&path)?.len(), live);
+        assert_eq!(store.get(&"a".to_string())?, Some(vec![999.0; 64]));
+
+        // compacted entries keep their record offsets
+        drop(store);
+        let mut store = VectorStore::<String, Embedding>::open(&path, vector)?;
+        let records: HashMap<String, u64> =
+            [("a".to_string(), 199_800), ("b".to_string(), 199_900)].into_iter().collect();
+        assert_eq!(store.sync(&mut tempfile_log("vector_store_compaction_log")?, &records)?, 0);
+        let mut scanned = Vec::new();
+        store.scan(|id, vector| scanned.push((id.clone(), vector[0])))?;
+        scanned.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(scanned, vec![("a".to_string(), 999.0), ("b".to_string(), 999.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_formats() -> Result<()> {
+        let path = test_path("vector_store_formats")?;
+        let mut store = VectorStore::<String, Embedding>::open(&path, vector)?;
+        let values = [0.5, -1.25, 3.0, 0.0];
+        store.append("a".into(), &embedding(&values), 0)?;
+        store.set_storage(VectorStorage::F16);
+        store.append("b".into(), &embedding(&values), 100)?;
+        store.set_storage(VectorStorage::Binary);
+        store.append("c".into(), &embedding(&values), 200)?;
+        // 16 byte headers with 16, 8 and 8 bytes of values, padded
+        assert_eq!(fs::metadata(&path)?.len(), 32 + 32 + 32);
+
+        assert_eq!(store.get(&"a".to_string())?, Some(values.to_vec()));
+        assert_eq!(store.get(&"b".to_string())?, Some(values.to_vec()));
+        assert_eq!(store.get(&"c".to_string())?, Some(vec![1.0, -1.0, 1.0, -1.0]));
+        let mut formats = Vec::new();
+        store.scan_where(
+            |id| id != "a",
+            |id, _, stored| formats.push((id.clone(), matches!(stored, StoredValues::Binary { dimension: 4, .. }))),
+        )?;
+        formats.sort();
+        assert_eq!(formats, vec![("b".to_string(), false), ("c".to_string(), true)]);
+        Ok(())
+    }
+
+    // tempfile_log opens an empty log per test, so that tests running in parallel do not share it
+    fn tempfile_log(name: &str) -> Result<std::fs::File> {
+        let path = test_path(name)?;
+        Ok(OpenOptions::new().read(true).create(true).append(true).open(path)?)
+    }
+}
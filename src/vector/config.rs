@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fs::errors::VectorValidationError;
+use crate::vector::metric::DistanceMetric;
+use crate::vector::storage::VectorStorage;
+
+// VectorConfig declares the embeddings of a collection: the number of values of every
+// vector, the metric they are compared with and their format in the vector store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorConfig {
+    pub dimension: usize,
+    #[serde(default)]
+    pub metric: DistanceMetric,
+    #[serde(default, skip_serializing_if = "VectorStorage::is_f32")]
+    pub storage: VectorStorage,
+}
+
+impl VectorConfig {
+    pub fn new(dimension: usize, metric: DistanceMetric) -> Self {
+        Self {
+            dimension,
+            metric,
+            storage: VectorStorage::default(),
+        }
+    }
+
+    pub fn with_storage(mut self, storage: VectorStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    // check_vector validates the vector of a record of the collection
+    pub fn check_vector(&self, collection: &str, vector: &[f32]) -> Result<(), VectorValidationError> {
+        if vector.len() != self.dimension {
+            return Err(VectorValidationError::DimensionMismatch {
+                collection: collection.to_string(),
+                expected: self.dimension,
+                actual: vector.len(),
+            });
+        }
+        match non_finite(vector) {
+            Some((position, value)) => Err(VectorValidationError::NonFiniteValue {
+                collection: collection.to_string(),
+                position,
+                value,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // check_query validates a query vector for the collection
+    pub fn check_query(&self, collection: &str, query: &[f32]) -> Result<(), VectorValidationError> {
+        if query.len() != self.dimension {
+            return Err(VectorValidationError::QueryDimensionMismatch {
+                collection: collection.to_string(),
+                expected: self.dimension,
+                actual: query.len(),
+            });
+        }
+        match non_finite(query) {
+            Some((position, value)) => Err(VectorValidationError::NonFiniteQuery {
+                collection: collection.to_string(),
+                position,
+                value,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+// non_finite returns the first NaN or infinite value with its position
+fn non_finite(vector: &[f32]) -> Option<(usize, f32)> {
+    vector
+        .iter()
+        .copied()
+        .enumerate()
+        .find(|(_, value)| !value.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::errors::VectorValidationError;
+    use crate::vector::config::VectorConfig;
+    use crate::vector::metric::DistanceMetric;
+    use crate::vector::storage::VectorStorage;
+
+    #[test]
+    fn test_check_vector_and_query() {
+        let config = VectorConfig::new(3, DistanceMetric::Cosine);
+        assert!(config.check_vector("notes", &[1.0, 0.0, -2.5]).is_ok());
+        assert_eq!(
+            config.check_vector("notes", &[1.0, 0.0]),
+            Err(VectorValidationError::DimensionMismatch {
+                collection: "notes".to_string(),
+                expected: 3,
+                actual: 2,
+            })
+        );
+        assert!(matches!(
+            config.check_vector("notes", &[1.0, f32::INFINITY, 0.0]),
+            Err(VectorValidationError::NonFiniteValue { position: 1, .. })
+        ));
+        assert!(matches!(
+            config.check_vector("notes", &[f32::NAN, 0.0, 0.0]),
+            Err(VectorValidationError::NonFiniteValue { position: 0, .. })
+        ));
+
+        assert!(config.check_query("notes", &[0.0, 0.0, 1.0]).is_ok());
+        assert!(matches!(
+            config.check_query("notes", &[0.0; 4]),
+            Err(VectorValidationError::QueryDimensionMismatch { expected: 3, actual: 4, .. })
+        ));
+        assert!(matches!(
+            config.check_query("notes", &[0.0, f32::NEG_INFINITY, 1.0]),
+            Err(VectorValidationError::NonFiniteQuery { position: 1, .. })
+        ));
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(json, r#"{"dimension":3,"metric":"cosine"}"#);
+        let declared: VectorConfig = serde_json::from_str(r#"{"dimension":8}"#).unwrap();
+        assert_eq!(declared, VectorConfig::new(8, DistanceMetric::Cosine));
+        let compact = VectorConfig::new(8, DistanceMetric::Hamming).with_storage(VectorStorage::Binary);
+        let json = serde_json::to_string(&compact).unwrap();
+        assert_eq!(json, r#"{"dimension":8,"metric":"hamming","storage":"binary"}"#);
+        assert_eq!(serde_json::from_str::<VectorConfig>(&json).unwrap(), compact);
+    }
+}
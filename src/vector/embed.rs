@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+
+use crate::fs::errors::VectorValidationError;
+use crate::vector::similarity::normalize;
+
+// Embedder turns text into embeddings, for the records of a collection and for query
+// texts. Implementations wrap a model or a remote embedding service.
+pub trait Embedder: Send + Sync + Debug {
+    // dimension is the length of the embeddings
+    fn dimension(&self) -> usize;
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    // embed_batch embeds several texts; services with batch endpoints override it
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        texts.iter().map(|text| self.embed(text)).collect()
+    }
+}
+
+// HashingEmbedder is a deterministic local embedder: every lowercased word is hashed to a
+// position and a sign (feature hashing), and the counts are normalized. Texts sharing
+// words are similar, which is enough for tests and examples, but it knows no synonyms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashingEmbedder {
+    dimension: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            dimension: dimension.max(1),
+        }
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let mut vector = vec![0.0; self.dimension];
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+            let hash = content_hash(&word.to_lowercase());
+            let position = (hash % self.dimension as u64) as usize;
+            vector[position] += if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        }
+        Ok(normalize(&vector))
+    }
+}
+
+// TextExtractor reads the text to embed of a model, normally Embeddable::embedding_text
+pub type TextExtractor<M> = fn(&M) -> String;
+
+// VectorSetter stores the embedding of a model, normally Embeddable::set_vector
+pub type VectorSetter<M> = fn(&mut M, Vec<f32>);
+
+// ModelEmbedder is the embedder of a collection with the accessors of the text and vector
+// of its models
+pub type ModelEmbedder<M> = (Arc<dyn Embedder>, TextExtractor<M>, VectorSetter<M>);
+
+// content_hash is the 64 bit FNV-1a hash of the text, stable across runs and platforms
+pub fn content_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// DEFAULT_CACHE_SIZE is the number of embeddings a CachedEmbedder keeps by default
+const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+// CachedEmbedder remembers the embeddings of the texts it embedded by their content
+// hash, so rewriting a record with unchanged text, or repeating a query, does not call
+// the embedder again. The cache is cleared when it is full.
+#[derive(Debug)]
+pub struct CachedEmbedder<E> {
+    inner: E,
+    capacity: usize,
+    cache: Mutex<HashMap<u64, Vec<f32>>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl<E: Embedder> CachedEmbedder<E> {
+    pub fn new(inner: E) -> Self {
+        Self::with_capacity(inner, DEFAULT_CACHE_SIZE)
+    }
+
+    pub fn with_capacity(inner: E, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity: capacity.max(1),
+            cache: Mutex::new(HashMap::new()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    // hits is the number of embeddings served from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    // misses is the number of texts passed to the inner embedder
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lock().map_or(0, |cache| cache.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn cached(&self, hash: u64) -> Option<Vec<f32>> {
+        let vector = self.cache.lock().ok()?.get(&hash).cloned();
+        if vector.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        vector
+    }
+
+    fn remember(&self, hash: u64, vector: &[f32]) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut cache) = self.cache.lock() {
+            if cache.len() >= self.capacity {
+                cache.clear();
+            }
+            cache.insert(hash, vector.to_vec());
+        }
+    }
+}
+
+impl<E: Embedder> Embedder for CachedEmbedder<E> {
+    fn dimension(&self) -> usize {
+        self.inner.dimension()
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let hash = content_hash(text);
+        if let Some(vector) = self.cached(hash) {
+            return Ok(vector);
+        }
+        let vector = self.inner.embed(text)?;
+        self.remember(hash, &vector);
+        Ok(vector)
+    }
+
+    // embed_batch passes only the texts missing from the cache to the inner embedder, which
+    // has to return one embedding per text
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let hashes: Vec<u64> = texts.iter().map(|text| content_hash(text)).collect();
+        let mut vectors: Vec<Option<Vec<f32>>> = hashes.iter().map(|hash| self.cached(*hash)).collect();
+        let missing: Vec<usize> = (0..texts.len()).filter(|i| vectors[*i].is_none()).collect();
+        if !missing.is_empty() {
+            let missing_texts: Vec<&str> = missing.iter().map(|i| texts[*i]).collect();
+            let embedded = self.inner.embed_batch(&missing_texts)?;
+            if embedded.len() != missing.len() {
+                return Err(anyhow::anyhow!(VectorValidationError::EmbeddingCountMismatch {
+                    expected: missing.len(),
+                    actual: embedded.len(),
+                }));
+            }
+            for (i, vector) in missing.into_iter().zip(embedded) {
+                self.remember(hashes[i], &vector);
+                vectors[i] = Some(vector);
+            }
+        }
+        Ok(vectors.into_iter().map(Option::unwrap_or_default).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::errors::VectorValidationError;
+    use crate::vector::embed::{CachedEmbedder, Embedder, HashingEmbedder, content_hash};
+    use crate::vector::similarity::cosine_similarity;
+
+    // TruncatingEmbedder drops the last embedding of every batch
+    #[derive(Debug)]
+    struct TruncatingEmbedder(HashingEmbedder);
+
+    impl Embedder for TruncatingEmbedder {
+        fn dimension(&self) -> usize {
+            self.0.dimension()
+        }
+
+        fn embed(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+            self.0.embed(text)
+        }
+
+        fn embed_batch(&self, texts: &[&str]) -> anyhow::Result<Vec<Vec<f32>>> {
+            let mut vectors = self.0.embed_batch(texts)?;
+            vectors.pop();
+            Ok(vectors)
+        }
+    }
+
+    #[test]
+    fn test_hashing_embedder() -> anyhow::Result<()> {
+        let embedder = HashingEmbedder::new(64);
+        let storage = embedder.embed("Rust storage engines")?;
+        assert_eq!(storage.len(), 64);
+        assert!((storage.iter().map(|v| v * v).sum::<f32>() - 1.0).abs() < 1e-5);
+        // deterministic, and insensitive to case and punctuation
+        assert_eq!(storage, HashingEmbedder::new(64).embed("rust, STORAGE engines!")?);
+
+        let related = embedder.embed("a storage engine in rust")?;
+        let unrelated = embedder.embed("baking sourdough bread")?;
+        assert!(cosine_similarity(&storage, &related) > cosine_similarity(&storage, &unrelated));
+        assert!(embedder.embed("")?.iter().all(|v| *v == 0.0));
+
+        assert_eq!(content_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8c);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cached_embedder() -> anyhow::Result<()> {
+        let embedder = CachedEmbedder::with_capacity(HashingEmbedder::new(16), 3);
+        let first = embedder.embed("one")?;
+        assert_eq!(embedder.embed("one")?, first);
+        assert_eq!((embedder.hits(), embedder.misses()), (1, 1));
+
+        let vectors = embedder.embed_batch(&["one", "two", "three"])?;
+        assert_eq!(vectors[0], first);
+        assert_eq!(vectors[2], embedder.inner().embed("three")?);
+        assert_eq!((embedder.hits(), embedder.misses()), (2, 3));
+        assert_eq!(embedder.len(), 3);
+
+        // a full cache starts over
+        embedder.embed("four")?;
+        assert_eq!(embedder.len(), 1);
+
+        // an inner embedder returning fewer embeddings than texts is an error, not empty vectors
+        let embedder = CachedEmbedder::new(TruncatingEmbedder(HashingEmbedder::new(16)));
+        embedder.embed("one")?;
+        let err = embedder.embed_batch(&["one", "two", "three"]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<VectorValidationError>(),
+            Some(&VectorValidationError::EmbeddingCountMismatch { expected: 2, actual: 1 })
+        );
+        Ok(())
+    }
+}
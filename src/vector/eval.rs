@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::core::RepoKey;
+use crate::vector::index::VectorIndexDefinition;
+use crate::vector::metric::DistanceMetric;
+use crate::vector::search::{select_top_k, vector_search};
+use crate::vector::similarity::cosine_similarity;
+
+// recall_at_k is the fraction of the exact top k ids found among the first k approximate
+// ones; 1 when there is nothing to find
+pub fn recall_at_k<K: Eq + Hash>(exact: &[(K, f32)], approximate: &[(K, f32)], k: usize) -> f32 {
+    let expected: HashSet<&K> = exact.iter().take(k).map(|(id, _)| id).collect();
+    if expected.is_empty() {
+        return 1.0;
+    }
+    let found = approximate.iter().take(k).filter(|(id, _)| expected.contains(id)).count();
+    found as f32 / expected.len() as f32
+}
+
+// uniform_vectors returns count vectors of the dimension with values in [-1, 1), drawn from the seed
+pub fn uniform_vectors(count: usize, dimension: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut random = xorshift(seed);
+    (0..count).map(|_| (0..dimension).map(|_| random()).collect()).collect()
+}
+
+// clustered_vectors returns count vectors spread around the given number of centers. The
+// centers are the same for every seed, so queries fall near the vectors like real embeddings.
+pub fn clustered_vectors(count: usize, dimension: usize, centers: usize, seed: u64) -> Vec<Vec<f32>> {
+    let centers = uniform_vectors(centers.max(1), dimension, 7);
+    let mut random = xorshift(seed);
+    (0..count)
+        .map(|i| centers[i % centers.len()].iter().map(|value| value + random()).collect())
+        .collect()
+}
+
+// xorshift draws values in [-1, 1) with xorshift64
+fn xorshift(seed: u64) -> impl FnMut() -> f32 {
+    let mut state = seed.max(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+// LatencyStats summarizes the durations of a set of queries; percentiles are nearest rank
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Self {
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+impl Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mean {:?}, p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+            self.mean, self.p50, self.p95, self.p99, self.max
+        )
+    }
+}
+
+// EvalReport compares a vector index with the exact search over the same vectors
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    pub definition: VectorIndexDefinition,
+    pub vectors: usize,
+    pub queries: usize,
+    pub top_k: usize,
+    // mean and lowest recall@k over the queries
+    pub recall: f32,
+    pub min_recall: f32,
+    pub build_time: Duration,
+    pub exact_latency: LatencyStats,
+    pub index_latency: LatencyStats,
+    // size in bytes of the f32 vectors, as scanned by the exact search
+    pub vector_memory: usize,
+    // memory_usage of the index
+    pub index_memory: usize,
+}
+
+impl Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:?}", self.definition)?;
+        writeln!(
+            f,
+            "  {} vectors, {} queries, recall@{} {:.4} (min {:.4})",
+            self.vectors, self.queries, self.top_k, self.recall, self.min_recall
+        )?;
+        writeln!(f, "  build {:?}", self.build_time)?;
+        writeln!(f, "  exact {}", self.exact_latency)?;
+        writeln!(f, "  index {}", self.index_latency)?;
+        write!(
+            f,
+            "  memory {} KiB index, {} KiB vectors",
+            self.index_memory / 1024,
+            self.vector_memory / 1024
+        )
+    }
+}
+
+// evaluate_index builds the index over the candidates and answers every query with it and with
+// vector_search, the exact cosine search. Index hits are re-ranked with the full vectors, as
+// repository searches do, within the index latency.
+pub fn evaluate_index<K: RepoKey>(
+    definition: &VectorIndexDefinition,
+    candidates: &[(K, Vec<f32>)],
+    queries: &[Vec<f32>],
+    top_k: usize,
+) -> EvalReport {
+    let start = Instant::now();
+    let mut index = definition.build::<K>();
+    index.rebuild(candidates.to_vec());
+    let build_time = start.elapsed();
+
+    let vectors: HashMap<&K, &[f32]> = candidates.iter().map(|(id, vector)| (id, vector.as_slice())).collect();
+    let mut exact_samples = Vec::with_capacity(queries.len());
+    let mut index_samples = Vec::with_capacity(queries.len());
+    let mut recalls = Vec::with_capacity(queries.len());
+    for query in queries {
+        let start = Instant::now();
+        let exact = vector_search(query, candidates, top_k);
+        exact_samples.push(start.elapsed());
+
+        let start = Instant::now();
+        let hits = index.search(query, index.rerank_depth(top_k), None);
+        let rescored = hits
+            .into_iter()
+            .filter_map(|(id, _)| vectors.get(&id).map(|vector| (id.clone(), cosine_similarity(query, vector))));
+        let approximate = select_top_k(rescored, top_k, DistanceMetric::Cosine, None);
+        index_samples.push(start.elapsed());
+
+        recalls.push(recall_at_k(&exact, &approximate, top_k));
+    }
+
+    EvalReport {
+        definition: definition.clone(),
+        vectors: candidates.len(),
+        queries: queries.len(),
+        top_k,
+        recall: if recalls.is_empty() { 1.0 } else { recalls.iter().sum::<f32>() / recalls.len() as f32 },
+        min_recall: recalls.iter().copied().fold(1.0, f32::min),
+        build_time,
+        exact_latency: LatencyStats::from_samples(&exact_samples),
+        index_latency: LatencyStats::from_samples(&index_samples),
+        vector_memory: candidates.iter().map(|(_, vector)| size_of_val(vector.as_slice())).sum(),
+        index_memory: index.memory_usage(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::vector::eval::{LatencyStats, evaluate_index, recall_at_k};
+    use crate::vector::hnsw::HnswParams;
+    use crate::vector::index::VectorIndexDefinition;
+    use crate::vector::ivf::IvfParams;
+
+    #[test]
+    fn test_recall_and_latency_stats() {
+        let exact = [("a", 1.0), ("b", 0.9), ("c", 0.8)];
+        assert_eq!(recall_at_k(&exact, &[("b", 0.9), ("a", 1.0), ("d", 0.7)], 3), 2.0 / 3.0);
+        assert_eq!(recall_at_k(&exact, &[("a", 1.0), ("d", 0.95), ("b", 0.9)], 2), 0.5);
+        assert_eq!(recall_at_k::<&str>(&[], &[], 3), 1.0);
+
+        let samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let stats = LatencyStats::from_samples(&samples);
+        assert_eq!(stats.p50, Duration::from_millis(50));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+        assert_eq!(LatencyStats::from_samples(&[]), LatencyStats::default());
+    }
+
+    #[test]
+    fn test_evaluate_index() {
+        let candidates: Vec<(usize, Vec<f32>)> = (0..300)
+            .map(|i| {
+                let angle = i as f32 * 0.37;
+                (i, vec![angle.cos(), angle.sin(), (i % 7) as f32 * 0.1])
+            })
+            .collect();
+        let queries: Vec<Vec<f32>> = (0..20).map(|i| vec![1.0, i as f32 * 0.1 - 1.0, 0.2]).collect();
+
+        // scanning every list is exact
+        let exhaustive = VectorIndexDefinition::IvfFlat(IvfParams::new(4, 4));
+        let report = evaluate_index(&exhaustive, &candidates, &queries, 5);
+        assert_eq!((report.vectors, report.queries, report.top_k), (300, 20, 5));
+        assert_eq!((report.recall, report.min_recall), (1.0, 1.0));
+        assert_eq!(report.vector_memory, 300 * 3 * 4);
+        assert!(report.index_memory > 0);
+
+        let report = evaluate_index(&VectorIndexDefinition::Hnsw(HnswParams::default()), &candidates, &queries, 5);
+        assert!(report.recall > 0.9, "hnsw recall {}", report.recall);
+        assert!(report.min_recall <= report.recall);
+    }
+}
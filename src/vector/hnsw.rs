@@ -0,0 +1,469 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::vector::index::{VectorIndex, next_random, save_snapshot};
+use crate::vector::similarity::{dot_product, normalize};
+
+// Highest layer a node can be placed on
+const MAX_LEVEL: usize = 16;
+
+// HnswParams tunes the graph. Larger m and ef_construction build a denser graph with
+// better recall at a higher insert cost; ef_search trades query time for recall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HnswParams {
+    // Neighbors per node on the upper layers, twice as many on layer 0
+    pub m: usize,
+    // Candidates considered when connecting a new node
+    pub ef_construction: usize,
+    // Candidates considered by a search, at least top_k
+    pub ef_search: usize,
+    // Seed of the level generator, so builds are reproducible
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            seed: 0,
+        }
+    }
+}
+
+impl HnswParams {
+    pub fn new(m: usize, ef_construction: usize, ef_search: usize) -> Self {
+        Self {
+            m: m.max(2),
+            ef_construction: ef_construction.max(1),
+            ef_search: ef_search.max(1),
+            seed: 0,
+        }
+    }
+
+    // compatible_with is true when a graph built with these parameters can be used with
+    // other; ef_search only applies to queries
+    pub fn compatible_with(&self, other: &HnswParams) -> bool {
+        self.m == other.m && self.ef_construction == other.ef_construction && self.seed == other.seed
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Node<K> {
+    id: K,
+    // Normalized, so the dot product is the cosine similarity
+    vector: Vec<f32>,
+    // Neighbors on each layer the node is placed on, layer 0 first
+    neighbors: Vec<Vec<usize>>,
+    // Removed nodes stay in the graph to keep it navigable but are never returned
+    deleted: bool,
+}
+
+// HnswIndex is a hierarchical navigable small world graph. Searches descend greedily
+// through the sparse upper layers and explore ef candidates on layer 0. Removed nodes
+// are tombstoned and the graph is rebuilt once they outnumber the live nodes.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: DeserializeOwned + Eq + Hash + Clone"))]
+pub struct HnswIndex<K> {
+    params: HnswParams,
+    nodes: Vec<Node<K>>,
+    entry: Option<usize>,
+    rng: u64,
+    #[serde(skip)]
+    ids: HashMap<K, usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    similarity: f32,
+    node: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> HnswIndex<K>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new(params: HnswParams) -> Self {
+        Self {
+            params,
+            nodes: Vec::new(),
+            entry: None,
+            rng: params.seed,
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn params(&self) -> &HnswParams {
+        &self.params
+    }
+
+    // restore_ids rebuilds the id lookup of a deserialized graph
+    pub(crate) fn restore_ids(&mut self) {
+        self.ids = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.deleted)
+            .map(|(position, node)| (node.id.clone(), position))
+            .collect();
+    }
+
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.params.ef_search = ef_search.max(1);
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 { self.params.m * 2 } else { self.params.m }
+    }
+
+    // random_level draws the top layer of a new node from an exponential distribution,
+    // so each layer holds about 1/m of the nodes of the layer below
+    fn random_level(&mut self) -> usize {
+        // uniform in (0, 1]
+        let uniform = 1.0 - next_random(&mut self.rng);
+        let level = -uniform.ln() / (self.params.m as f64).ln();
+        (level as usize).min(MAX_LEVEL)
+    }
+
+    fn similarity(&self, query: &[f32], node: usize) -> f32 {
+        dot_product(query, &self.nodes[node].vector)
+    }
+
+    // search_layer explores the layer from the entry points and returns the ef most
+    // similar nodes found, best first
+    fn search_layer(&self, query: &[f32], entry: &[usize], ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entry.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+        for &node in entry {
+            let candidate = Candidate {
+                similarity: self.similarity(query, node),
+                node,
+            };
+            candidates.push(candidate);
+            results.push(Reverse(candidate));
+        }
+        while results.len() > ef {
+            results.pop();
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0.similarity);
+            if candidate.similarity < worst && results.len() >= ef {
+                break;
+            }
+            for &neighbor in &self.nodes[candidate.node].neighbors[layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let similarity = self.similarity(query, neighbor);
+                let worst = results.peek().map_or(f32::NEG_INFINITY, |r| r.0.similarity);
+                if results.len() < ef || similarity > worst {
+                    let candidate = Candidate {
+                        similarity,
+                        node: neighbor,
+                    };
+                    candidates.push(candidate);
+                    results.push(Reverse(candidate));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results: Vec<Candidate> = results.into_iter().map(|r| r.0).collect();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    // select_neighbors keeps candidates that are closer to the base than to any already
+    // selected neighbor, so links spread in different directions, then fills up with
+    // the best of the pruned ones
+    fn select_neighbors(&self, candidates: &[Candidate], max: usize) -> Vec<usize> {
+        let mut selected: Vec<usize> = Vec::with_capacity(max);
+        let mut pruned = Vec::new();
+        for candidate in candidates {
+            if selected.len() >= max {
+                break;
+            }
+            let vector = &self.nodes[candidate.node].vector;
+            let diverse = selected
+                .iter()
+                .all(|&s| dot_product(vector, &self.nodes[s].vector) < candidate.similarity);
+            if diverse {
+                selected.push(candidate.node);
+            } else {
+                pruned.push(candidate.node);
+            }
+        }
+        let missing = max.saturating_sub(selected.len());
+        selected.extend(pruned.into_iter().take(missing));
+        selected
+    }
+
+    // connect links node to neighbor on the layer, shrinking the neighbor's links when
+    // it has too many
+    fn connect(&mut self, neighbor: usize, node: usize, layer: usize) {
+        self.nodes[neighbor].neighbors[layer].push(node);
+        let max = self.max_neighbors(layer);
+        if self.nodes[neighbor].neighbors[layer].len() <= max {
+            return;
+        }
+
+        let base = &self.nodes[neighbor].vector;
+        let mut candidates: Vec<Candidate> = self.nodes[neighbor].neighbors[layer]
+            .iter()
+            .map(|&n| Candidate {
+                similarity: dot_product(base, &self.nodes[n].vector),
+                node: n,
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.cmp(a));
+        self.nodes[neighbor].neighbors[layer] = self.select_neighbors(&candidates, max);
+    }
+
+    fn live(&self) -> usize {
+        self.ids.len()
+    }
+
+    // rebuild inserts the live nodes into a new graph, dropping the tombstones
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.ids.clear();
+        self.entry = None;
+        for node in nodes.into_iter().filter(|node| !node.deleted) {
+            self.insert_normalized(node.id, node.vector);
+        }
+    }
+
+    fn insert_normalized(&mut self, id: K, vector: Vec<f32>) {
+        let level = self.random_level();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            id: id.clone(),
+            vector: vector.clone(),
+            neighbors: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id, node);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+
+        // descend greedily through the layers above the node's level
+        let top = self.nodes[entry].neighbors.len() - 1;
+        let mut entry_points = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            entry_points = vec![self.search_layer(&vector, &entry_points, 1, layer)[0].node];
+        }
+
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&vector, &entry_points, self.params.ef_construction, layer);
+            let neighbors = self.select_neighbors(&found, self.params.m);
+            for &neighbor in &neighbors {
+                self.connect(neighbor, node, layer);
+            }
+            self.nodes[node].neighbors[layer] = neighbors;
+            entry_points = found.iter().map(|candidate| candidate.node).collect();
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+}
+
+impl<K> VectorIndex<K> for HnswIndex<K>
+where
+    K: Eq + Hash + Clone + Send + Sync + std::fmt::Debug + Serialize,
+{
+    fn insert(&mut self, id: K, vector: &[f32]) {
+        self.remove(&id);
+        self.insert_normalized(id, normalize(vector));
+    }
+
+    fn remove(&mut self, id: &K) {
+        let Some(node) = self.ids.remove(id) else {
+            return;
+        };
+        self.nodes[node].deleted = true;
+        if self.nodes.len() - self.live() > self.live() {
+            self.rebuild();
+        }
+    }
+
+    fn search(&self, query: &[f32], top_k: usize, allowed: Option<&HashSet<K>>) -> Vec<(K, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if top_k == 0 || self.live() == 0 {
+            return Vec::new();
+        }
+
+        // widen the search by the share of nodes that cannot be returned
+        let returnable = allowed.map_or(self.live(), |allowed| allowed.len().min(self.live()));
+        if returnable == 0 {
+            return Vec::new();
+        }
+        let ef = self.params.ef_search.max(top_k);
+        let ef = (ef * self.nodes.len()).div_ceil(returnable).min(self.nodes.len());
+
+        let query = normalize(query);
+        let top = self.nodes[entry].neighbors.len() - 1;
+        let mut entry_points = vec![entry];
+        for layer in (1..=top).rev() {
+            entry_points = vec![self.search_layer(&query, &entry_points, 1, layer)[0].node];
+        }
+
+        self.search_layer(&query, &entry_points, ef, 0)
+            .into_iter()
+            .map(|candidate| (&self.nodes[candidate.node], candidate.similarity))
+            .filter(|(node, _)| !node.deleted && allowed.is_none_or(|allowed| allowed.contains(&node.id)))
+            .map(|(node, similarity)| (node.id.clone(), similarity))
+            .take(top_k)
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.live()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let links: usize = node.neighbors.iter().map(|layer| layer.len()).sum();
+                size_of_val(&node.id) + node.vector.len() * size_of::<f32>() + links * size_of::<usize>()
+            })
+            .sum()
+    }
+
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.ids.clear();
+        self.entry = None;
+        self.rng = self.params.seed;
+    }
+
+    fn save(&self, path: &Path, watermark: u64) -> Result<()> {
+        save_snapshot(path, self, watermark)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::vector::hnsw::{HnswIndex, HnswParams};
+    use crate::vector::index::testing::{random_vectors, recall_at_k};
+    use crate::vector::index::{VectorIndex, VectorIndexDefinition};
+
+    fn build(params: HnswParams, data: &[(u32, Vec<f32>)]) -> HnswIndex<u32> {
+        let mut index = HnswIndex::new(params);
+        for (id, vector) in data {
+            index.insert(*id, vector);
+        }
+        index
+    }
+
+    #[test]
+    fn test_recall_against_brute_force() {
+        let data = random_vectors(1000, 16, 7);
+        let queries = random_vectors(50, 16, 11);
+
+        let mut index = build(HnswParams::new(12, 100, 10), &data);
+        assert_eq!(index.len(), 1000);
+        let low = recall_at_k(&index, &data, &queries, 10);
+
+        index.set_ef_search(100);
+        let high = recall_at_k(&index, &data, &queries, 10);
+        assert!(high >= 0.95, "recall@10 with ef 100: {}", high);
+        assert!(high >= low, "recall@10 with ef 10: {}, ef 100: {}", low, high);
+
+        // scores are cosine similarities, best first
+        let results = index.search(&data[3].1, 5, None);
+        assert_eq!(results[0].0, 3);
+        assert!((results[0].1 - 1.0).abs() < 1e-5);
+        assert!(results.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn test_remove_update_and_filter() {
+        let data = random_vectors(300, 8, 3);
+        let mut index = build(HnswParams::default(), &data);
+
+        index.remove(&3);
+        assert_eq!(index.len(), 299);
+        assert!(index.search(&data[3].1, 10, None).iter().all(|(id, _)| *id != 3));
+
+        // updating moves the vector
+        index.insert(4, &data[5].1);
+        assert_eq!(index.search(&data[5].1, 2, None).len(), 2);
+        assert!(index.search(&data[5].1, 2, None).iter().any(|(id, _)| *id == 4));
+        assert_eq!(index.len(), 299);
+
+        let allowed: HashSet<u32> = [10, 20, 30].into_iter().collect();
+        let results = index.search(&data[10].1, 10, Some(&allowed));
+        let ids: HashSet<u32> = results.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, allowed);
+
+        // removing most nodes rebuilds the graph without the tombstones
+        for id in 0..250 {
+            index.remove(&id);
+        }
+        assert_eq!(index.len(), 50);
+        assert!(index.nodes.len() < 300);
+        let found = index.search(&data[260].1, 1, None);
+        assert_eq!(found[0].0, 260);
+    }
+
+    #[test]
+    fn test_save_and_load() -> anyhow::Result<()> {
+        let dir = std::path::Path::new("data/tests/hnsw_snapshot");
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("vectors.hnsw");
+
+        let data = random_vectors(200, 8, 5);
+        let params = HnswParams::default();
+        let index = build(params, &data);
+        index.save(&path, 42)?;
+
+        let definition = VectorIndexDefinition::Hnsw(HnswParams { ef_search: 16, ..params });
+        let (loaded, watermark) = definition.load::<u32>(&path, 100)?.expect("snapshot");
+        assert_eq!(watermark, 42);
+        assert_eq!(loaded.len(), 200);
+        assert_eq!(loaded.search(&data[7].1, 5, None), index.search(&data[7].1, 5, None));
+
+        // another graph shape or a shorter log discards the snapshot
+        let other = VectorIndexDefinition::Hnsw(HnswParams::new(8, 200, 64));
+        assert!(other.load::<u32>(&path, 100)?.is_none());
+        assert!(definition.load::<u32>(&path, 41)?.is_none());
+        Ok(())
+    }
+}
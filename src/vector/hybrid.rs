@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Fusion is how the ranked lists of a vector search and a keyword search are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    // Sum of 1 / (k + rank) over the lists holding the id; k dampens the top ranks
+    ReciprocalRank { k: f32 },
+    // Blend of the min-max normalized scores: vector_weight * vector + (1 - vector_weight) * text
+    Weighted { vector_weight: f32 },
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::ReciprocalRank { k: 60.0 }
+    }
+}
+
+// fuse combines the vector and text results, both ordered best first, into a single
+// list ordered by descending fused score. Equal scores keep the order in which the ids
+// first appear, vector results before text results.
+pub fn fuse<K>(vector: &[(K, f32)], text: &[(K, f32)], fusion: Fusion) -> Vec<(K, f32)>
+where
+    K: Eq + Hash + Clone,
+{
+    match fusion {
+        Fusion::ReciprocalRank { k } => reciprocal_rank_fusion(&[vector, text], k),
+        Fusion::Weighted { vector_weight } => weighted_fusion(vector, text, vector_weight),
+    }
+}
+
+// reciprocal_rank_fusion scores each id by the sum of 1 / (k + rank), ranks starting at 1
+pub fn reciprocal_rank_fusion<K>(lists: &[&[(K, f32)]], k: f32) -> Vec<(K, f32)>
+where
+    K: Eq + Hash + Clone,
+{
+    let mut fused = Fused::default();
+    for list in lists {
+        for (rank, (id, _)) in list.iter().enumerate() {
+            fused.add(id, 1.0 / (k + rank as f32 + 1.0));
+        }
+    }
+    fused.finish()
+}
+
+// weighted_fusion blends the scores after scaling each list to [0, 1]; an id missing
+// from a list scores 0 there
+pub fn weighted_fusion<K>(vector: &[(K, f32)], text: &[(K, f32)], vector_weight: f32) -> Vec<(K, f32)>
+where
+    K: Eq + Hash + Clone,
+{
+    let vector_weight = vector_weight.clamp(0.0, 1.0);
+    let mut fused = Fused::default();
+    for (list, weight) in [(vector, vector_weight), (text, 1.0 - vector_weight)] {
+        for (id, score) in normalize(list) {
+            fused.add(id, weight * score);
+        }
+    }
+    fused.finish()
+}
+
+// normalize scales the scores of a list to [0, 1]; when all scores are equal they become 1
+fn normalize<K>(list: &[(K, f32)]) -> impl Iterator<Item = (&K, f32)> {
+    let scores = list.iter().map(|(_, score)| *score).filter(|score| score.is_finite());
+    let min = scores.clone().fold(f32::INFINITY, f32::min);
+    let max = scores.fold(f32::NEG_INFINITY, f32::max);
+    list.iter().map(move |(id, score)| {
+        let score = if !score.is_finite() {
+            0.0
+        } else if max > min {
+            (score - min) / (max - min)
+        } else {
+            1.0
+        };
+        (id, score)
+    })
+}
+
+// Fused accumulates scores per id, remembering the order ids were first seen
+struct Fused<K> {
+    positions: HashMap<K, usize>,
+    scores: Vec<(K, f32)>,
+}
+
+impl<K> Default for Fused<K> {
+    fn default() -> Self {
+        Self {
+            positions: HashMap::new(),
+            scores: Vec::new(),
+        }
+    }
+}
+
+impl<K> Fused<K>
+where
+    K: Eq + Hash + Clone,
+{
+    fn add(&mut self, id: &K, score: f32) {
+        match self.positions.get(id) {
+            Some(position) => self.scores[*position].1 += score,
+            None => {
+                self.positions.insert(id.clone(), self.scores.len());
+                self.scores.push((id.clone(), score));
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<(K, f32)> {
+        // stable sort keeps the first seen order of equal scores
+        self.scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        self.scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vector::hybrid::{Fusion, fuse, reciprocal_rank_fusion, weighted_fusion};
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let vector = [(1, 0.9), (2, 0.8), (3, 0.1)];
+        let text = [(3, 12.0), (2, 4.0), (4, 1.0)];
+
+        let fused = reciprocal_rank_fusion(&[&vector, &text], 60.0);
+        let ids: Vec<i32> = fused.iter().map(|(id, _)| *id).collect();
+        // 3 (first and third) edges out 2 (second twice); 1 and 4 are in a single list
+        assert_eq!(ids, vec![3, 2, 1, 4]);
+        assert!((fused[0].1 - (1.0 / 61.0 + 1.0 / 63.0)).abs() < 1e-6);
+        assert!((fused[1].1 - 2.0 / 62.0).abs() < 1e-6);
+        assert_eq!(fuse(&vector, &text, Fusion::default()), fused);
+    }
+
+    #[test]
+    fn test_weighted_fusion() {
+        let vector = [(1, 0.9), (2, 0.5), (3, 0.1)];
+        let text = [(3, 10.0), (2, 5.0)];
+
+        let ids = |fused: Vec<(i32, f32)>| -> Vec<i32> { fused.into_iter().map(|(id, _)| id).collect() };
+        assert_eq!(ids(weighted_fusion(&vector, &text, 1.0)), vec![1, 2, 3]);
+        assert_eq!(ids(weighted_fusion(&vector, &text, 0.0)), vec![3, 1, 2]);
+
+        // 1: 0.7 * 1.0, 2: 0.7 * 0.5 + 0.3 * 0.0, 3: 0.3 * 1.0
+        let fused = weighted_fusion(&vector, &text, 0.7);
+        assert_eq!(ids(fused.clone()), vec![1, 2, 3]);
+        assert!((fused[1].1 - 0.35).abs() < 1e-6);
+
+        // a single result scores 1 in its list
+        let fused = weighted_fusion(&[(1, 0.2)], &[(1, 3.0)], 0.5);
+        assert_eq!(fused, vec![(1, 1.0)]);
+    }
+}
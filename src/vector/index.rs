@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::core::RepoKey;
+use crate::vector::hnsw::{HnswIndex, HnswParams};
+use crate::vector::ivf::{IvfFlatIndex, IvfParams};
+
+// VectorIndex answers nearest neighbor queries over the embeddings of a collection.
+// Scores are cosine similarities, higher is better; other metrics are not indexed.
+pub trait VectorIndex<K>: Debug + Send + Sync {
+    // insert adds the vector of id, replacing its previous vector
+    fn insert(&mut self, id: K, vector: &[f32]);
+
+    fn remove(&mut self, id: &K);
+
+    // search returns up to top_k ids ordered by descending similarity, restricted to
+    // the allowed ids when given. Indexes with approximate scores may add the candidates
+    // tied with the last one.
+    fn search(&self, query: &[f32], top_k: usize, allowed: Option<&HashSet<K>>) -> Vec<(K, f32)>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // rerank_depth is the number of candidates to request for top_k results. Indexes with
+    // approximate scores return more, to be re-ranked with the full precision vectors.
+    fn rerank_depth(&self, top_k: usize) -> usize {
+        top_k
+    }
+
+    // needs_rebuild is true when the index should be rebuilt from the full precision
+    // vectors, which quantized indexes need instead of retraining on their codes
+    fn needs_rebuild(&self) -> bool {
+        false
+    }
+
+    // rebuild replaces the vectors of the index with the given ones
+    fn rebuild(&mut self, vectors: Vec<(K, Vec<f32>)>) {
+        self.clear();
+        for (id, vector) in vectors {
+            self.insert(id, &vector);
+        }
+    }
+
+    // memory_usage is the approximate size in bytes of the vectors, codes and links held
+    fn memory_usage(&self) -> usize;
+
+    fn clear(&mut self);
+
+    // save writes the index with the log offset it covers to path
+    fn save(&self, path: &Path, watermark: u64) -> Result<()>;
+}
+
+// LoadedIndex is a vector index read from a snapshot with its watermark
+pub type LoadedIndex<K> = (Box<dyn VectorIndex<K>>, u64);
+
+// VectorIndexDefinition declares the vector index of a collection and its parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum VectorIndexDefinition {
+    Hnsw(HnswParams),
+    #[serde(rename = "ivf_flat")]
+    IvfFlat(IvfParams),
+}
+
+impl VectorIndexDefinition {
+    // build creates an empty index
+    pub fn build<K: RepoKey>(&self) -> Box<dyn VectorIndex<K>> {
+        match self {
+            VectorIndexDefinition::Hnsw(params) => Box::new(HnswIndex::new(*params)),
+            VectorIndexDefinition::IvfFlat(params) => Box::new(IvfFlatIndex::new(*params)),
+        }
+    }
+
+    // load reads the index saved at path with its watermark. It returns None when there is
+    // no snapshot, it was built with other parameters, or its watermark is past the end of the log.
+    pub fn load<K: RepoKey>(&self, path: &Path, log_length: u64) -> Result<Option<LoadedIndex<K>>> {
+        let loaded: Option<LoadedIndex<K>> = match self {
+            VectorIndexDefinition::Hnsw(params) => load_snapshot::<HnswIndex<K>>(path)?
+                .filter(|(index, _)| index.params().compatible_with(params))
+                .map(|(mut index, watermark)| {
+                    index.restore_ids();
+                    index.set_ef_search(params.ef_search);
+                    (Box::new(index) as Box<dyn VectorIndex<K>>, watermark)
+                }),
+            VectorIndexDefinition::IvfFlat(params) => load_snapshot::<IvfFlatIndex<K>>(path)?
+                .filter(|(index, _)| index.params().compatible_with(params))
+                .map(|(mut index, watermark)| {
+                    index.restore_positions();
+                    index.set_nprobe(params.nprobe);
+                    index.set_retrain_threshold(params.retrain_threshold);
+                    index.set_rerank(params.rerank);
+                    (Box::new(index) as Box<dyn VectorIndex<K>>, watermark)
+                }),
+        };
+        Ok(loaded.filter(|(_, watermark)| *watermark <= log_length))
+    }
+}
+
+// VectorSnapshot is the persisted form of a vector index
+#[derive(Serialize, Deserialize)]
+struct VectorSnapshot<T> {
+    watermark: u64,
+    index: T,
+}
+
+pub(crate) fn save_snapshot<T: Serialize>(path: &Path, index: &T, watermark: u64) -> Result<()> {
+    let snapshot = VectorSnapshot { watermark, index };
+    fs::write(path, serde_json::to_vec(&snapshot)?)?;
+    Ok(())
+}
+
+pub(crate) fn load_snapshot<T: DeserializeOwned>(path: &Path) -> Result<Option<(T, u64)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let snapshot: VectorSnapshot<T> = serde_json::from_slice(&fs::read(path)?)?;
+    Ok(Some((snapshot.index, snapshot.watermark)))
+}
+
+// next_random returns a uniform value in [0, 1) and advances the state (splitmix64)
+pub(crate) fn next_random(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// VectorExtractor reads the embedding of a model, normally VectorEmbedding::vector
+pub type VectorExtractor<M> = fn(&M) -> &[f32];
+
+// EmbeddingIndex keeps a vector index up to date with the models of a collection. The
+// watermark is the offset of the log up to which records are applied.
+#[derive(Debug)]
+pub struct EmbeddingIndex<K, M> {
+    definition: VectorIndexDefinition,
+    extract: VectorExtractor<M>,
+    index: Box<dyn VectorIndex<K>>,
+    watermark: u64,
+}
+
+impl<K: RepoKey, M> EmbeddingIndex<K, M> {
+    pub fn new(definition: VectorIndexDefinition, extract: VectorExtractor<M>) -> Self {
+        Self {
+            index: definition.build(),
+            definition,
+            extract,
+            watermark: 0,
+        }
+    }
+
+    pub fn definition(&self) -> &VectorIndexDefinition {
+        &self.definition
+    }
+
+    pub fn index(&self) -> &dyn VectorIndex<K> {
+        self.index.as_ref()
+    }
+
+    pub fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    pub fn set_watermark(&mut self, watermark: u64) {
+        self.watermark = watermark;
+    }
+
+    pub fn insert(&mut self, id: K, model: &M) {
+        self.index.insert(id, (self.extract)(model));
+    }
+
+    // vector reads the embedding of the model that the index holds
+    pub fn vector<'m>(&self, model: &'m M) -> &'m [f32] {
+        (self.extract)(model)
+    }
+
+    // rebuild replaces the vectors of the index
+    pub fn rebuild(&mut self, vectors: Vec<(K, Vec<f32>)>) {
+        self.index.rebuild(vectors);
+    }
+
+    pub fn remove(&mut self, id: &K) {
+        self.index.remove(id);
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.watermark = 0;
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.index.save(path, self.watermark)
+    }
+
+    // load replaces the index with the snapshot at path. It returns false, leaving the
+    // index cleared, when the snapshot cannot be used.
+    pub fn load(&mut self, path: &Path, log_length: u64) -> Result<bool> {
+        self.clear();
+        match self.definition.load(path, log_length)? {
+            Some((index, watermark)) => {
+                self.index = index;
+                self.watermark = watermark;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::vector::eval::{self, clustered_vectors};
+    use crate::vector::index::VectorIndex;
+    use crate::vector::search::vector_search;
+
+    // random_vectors draws vectors around eight centers, numbered from 0
+    pub(crate) fn random_vectors(count: usize, dimension: usize, seed: u64) -> Vec<(u32, Vec<f32>)> {
+        (0u32..).zip(clustered_vectors(count, dimension, 8, seed)).collect()
+    }
+
+    // recall_at_k is the share of the exact top_k neighbors found by the index, averaged over the queries
+    pub(crate) fn recall_at_k(
+        index: &dyn VectorIndex<u32>,
+        data: &[(u32, Vec<f32>)],
+        queries: &[(u32, Vec<f32>)],
+        top_k: usize,
+    ) -> f32 {
+        let total: f32 = queries
+            .iter()
+            .map(|(_, query)| {
+                let exact = vector_search(query, data, top_k);
+                eval::recall_at_k(&exact, &index.search(query, top_k, None), top_k)
+            })
+            .sum();
+        total / queries.len() as f32
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::vector::index::{VectorIndex, next_random, save_snapshot};
+use crate::vector::metric::DistanceMetric;
+use crate::vector::quantization::{Quantization, Quantizer, StoredVector};
+use crate::vector::search::select_top_k;
+use crate::vector::similarity::{dot_product, normalize};
+
+// k-means iterations per training
+const KMEANS_ITERATIONS: usize = 10;
+// Vectors per list needed before the centroids are trained
+const MIN_VECTORS_PER_LIST: usize = 4;
+// Vectors per list sampled for training
+const MAX_SAMPLE_PER_LIST: usize = 256;
+
+// IvfParams tunes the inverted file. More lists make each list shorter; probing more
+// lists per query trades query time for recall.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IvfParams {
+    // Number of centroids, each with its own list of vectors
+    pub lists: usize,
+    // Lists scanned per query, the ones with the most similar centroids
+    pub nprobe: usize,
+    // Relative change of the number of vectors since the last training that triggers
+    // a retraining; 1.0 retrains when the collection doubled or halved
+    pub retrain_threshold: f32,
+    // Seed of the centroid initialization, so trainings are reproducible
+    #[serde(default)]
+    pub seed: u64,
+    // Encoding of the vectors in the lists once the index is trained
+    #[serde(default)]
+    pub quantization: Quantization,
+    // Candidates per requested result returned for full precision re-ranking when quantized
+    #[serde(default = "default_rerank")]
+    pub rerank: usize,
+}
+
+fn default_rerank() -> usize {
+    4
+}
+
+impl Default for IvfParams {
+    fn default() -> Self {
+        Self {
+            lists: 64,
+            nprobe: 8,
+            retrain_threshold: 1.0,
+            seed: 0,
+            quantization: Quantization::None,
+            rerank: default_rerank(),
+        }
+    }
+}
+
+impl IvfParams {
+    pub fn new(lists: usize, nprobe: usize) -> Self {
+        Self {
+            lists: lists.max(1),
+            nprobe: nprobe.max(1),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = quantization;
+        self
+    }
+
+    // compatible_with is true when an index trained with these parameters can be used with
+    // other; nprobe, retrain_threshold and rerank only apply to queries and writes
+    pub fn compatible_with(&self, other: &IvfParams) -> bool {
+        self.lists == other.lists && self.seed == other.seed && self.quantization == other.quantization
+    }
+}
+
+// IvfFlatIndex assigns every vector to the list of its most similar centroid and scans
+// only the lists of the nprobe centroids most similar to the query. Until there are
+// enough vectors to train the centroids, all vectors are kept in one list and scanned.
+// With quantization, the vectors are encoded once the index is trained and scored
+// against the query without decoding them.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: DeserializeOwned + Eq + Hash + Clone"))]
+pub struct IvfFlatIndex<K> {
+    params: IvfParams,
+    // Normalized centroids, empty while untrained
+    centroids: Vec<Vec<f32>>,
+    // Quantizer trained with the centroids
+    quantizer: Option<Quantizer>,
+    // Normalized vectors of each list
+    lists: Vec<Vec<(K, StoredVector)>>,
+    // Number of vectors at the last training
+    trained_size: usize,
+    #[serde(skip)]
+    positions: HashMap<K, (usize, usize)>,
+}
+
+impl<K> IvfFlatIndex<K>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new(params: IvfParams) -> Self {
+        Self {
+            params,
+            centroids: Vec::new(),
+            quantizer: None,
+            lists: vec![Vec::new()],
+            trained_size: 0,
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn params(&self) -> &IvfParams {
+        &self.params
+    }
+
+    pub fn set_nprobe(&mut self, nprobe: usize) {
+        self.params.nprobe = nprobe.max(1);
+    }
+
+    pub fn set_retrain_threshold(&mut self, retrain_threshold: f32) {
+        self.params.retrain_threshold = retrain_threshold;
+    }
+
+    pub fn set_rerank(&mut self, rerank: usize) {
+        self.params.rerank = rerank.max(1);
+    }
+
+    pub fn is_trained(&self) -> bool {
+        !self.centroids.is_empty()
+    }
+
+    // restore_positions rebuilds the id lookup of a deserialized index
+    pub(crate) fn restore_positions(&mut self) {
+        self.positions = self
+            .lists
+            .iter()
+            .enumerate()
+            .flat_map(|(list, entries)| {
+                entries
+                    .iter()
+                    .enumerate()
+                    .map(move |(position, (id, _))| (id.clone(), (list, position)))
+            })
+            .collect();
+    }
+
+    // drift is the relative change of the number of vectors since the last training
+    pub fn drift(&self) -> f32 {
+        let trained = self.trained_size.max(1) as f32;
+        (self.positions.len() as f32 - trained).abs() / trained
+    }
+
+    // needs_training is true when an untrained index has enough vectors, or a trained
+    // index drifted past the retrain threshold
+    pub fn needs_training(&self) -> bool {
+        let enough = self.positions.len() >= self.params.lists * MIN_VECTORS_PER_LIST;
+        if !self.is_trained() {
+            return self.params.lists > 1 && enough;
+        }
+        enough && self.drift() > self.params.retrain_threshold
+    }
+
+    // train runs spherical k-means on a sample of the vectors and reassigns every vector
+    // to the list of its most similar centroid. A quantized index is not retrained: its
+    // codes would be trained on and encoded again, losing precision each time, so it waits
+    // for a rebuild from the full precision vectors instead.
+    pub fn train(&mut self) {
+        if self.quantizer.is_some() {
+            return;
+        }
+        let entries: Vec<(K, Vec<f32>)> = std::mem::take(&mut self.lists)
+            .into_iter()
+            .flatten()
+            .filter_map(|(id, stored)| match stored {
+                StoredVector::Full(vector) => Some((id, vector)),
+                StoredVector::Codes(_) => None,
+            })
+            .collect();
+        let k = self.params.lists.min(entries.len()).max(1);
+
+        let step = (entries.len() / (k * MAX_SAMPLE_PER_LIST)).max(1);
+        let sample: Vec<&[f32]> = entries.iter().step_by(step).map(|(_, v)| v.as_slice()).collect();
+        self.centroids = kmeans(&sample, k, self.params.seed);
+        self.quantizer = Quantizer::train(self.params.quantization, &sample, self.params.seed);
+        self.trained_size = entries.len();
+
+        self.lists = vec![Vec::new(); self.centroids.len().max(1)];
+        self.positions.clear();
+        for (id, vector) in entries {
+            self.push(id, vector);
+        }
+    }
+
+    fn nearest_list(&self, vector: &[f32]) -> usize {
+        nearest(&self.centroids, vector)
+    }
+
+    // ranked_lists orders the lists by the similarity of their centroid to the vector
+    fn ranked_lists(&self, vector: &[f32]) -> Vec<usize> {
+        if !self.is_trained() {
+            return vec![0];
+        }
+        let mut ranked: Vec<(usize, f32)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(list, centroid)| (list, dot_product(vector, centroid)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.into_iter().map(|(list, _)| list).collect()
+    }
+
+    fn push(&mut self, id: K, vector: Vec<f32>) {
+        let list = self.nearest_list(&vector);
+        let stored = match &self.quantizer {
+            Some(quantizer) => StoredVector::Codes(quantizer.encode(&vector)),
+            None => StoredVector::Full(vector),
+        };
+        self.positions.insert(id.clone(), (list, self.lists[list].len()));
+        self.lists[list].push((id, stored));
+    }
+}
+
+impl<K> VectorIndex<K> for IvfFlatIndex<K>
+where
+    K: Eq + Hash + Clone + Send + Sync + std::fmt::Debug + Serialize,
+{
+    fn insert(&mut self, id: K, vector: &[f32]) {
+        self.remove(&id);
+        self.push(id, normalize(vector));
+        if self.needs_training() {
+            self.train();
+        }
+    }
+
+    // needs_rebuild is true when a quantized index drifted past the retrain threshold
+    fn needs_rebuild(&self) -> bool {
+        self.quantizer.is_some() && self.needs_training()
+    }
+
+    // rebuild trains the index once over all the vectors, before encoding them
+    fn rebuild(&mut self, vectors: Vec<(K, Vec<f32>)>) {
+        self.clear();
+        for (id, vector) in vectors {
+            self.push(id, normalize(&vector));
+        }
+        if self.needs_training() {
+            self.train();
+        }
+    }
+
+    fn remove(&mut self, id: &K) {
+        let Some((list, position)) = self.positions.remove(id) else {
+            return;
+        };
+        self.lists[list].swap_remove(position);
+        if let Some((moved, _)) = self.lists[list].get(position) {
+            self.positions.insert(moved.clone(), (list, position));
+        }
+        if self.needs_training() {
+            self.train();
+        }
+    }
+
+    // search scans the nprobe lists with the most similar centroids, and further lists
+    // while fewer than top_k allowed vectors were found
+    fn search(&self, query: &[f32], top_k: usize, allowed: Option<&HashSet<K>>) -> Vec<(K, f32)> {
+        if top_k == 0 {
+            return Vec::new();
+        }
+
+        let query = normalize(query);
+        let scorer = self.quantizer.as_ref().map(|quantizer| quantizer.scorer(&query));
+        let mut results: Vec<(&K, f32)> = Vec::new();
+        for (probed, list) in self.ranked_lists(&query).into_iter().enumerate() {
+            if probed >= self.params.nprobe && results.len() >= top_k {
+                break;
+            }
+            for (id, stored) in &self.lists[list] {
+                if allowed.is_some_and(|allowed| !allowed.contains(id)) {
+                    continue;
+                }
+                let score = match (stored, &scorer) {
+                    (StoredVector::Full(vector), _) => dot_product(&query, vector),
+                    (StoredVector::Codes(codes), Some(scorer)) => scorer.score(codes),
+                    (StoredVector::Codes(_), None) => continue,
+                };
+                results.push((id, score));
+            }
+        }
+
+        let scores = results.iter().enumerate().map(|(position, (_, score))| (position, *score));
+        let mut best = select_top_k(scores, top_k, DistanceMetric::Cosine, None);
+        // codes cannot order vectors that quantize alike, so candidates tied with the last
+        // one are kept for the re-ranking
+        if self.quantizer.is_some()
+            && best.len() == top_k
+            && let Some(&(_, last)) = best.last()
+        {
+            let chosen: HashSet<usize> = best.iter().map(|(position, _)| *position).collect();
+            let ties = results
+                .iter()
+                .enumerate()
+                .filter(|(position, (_, score))| *score >= last && !chosen.contains(position));
+            best.extend(ties.map(|(position, (_, score))| (position, *score)));
+        }
+        best.into_iter().map(|(position, score)| (results[position].0.clone(), score)).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn rerank_depth(&self, top_k: usize) -> usize {
+        match self.quantizer {
+            Some(_) => top_k.saturating_mul(self.params.rerank),
+            None => top_k,
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        let vectors: usize = self
+            .lists
+            .iter()
+            .flatten()
+            .map(|(id, stored)| size_of_val(id) + stored.memory_usage())
+            .sum();
+        let centroids: usize = self.centroids.iter().map(|c| c.len() * size_of::<f32>()).sum();
+        vectors + centroids + self.quantizer.as_ref().map_or(0, |quantizer| quantizer.memory_usage())
+    }
+
+    fn clear(&mut self) {
+        self.centroids.clear();
+        self.quantizer = None;
+        self.lists = vec![Vec::new()];
+        self.positions.clear();
+        self.trained_size = 0;
+    }
+
+    fn save(&self, path: &Path, watermark: u64) -> Result<()> {
+        save_snapshot(path, self, watermark)
+    }
+}
+
+// kmeans clusters normalized vectors by cosine similarity. Centroids are seeded with
+// k-means++ and normalized after every update; an empty cluster keeps its centroid.
+fn kmeans(vectors: &[&[f32]], k: usize, seed: u64) -> Vec<Vec<f32>> {
+    if vectors.is_empty() {
+        return Vec::new();
+    }
+
+    let mut rng = seed;
+    let first = (next_random(&mut rng) * vectors.len() as f64) as usize;
+    let mut centroids: Vec<Vec<f32>> = vec![vectors[first.min(vectors.len() - 1)].to_vec()];
+    // distance of each vector to its nearest centroid so far
+    let mut distances: Vec<f32> = vectors.iter().map(|v| 1.0 - dot_product(v, &centroids[0])).collect();
+    while centroids.len() < k {
+        let total: f32 = distances.iter().map(|d| d.max(0.0)).sum();
+        let chosen = if total > 0.0 {
+            let mut target = next_random(&mut rng) as f32 * total;
+            distances
+                .iter()
+                .position(|d| {
+                    target -= d.max(0.0);
+                    target <= 0.0
+                })
+                .unwrap_or(vectors.len() - 1)
+        } else {
+            centroids.len() % vectors.len()
+        };
+        centroids.push(vectors[chosen].to_vec());
+        for (distance, vector) in distances.iter_mut().zip(vectors) {
+            *distance = distance.min(1.0 - dot_product(vector, &centroids[centroids.len() - 1]));
+        }
+    }
+
+    let dimension = vectors[0].len();
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![vec![0.0f32; dimension]; k];
+        let mut counts = vec![0usize; k];
+        for vector in vectors {
+            let nearest = nearest(&centroids, vector);
+            counts[nearest] += 1;
+            for (sum, value) in sums[nearest].iter_mut().zip(vector.iter()) {
+                *sum += value;
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *centroid = normalize(&sum);
+            }
+        }
+    }
+    centroids
+}
+
+// nearest returns the position of the centroid most similar to the vector, 0 without centroids
+fn nearest(centroids: &[Vec<f32>], vector: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(position, centroid)| (position, dot_product(vector, centroid)))
+        .fold((0, f32::NEG_INFINITY), |best, current| if current.1 > best.1 { current } else { best })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::vector::index::testing::{random_vectors, recall_at_k};
+    use crate::vector::index::{VectorIndex, VectorIndexDefinition};
+    use crate::vector::ivf::{IvfFlatIndex, IvfParams};
+    use crate::vector::quantization::Quantization;
+    use crate::vector::search::vector_search;
+
+    fn build(params: IvfParams, data: &[(u32, Vec<f32>)]) -> IvfFlatIndex<u32> {
+        let mut index = IvfFlatIndex::new(params);
+        for (id, vector) in data {
+            index.insert(*id, vector);
+        }
+        index
+    }
+
+    #[test]
+    fn test_recall_against_brute_force() {
+        let data = random_vectors(1000, 16, 7);
+        let queries = random_vectors(50, 16, 11);
+
+        let mut index = build(IvfParams::new(16, 1), &data);
+        assert!(index.is_trained());
+        assert_eq!(index.len(), 1000);
+        let low = recall_at_k(&index, &data, &queries, 10);
+
+        index.set_nprobe(6);
+        let high = recall_at_k(&index, &data, &queries, 10);
+        assert!(high >= 0.95, "recall@10 with nprobe 6: {}", high);
+        assert!(high >= low, "recall@10 with nprobe 1: {}, nprobe 6: {}", low, high);
+
+        // probing every list is exact
+        index.set_nprobe(16);
+        assert_eq!(recall_at_k(&index, &data, &queries, 10), 1.0);
+    }
+
+    #[test]
+    fn test_quantized_memory_and_rerank_candidates() {
+        let data = random_vectors(2000, 64, 17);
+        let queries = random_vectors(20, 64, 19);
+
+        let flat = build(IvfParams::new(8, 8), &data);
+        let scalar = build(IvfParams::new(8, 8).with_quantization(Quantization::Scalar), &data);
+        let product = build(
+            IvfParams::new(8, 8).with_quantization(Quantization::Product { subspaces: 16 }),
+            &data,
+        );
+
+        let full = flat.memory_usage() as f32;
+        let scalar_ratio = scalar.memory_usage() as f32 / full;
+        let product_ratio = product.memory_usage() as f32 / full;
+        assert!(scalar_ratio < 0.3, "scalar memory ratio {}", scalar_ratio);
+        assert!(product_ratio < scalar_ratio, "product memory ratio {}", product_ratio);
+
+        // the rerank depth candidates hold the exact top 10 for the full precision re-ranking
+        for (index, min_recall) in [(&scalar, 0.99), (&product, 0.5)] {
+            let depth = index.rerank_depth(10);
+            assert_eq!(depth, 40);
+            let mut found = 0;
+            for (_, query) in &queries {
+                let candidates: HashSet<u32> = index.search(query, depth, None).into_iter().map(|(id, _)| id).collect();
+                found += vector_search(query, &data, 10)
+                    .iter()
+                    .filter(|(id, _)| candidates.contains(id))
+                    .count();
+            }
+            let recall = found as f32 / (queries.len() * 10) as f32;
+            assert!(recall >= min_recall, "candidate recall {}", recall);
+        }
+
+        // vectors and queries shorter than the trained dimension are padded, not a panic
+        let mut product = product;
+        product.insert(5000, &queries[0].1[..20]);
+        assert!(!product.search(&queries[1].1[..7], 10, None).is_empty());
+    }
+
+    #[test]
+    fn test_training_and_retraining() {
+        let data = random_vectors(200, 8, 3);
+        let mut index = IvfFlatIndex::new(IvfParams::new(10, 2));
+
+        // untrained, all vectors are in one list and searches are exact
+        for (id, vector) in &data[..39] {
+            index.insert(*id, vector);
+        }
+        assert!(!index.is_trained());
+        assert_eq!(index.search(&data[5].1, 1, None)[0].0, 5);
+
+        index.insert(data[39].0, &data[39].1);
+        assert!(index.is_trained());
+        assert_eq!(index.trained_size, 40);
+
+        // doubling the collection drifts past the threshold and retrains
+        for (id, vector) in &data[40..80] {
+            index.insert(*id, vector);
+        }
+        assert_eq!(index.trained_size, 40);
+        index.insert(data[80].0, &data[80].1);
+        assert_eq!(index.trained_size, 81);
+        assert!(index.drift() < 0.01);
+    }
+
+    #[test]
+    fn test_quantized_index_is_rebuilt_from_full_vectors() {
+        let data = random_vectors(1000, 32, 23);
+        let queries = random_vectors(20, 32, 29);
+        let mut params = IvfParams::new(8, 8).with_quantization(Quantization::Scalar);
+        params.retrain_threshold = 0.4;
+        let mut index = IvfFlatIndex::new(params);
+        index.rebuild(data.clone());
+        assert!(index.is_trained());
+        assert_eq!(index.trained_size, 1000);
+        let recall = recall_at_k(&index, &data, &queries, 10);
+        let results: Vec<_> = queries.iter().map(|(_, query)| index.search(query, 10, None)).collect();
+
+        for _ in 0..2 {
+            // drifting past the threshold asks for a rebuild instead of retraining on the codes
+            for (id, _) in &data[..500] {
+                index.remove(id);
+            }
+            assert!(index.needs_rebuild());
+            assert_eq!(index.trained_size, 1000);
+            index.train();
+            assert_eq!(index.trained_size, 1000);
+
+            index.rebuild(data.clone());
+            assert!(!index.needs_rebuild());
+            assert_eq!(recall_at_k(&index, &data, &queries, 10), recall);
+            for ((_, query), expected) in queries.iter().zip(&results) {
+                assert_eq!(&index.search(query, 10, None), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_update_and_filter() {
+        let data = random_vectors(300, 8, 5);
+        let mut index = build(IvfParams::new(8, 2), &data);
+
+        index.remove(&3);
+        assert_eq!(index.len(), 299);
+        assert!(index.search(&data[3].1, 10, None).iter().all(|(id, _)| *id != 3));
+
+        index.insert(4, &data[5].1);
+        assert_eq!(index.len(), 299);
+        assert!(index.search(&data[5].1, 2, None).iter().any(|(id, _)| *id == 4));
+
+        // a filter keeps probing lists until top_k allowed vectors are found
+        let allowed: HashSet<u32> = [10, 21, 32].into_iter().collect();
+        let ids: HashSet<u32> = index
+            .search(&data[10].1, 10, Some(&allowed))
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, allowed);
+
+        // NaN vectors score NaN and are dropped instead of breaking the ordering
+        let mut nan = data[7].1.clone();
+        nan[0] = f32::NAN;
+        index.insert(7, &nan);
+        let results = index.search(&data[7].1, 300, None);
+        assert!(results.iter().all(|(id, score)| *id != 7 && !score.is_nan()));
+        assert!(results.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn test_save_and_load() -> anyhow::Result<()> {
+        let dir = std::path::Path::new("data/tests/ivf_snapshot");
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join("vectors.vidx");
+
+        let data = random_vectors(200, 8, 9);
+        let params = IvfParams::new(8, 2);
+        let index = build(params, &data);
+        index.save(&path, 42)?;
+
+        let definition = VectorIndexDefinition::IvfFlat(params);
+        let (loaded, watermark) = definition.load::<u32>(&path, 100)?.expect("snapshot");
+        assert_eq!(watermark, 42);
+        assert_eq!(loaded.len(), 200);
+        assert_eq!(loaded.search(&data[7].1, 5, None), index.search(&data[7].1, 5, None));
+
+        let other = VectorIndexDefinition::IvfFlat(IvfParams::new(4, 2));
+        assert!(other.load::<u32>(&path, 100)?.is_none());
+        Ok(())
+    }
+}
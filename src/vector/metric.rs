@@ -0,0 +1,116 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vector::similarity::{
+    CompactFloat, cosine_similarity, cosine_similarity_compact, dot_product, dot_product_compact, euclidean_distance,
+    euclidean_distance_compact, manhattan_distance, manhattan_distance_compact, sign_distance, sign_distance_compact,
+};
+
+// DistanceMetric is how a query vector is compared with stored vectors. Cosine and dot
+// product are similarities, higher is better; Euclidean, Manhattan and Hamming are
+// distances, lower is better. Hamming counts the values of different sign, the distance
+// of vectors stored as sign bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceMetric {
+    #[default]
+    Cosine,
+    DotProduct,
+    Euclidean,
+    Manhattan,
+    Hamming,
+}
+
+impl DistanceMetric {
+    // score compares two vectors: a similarity or a distance depending on the metric
+    pub fn score(&self, vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        match self {
+            DistanceMetric::Cosine => cosine_similarity(vec_a, vec_b),
+            DistanceMetric::DotProduct => dot_product(vec_a, vec_b),
+            DistanceMetric::Euclidean => euclidean_distance(vec_a, vec_b),
+            DistanceMetric::Manhattan => manhattan_distance(vec_a, vec_b),
+            DistanceMetric::Hamming => sign_distance(vec_a, vec_b),
+        }
+    }
+
+    // score_compact is score with the second vector in a 16 bit float format
+    pub fn score_compact<T: CompactFloat>(&self, vec_a: &[f32], vec_b: &[T]) -> f32 {
+        match self {
+            DistanceMetric::Cosine => cosine_similarity_compact(vec_a, vec_b),
+            DistanceMetric::DotProduct => dot_product_compact(vec_a, vec_b),
+            DistanceMetric::Euclidean => euclidean_distance_compact(vec_a, vec_b),
+            DistanceMetric::Manhattan => manhattan_distance_compact(vec_a, vec_b),
+            DistanceMetric::Hamming => sign_distance_compact(vec_a, vec_b),
+        }
+    }
+
+    pub fn higher_is_better(&self) -> bool {
+        matches!(self, DistanceMetric::Cosine | DistanceMetric::DotProduct)
+    }
+
+    // compare orders two scores best first
+    pub fn compare(&self, score_a: f32, score_b: f32) -> Ordering {
+        if self.higher_is_better() {
+            score_b.total_cmp(&score_a)
+        } else {
+            score_a.total_cmp(&score_b)
+        }
+    }
+
+    // similarity turns a score into a value where higher is better, for fusing rankings
+    pub fn similarity(&self, score: f32) -> f32 {
+        if self.higher_is_better() { score } else { -score }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use half::f16;
+
+    use crate::vector::metric::DistanceMetric;
+
+    #[test]
+    fn test_scores() {
+        let vec_a = [1.0, 2.0, 3.0];
+        let vec_b = [4.0, 0.0, -1.0];
+
+        let cosine = DistanceMetric::Cosine.score(&vec_a, &vec_b);
+        assert!((cosine - 1.0 / (14.0f32.sqrt() * 17.0f32.sqrt())).abs() < 1e-6);
+        assert_eq!(DistanceMetric::DotProduct.score(&vec_a, &vec_b), 1.0);
+        assert!((DistanceMetric::Euclidean.score(&vec_a, &vec_b) - 29.0f32.sqrt()).abs() < 1e-6);
+        assert_eq!(DistanceMetric::Manhattan.score(&vec_a, &vec_b), 9.0);
+        assert_eq!(DistanceMetric::Euclidean.score(&vec_a, &vec_a), 0.0);
+        assert_eq!(DistanceMetric::Hamming.score(&vec_a, &vec_b), 2.0);
+        assert!(!DistanceMetric::Hamming.higher_is_better());
+
+        // the values of vec_b are exact in f16
+        let compact: Vec<f16> = vec_b.iter().map(|value| f16::from_f32(*value)).collect();
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::DotProduct,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::Hamming,
+        ] {
+            assert_eq!(metric.score_compact(&vec_a, &compact), metric.score(&vec_a, &vec_b), "{:?}", metric);
+        }
+    }
+
+    #[test]
+    fn test_ordering_semantics() {
+        assert!(DistanceMetric::Cosine.higher_is_better());
+        assert!(DistanceMetric::DotProduct.higher_is_better());
+        assert!(!DistanceMetric::Euclidean.higher_is_better());
+        assert!(!DistanceMetric::Manhattan.higher_is_better());
+
+        assert_eq!(DistanceMetric::Cosine.compare(0.9, 0.1), Ordering::Less);
+        assert_eq!(DistanceMetric::Euclidean.compare(0.9, 0.1), Ordering::Greater);
+        assert!(DistanceMetric::Manhattan.similarity(1.0) > DistanceMetric::Manhattan.similarity(2.0));
+
+        assert_eq!(serde_json::to_string(&DistanceMetric::DotProduct).unwrap(), "\"dot_product\"");
+        assert_eq!(DistanceMetric::default(), DistanceMetric::Cosine);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::vector::similarity::{dot_product, normalize};
+
+// Mmr re-ranks the best fetch_k results of a vector search by maximal marginal relevance,
+// trading similarity to the query for diversity among the results
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mmr {
+    // weight of the relevance: 1 ranks by similarity to the query only, 0 by diversity only
+    pub lambda: f32,
+    // number of candidates to pick from, 4 times top_k when unset
+    pub fetch_k: Option<usize>,
+}
+
+impl Mmr {
+    pub fn new(lambda: f32) -> Self {
+        Self { lambda, fetch_k: None }
+    }
+
+    pub fn with_fetch_k(mut self, fetch_k: usize) -> Self {
+        self.fetch_k = Some(fetch_k);
+        self
+    }
+
+    // candidates returns how many results to fetch for top_k
+    pub fn candidates(&self, top_k: usize) -> usize {
+        self.fetch_k.unwrap_or(top_k.saturating_mul(4)).max(top_k)
+    }
+}
+
+// mmr_select picks top_k of the vectors one at a time, each maximizing
+//   lambda * sim(query, v) - (1 - lambda) * max sim(v, picked)
+// with cosine similarity, and returns their positions in the order picked. Equal values
+// go to the earlier vector, so candidates should be passed best first.
+pub fn mmr_select(query: &[f32], vectors: &[&[f32]], top_k: usize, lambda: f32) -> Vec<usize> {
+    let lambda = lambda.clamp(0.0, 1.0);
+    let query = normalize(query);
+    let units: Vec<Vec<f32>> = vectors.iter().map(|vec| normalize(vec)).collect();
+    let relevance: Vec<f32> = units.iter().map(|unit| dot_product(&query, unit)).collect();
+
+    // highest similarity of each candidate to the picked ones, None once picked
+    let mut redundancy: Vec<Option<f32>> = vec![Some(f32::NEG_INFINITY); units.len()];
+    let mut picked = Vec::with_capacity(top_k.min(units.len()));
+    while picked.len() < top_k {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, max_sim) in redundancy.iter().enumerate() {
+            let Some(max_sim) = max_sim else {
+                continue;
+            };
+            let penalty = if picked.is_empty() { 0.0 } else { *max_sim };
+            let value = lambda * relevance[i] - (1.0 - lambda) * penalty;
+            if value.is_nan() {
+                continue;
+            }
+            if best.is_none_or(|(_, best_value)| value > best_value) {
+                best = Some((i, value));
+            }
+        }
+        let Some((next, _)) = best else {
+            break;
+        };
+        redundancy[next] = None;
+        for (i, max_sim) in redundancy.iter_mut().enumerate() {
+            if let Some(max_sim) = max_sim {
+                *max_sim = max_sim.max(dot_product(&units[next], &units[i]));
+            }
+        }
+        picked.push(next);
+    }
+    picked
+}
+
+// mmr_rerank re-ranks the candidates, ordered best first, and returns the top_k picked
+// with their cosine similarity to the query
+pub fn mmr_rerank<K: Clone>(query: &[f32], candidates: &[(K, Vec<f32>)], top_k: usize, lambda: f32) -> Vec<(K, f32)> {
+    let vectors: Vec<&[f32]> = candidates.iter().map(|(_, vec)| vec.as_slice()).collect();
+    mmr_select(query, &vectors, top_k, lambda)
+        .into_iter()
+        .map(|i| {
+            let (id, vec) = &candidates[i];
+            (id.clone(), dot_product(&normalize(query), &normalize(vec)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vector::mmr::{Mmr, mmr_rerank, mmr_select};
+
+    #[test]
+    fn test_lambda_trades_relevance_for_diversity() {
+        let query = [1.0, 0.0];
+        let candidates = vec![
+            ("a", vec![1.0, 0.05]),
+            ("a_copy", vec![1.0, 0.06]),
+            ("b", vec![0.7, 0.7]),
+            ("c", vec![0.7, -0.7]),
+        ];
+
+        // pure relevance keeps the order of the similarities
+        let ids: Vec<&str> = mmr_rerank(&query, &candidates, 3, 1.0).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["a", "a_copy", "b"]);
+
+        // the near duplicate of the first pick is penalized
+        let ids: Vec<&str> = mmr_rerank(&query, &candidates, 3, 0.3).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["a", "c", "b"]);
+
+        let results = mmr_rerank(&query, &candidates, 1, 0.5);
+        assert_eq!(results[0].0, "a");
+        assert!((results[0].1 - 1.0 / 1.0025f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_select_bounds() {
+        let query = [1.0, 0.0];
+        let vectors: Vec<&[f32]> = vec![&[1.0, 0.0], &[f32::NAN, 0.0], &[0.0, 1.0]];
+        assert_eq!(mmr_select(&query, &vectors, 5, 0.5), vec![0, 2]);
+        assert!(mmr_select(&query, &vectors, 0, 0.5).is_empty());
+        assert!(mmr_select(&query, &[], 3, 0.5).is_empty());
+
+        assert_eq!(Mmr::new(0.5).candidates(5), 20);
+        assert_eq!(Mmr::new(0.5).with_fetch_k(2).candidates(5), 5);
+    }
+}
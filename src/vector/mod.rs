@@ -1,2 +1,14 @@
 pub mod similarity;
+pub mod metric;
+pub mod config;
+pub mod storage;
+pub mod embed;
 pub mod search;
+pub mod hybrid;
+pub mod mmr;
+pub mod multi;
+pub mod index;
+pub mod hnsw;
+pub mod ivf;
+pub mod quantization;
+pub mod eval;
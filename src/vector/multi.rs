@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::RepoKey;
+use crate::vector::index::{VectorIndex, VectorIndexDefinition};
+use crate::vector::metric::DistanceMetric;
+use crate::vector::search::select_top_k;
+use crate::vector::similarity::cosine_similarity;
+
+// ChunkId is the key of a chunk embedding in a vector index: the record id with the
+// position of the chunk in MultiVectorEmbedding::vectors
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChunkId<K> {
+    pub id: K,
+    pub chunk: u32,
+}
+
+impl<K: Display> Display for ChunkId<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}", self.id, self.chunk)
+    }
+}
+
+// ChunkAggregation is how the scores of the matched chunks of a document are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkAggregation {
+    // the score of the best chunk
+    #[default]
+    Max,
+    // the sum of the scores, favoring documents with many matching chunks
+    Sum,
+}
+
+// MultiVectorOptions tunes a search over chunk embeddings. The best chunk_k chunks are
+// matched and aggregated per document, with more when they hold fewer than top_k documents.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MultiVectorOptions {
+    pub aggregation: ChunkAggregation,
+    // number of chunks to match, 4 times top_k when unset
+    pub chunk_k: Option<usize>,
+    // lowest cosine similarity of the matched chunks
+    pub min_score: Option<f32>,
+}
+
+impl MultiVectorOptions {
+    pub fn new(aggregation: ChunkAggregation) -> Self {
+        Self {
+            aggregation,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_chunk_k(mut self, chunk_k: usize) -> Self {
+        self.chunk_k = Some(chunk_k);
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    // chunks returns how many chunks to match first for top_k documents
+    pub fn chunks(&self, top_k: usize) -> usize {
+        self.chunk_k.unwrap_or(top_k.saturating_mul(4)).max(top_k).max(1)
+    }
+}
+
+// DocumentMatch is a document found by its chunks, with the aggregated score and the
+// matched chunks with their cosine similarity, best first
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentMatch<T> {
+    pub item: T,
+    pub score: f32,
+    pub chunks: Vec<(usize, f32)>,
+}
+
+impl<T> DocumentMatch<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> DocumentMatch<U> {
+        DocumentMatch {
+            item: f(self.item),
+            score: self.score,
+            chunks: self.chunks,
+        }
+    }
+}
+
+// aggregate_chunks groups the chunk hits, ordered best first, by document and returns the
+// top_k documents by aggregated score. Equal scores keep the order of the best chunk of each document.
+pub fn aggregate_chunks<K>(hits: &[(ChunkId<K>, f32)], top_k: usize, aggregation: ChunkAggregation) -> Vec<DocumentMatch<K>>
+where
+    K: Eq + Hash + Clone,
+{
+    let mut positions: HashMap<&K, usize> = HashMap::new();
+    let mut documents: Vec<(&K, Vec<(usize, f32)>)> = Vec::new();
+    for (chunk_id, score) in hits {
+        let position = *positions.entry(&chunk_id.id).or_insert_with(|| {
+            documents.push((&chunk_id.id, Vec::new()));
+            documents.len() - 1
+        });
+        documents[position].1.push((chunk_id.chunk as usize, *score));
+    }
+    let scores = documents.iter().enumerate().map(|(position, (_, matched))| {
+        let score = match aggregation {
+            ChunkAggregation::Max => matched.iter().map(|(_, score)| *score).fold(f32::NEG_INFINITY, f32::max),
+            ChunkAggregation::Sum => matched.iter().map(|(_, score)| *score).sum(),
+        };
+        (position, score)
+    });
+    select_top_k(scores, top_k, DistanceMetric::Cosine, None)
+        .into_iter()
+        .map(|(position, score)| DocumentMatch {
+            item: documents[position].0.clone(),
+            score,
+            chunks: documents[position].1.clone(),
+        })
+        .collect()
+}
+
+// multi_vector_search compares the query with every chunk of the candidates by cosine
+// similarity, and aggregates the best chunks per document, see MultiVectorOptions
+pub fn multi_vector_search<K>(
+    query: &[f32],
+    candidates: &[(K, Vec<Vec<f32>>)],
+    top_k: usize,
+    options: &MultiVectorOptions,
+) -> Vec<DocumentMatch<K>>
+where
+    K: Eq + Hash + Clone,
+{
+    let scores: Vec<(ChunkId<&K>, f32)> = candidates
+        .iter()
+        .flat_map(|(id, vectors)| {
+            vectors.iter().enumerate().map(move |(chunk, vector)| {
+                let chunk_id = ChunkId { id, chunk: chunk as u32 };
+                (chunk_id, cosine_similarity(query, vector))
+            })
+        })
+        .collect();
+    // select the best chunk_k chunks, and more only while they hold fewer than top_k documents
+    let chunk_k = options.chunks(top_k);
+    let mut k = chunk_k;
+    let ranked = loop {
+        let positions = scores.iter().enumerate().map(|(position, (_, score))| (position, *score));
+        let ranked = select_top_k(positions, k, DistanceMetric::Cosine, options.min_score);
+        let documents: HashSet<&K> = ranked.iter().map(|(position, _)| scores[*position].0.id).collect();
+        if ranked.len() < k || documents.len() >= top_k {
+            break ranked;
+        }
+        k = k.saturating_mul(4);
+    };
+    let ranked: Vec<(ChunkId<K>, f32)> = ranked
+        .into_iter()
+        .map(|(position, score)| {
+            let chunk_id = &scores[position].0;
+            (ChunkId { id: chunk_id.id.clone(), chunk: chunk_id.chunk }, score)
+        })
+        .collect();
+
+    let matched = matched_chunks(&ranked, top_k, chunk_k);
+    aggregate_chunks(&ranked[..matched], top_k, options.aggregation)
+}
+
+// matched_chunks returns how many of the chunk hits, ordered best first, are matched: the
+// first chunk_k, extended until they hold top_k documents
+pub fn matched_chunks<K: Eq + Hash>(hits: &[(ChunkId<K>, f32)], top_k: usize, chunk_k: usize) -> usize {
+    let mut documents = HashSet::new();
+    for (position, (chunk_id, _)) in hits.iter().enumerate() {
+        if position >= chunk_k && documents.len() >= top_k {
+            return position;
+        }
+        documents.insert(&chunk_id.id);
+    }
+    hits.len()
+}
+
+// ChunkExtractor reads the chunk embeddings of a model, normally MultiVectorEmbedding::vectors
+pub type ChunkExtractor<M> = fn(&M) -> &[Vec<f32>];
+
+// ChunkIndex keeps a vector index of the chunk embeddings of the models of a collection,
+// with the number of chunks of each record to remove them
+#[derive(Debug)]
+pub struct ChunkIndex<K, M> {
+    definition: VectorIndexDefinition,
+    extract: ChunkExtractor<M>,
+    index: Box<dyn VectorIndex<ChunkId<K>>>,
+    chunks: HashMap<K, u32>,
+}
+
+impl<K: RepoKey, M> ChunkIndex<K, M> {
+    pub fn new(definition: VectorIndexDefinition, extract: ChunkExtractor<M>) -> Self {
+        Self {
+            index: definition.build(),
+            definition,
+            extract,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn definition(&self) -> &VectorIndexDefinition {
+        &self.definition
+    }
+
+    pub fn index(&self) -> &dyn VectorIndex<ChunkId<K>> {
+        self.index.as_ref()
+    }
+
+    // insert replaces the chunks of id with the chunks of model
+    pub fn insert(&mut self, id: K, model: &M) {
+        self.remove(&id);
+        let vectors = (self.extract)(model);
+        for (chunk, vector) in vectors.iter().enumerate() {
+            let chunk_id = ChunkId {
+                id: id.clone(),
+                chunk: chunk as u32,
+            };
+            self.index.insert(chunk_id, vector);
+        }
+        self.chunks.insert(id, vectors.len() as u32);
+    }
+
+    pub fn remove(&mut self, id: &K) {
+        let Some(count) = self.chunks.remove(id) else {
+            return;
+        };
+        for chunk in 0..count {
+            self.index.remove(&ChunkId { id: id.clone(), chunk });
+        }
+    }
+
+    // vectors reads the chunk embeddings of the model that the index holds
+    pub fn vectors<'m>(&self, model: &'m M) -> &'m [Vec<f32>] {
+        (self.extract)(model)
+    }
+
+    // rebuild replaces the chunks of the index with the chunk embeddings of each id
+    pub fn rebuild(&mut self, documents: Vec<(K, Vec<Vec<f32>>)>) {
+        self.chunks.clear();
+        let mut vectors = Vec::new();
+        for (id, chunks) in documents {
+            self.chunks.insert(id.clone(), chunks.len() as u32);
+            for (chunk, vector) in chunks.into_iter().enumerate() {
+                vectors.push((ChunkId { id: id.clone(), chunk: chunk as u32 }, vector));
+            }
+        }
+        self.index.rebuild(vectors);
+    }
+
+    // chunk_ids returns the keys of the chunks of the ids, to restrict a search to them
+    pub fn chunk_ids(&self, ids: &HashSet<K>) -> HashSet<ChunkId<K>> {
+        ids.iter()
+            .flat_map(|id| {
+                let count = self.chunks.get(id).copied().unwrap_or(0);
+                (0..count).map(move |chunk| ChunkId { id: id.clone(), chunk })
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.chunks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vector::hnsw::HnswParams;
+    use crate::vector::index::VectorIndexDefinition;
+    use crate::vector::multi::{
+        ChunkAggregation, ChunkId, ChunkIndex, MultiVectorOptions, aggregate_chunks, matched_chunks, multi_vector_search,
+    };
+
+    fn hit(id: &str, chunk: u32, score: f32) -> (ChunkId<String>, f32) {
+        (ChunkId { id: id.to_string(), chunk }, score)
+    }
+
+    #[test]
+    fn test_aggregate_max_and_sum() {
+        let hits = vec![hit("a", 2, 0.9), hit("b", 0, 0.8), hit("b", 1, 0.7), hit("a", 0, 0.1)];
+
+        let documents = aggregate_chunks(&hits, 2, ChunkAggregation::Max);
+        assert_eq!(documents[0].item, "a");
+        assert_eq!(documents[0].score, 0.9);
+        assert_eq!(documents[0].chunks, vec![(2, 0.9), (0, 0.1)]);
+        assert_eq!(documents[1].item, "b");
+
+        let documents = aggregate_chunks(&hits, 2, ChunkAggregation::Sum);
+        assert_eq!(documents[0].item, "b");
+        assert!((documents[0].score - 1.5).abs() < 1e-6);
+        assert_eq!(documents[0].chunks, vec![(0, 0.8), (1, 0.7)]);
+    }
+
+    #[test]
+    fn test_matched_chunks() {
+        let hits = vec![hit("a", 0, 0.9), hit("a", 1, 0.8), hit("b", 0, 0.7), hit("a", 2, 0.6), hit("c", 0, 0.5)];
+        assert_eq!(matched_chunks(&hits, 1, 2), 2);
+        assert_eq!(matched_chunks(&hits, 2, 1), 3);
+        assert_eq!(matched_chunks(&hits, 2, 4), 4);
+        assert_eq!(matched_chunks(&hits, 4, 1), 5);
+    }
+
+    #[test]
+    fn test_search_widens_to_top_k_documents() {
+        let query = [1.0, 0.0];
+        let candidates = vec![
+            ("a".to_string(), vec![vec![1.0, 0.0], vec![1.0, 0.1], vec![1.0, 0.2], vec![0.0, 1.0]]),
+            ("b".to_string(), vec![vec![0.5, 0.5]]),
+            ("c".to_string(), vec![]),
+        ];
+        // the three best chunks belong to a
+        let options = MultiVectorOptions::new(ChunkAggregation::Max).with_chunk_k(3);
+        let documents = multi_vector_search(&query, &candidates, 2, &options);
+        let ids: Vec<&str> = documents.iter().map(|document| document.item.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(documents[0].chunks.iter().map(|(chunk, _)| *chunk).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        let options = options.with_min_score(0.8);
+        let documents = multi_vector_search(&query, &candidates, 2, &options);
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_search_keeps_chunk_order_on_ties() {
+        let query = [1.0, 0.0];
+        let candidates = vec![("a".to_string(), vec![vec![1.0, 0.0]; 12]), ("b".to_string(), vec![vec![1.0, 0.0]])];
+        let options = MultiVectorOptions::new(ChunkAggregation::Max);
+        let documents = multi_vector_search(&query, &candidates, 1, &options);
+        assert_eq!(documents[0].item, "a");
+        assert_eq!(documents[0].chunks.iter().map(|(chunk, _)| *chunk).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        // equal documents keep the order of the candidates
+        let documents = multi_vector_search(&query, &candidates, 2, &options.with_chunk_k(2));
+        let ids: Vec<&str> = documents.iter().map(|document| document.item.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(documents[0].chunks.len(), 12);
+    }
+
+    #[test]
+    fn test_chunk_index_replaces_chunks() {
+        let mut index = ChunkIndex::<String, Vec<Vec<f32>>>::new(
+            VectorIndexDefinition::Hnsw(HnswParams::default()),
+            |vectors| vectors.as_slice(),
+        );
+        index.insert("a".to_string(), &vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]]);
+        index.insert("b".to_string(), &vec![vec![0.9, 0.1]]);
+        assert_eq!(index.index().len(), 4);
+
+        index.insert("a".to_string(), &vec![vec![0.0, 1.0]]);
+        assert_eq!(index.index().len(), 2);
+        let hits = index.index().search(&[1.0, 0.0], 1, None);
+        assert_eq!(hits[0].0, ChunkId { id: "b".to_string(), chunk: 0 });
+
+        let allowed = index.chunk_ids(&["a".to_string()].into_iter().collect());
+        let hits = index.index().search(&[1.0, 0.0], 1, Some(&allowed));
+        assert_eq!(hits[0].0.to_string(), "a#0");
+
+        index.remove(&"a".to_string());
+        assert_eq!(index.index().len(), 1);
+    }
+}
@@ -0,0 +1,358 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vector::index::next_random;
+use crate::vector::similarity::{dot_product, dot_product_u8, squared_euclidean, table_lookup_sum};
+
+// Centroids per product quantization subspace, so a code fits in a byte
+const PQ_CENTROIDS: usize = 256;
+// k-means iterations per subspace codebook
+const PQ_ITERATIONS: usize = 10;
+
+// Quantization selects how a vector index stores its vectors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Quantization {
+    // Full precision f32 values
+    #[default]
+    None,
+    // One byte per dimension, scaled between the per-dimension minimum and maximum
+    Scalar,
+    // One byte per subspace, the nearest of 256 centroids trained for that subspace
+    Product { subspaces: usize },
+}
+
+// ScalarQuantizer maps each dimension linearly onto 0..=255
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScalarQuantizer {
+    min: Vec<f32>,
+    step: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    pub fn train(vectors: &[&[f32]]) -> Self {
+        let dimension = vectors.first().map_or(0, |v| v.len());
+        let mut min = vec![f32::INFINITY; dimension];
+        let mut max = vec![f32::NEG_INFINITY; dimension];
+        for vector in vectors {
+            for (i, value) in vector.iter().enumerate().take(dimension) {
+                min[i] = min[i].min(*value);
+                max[i] = max[i].max(*value);
+            }
+        }
+        let step = min.iter().zip(&max).map(|(lo, hi)| ((hi - lo) / 255.0).max(f32::EPSILON)).collect();
+        Self { min, step }
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        vector
+            .iter()
+            .zip(self.min.iter().zip(&self.step))
+            .map(|(value, (min, step))| ((value - min) / step).round().clamp(0.0, 255.0) as u8)
+            .collect()
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes
+            .iter()
+            .zip(self.min.iter().zip(&self.step))
+            .map(|(code, (min, step))| min + *code as f32 * step)
+            .collect()
+    }
+
+    // scorer folds the scaling into the query: dot(q, min + code * step) = dot(q, min) + dot(q * step, code)
+    fn scorer(&self, query: &[f32]) -> QueryScorer {
+        QueryScorer::Scalar {
+            bias: dot_product(query, &self.min),
+            weights: query.iter().zip(&self.step).map(|(q, step)| q * step).collect(),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        (self.min.len() + self.step.len()) * size_of::<f32>()
+    }
+}
+
+// ProductQuantizer splits vectors into subspaces and encodes each part as the position
+// of its nearest centroid in the subspace codebook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductQuantizer {
+    // Start of each subspace, followed by the dimension
+    bounds: Vec<usize>,
+    // Centroids of each subspace
+    codebooks: Vec<Vec<Vec<f32>>>,
+}
+
+impl ProductQuantizer {
+    pub fn train(vectors: &[&[f32]], subspaces: usize, seed: u64) -> Self {
+        let dimension = vectors.first().map_or(0, |v| v.len());
+        let subspaces = subspaces.clamp(1, dimension.max(1));
+        let bounds: Vec<usize> = (0..=subspaces).map(|i| i * dimension / subspaces).collect();
+        let codebooks = bounds
+            .windows(2)
+            .enumerate()
+            .map(|(subspace, range)| {
+                let parts: Vec<Cow<[f32]>> = vectors.iter().map(|v| part(v, range)).collect();
+                let parts: Vec<&[f32]> = parts.iter().map(|part| part.as_ref()).collect();
+                kmeans_l2(&parts, PQ_CENTROIDS, seed.wrapping_add(subspace as u64))
+            })
+            .collect();
+        Self { bounds, codebooks }
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        self.bounds
+            .windows(2)
+            .zip(&self.codebooks)
+            .map(|(range, codebook)| nearest_l2(codebook, &part(vector, range)) as u8)
+            .collect()
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes
+            .iter()
+            .zip(&self.codebooks)
+            .flat_map(|(code, codebook)| codebook[*code as usize].iter().copied())
+            .collect()
+    }
+
+    // scorer precomputes the dot product of each query part with every centroid of its subspace
+    fn scorer(&self, query: &[f32]) -> QueryScorer {
+        let mut table = vec![0.0; self.codebooks.len() * PQ_CENTROIDS];
+        for (subspace, (range, codebook)) in self.bounds.windows(2).zip(&self.codebooks).enumerate() {
+            let part = part(query, range);
+            for (code, centroid) in codebook.iter().enumerate() {
+                table[subspace * PQ_CENTROIDS + code] = dot_product(&part, centroid);
+            }
+        }
+        QueryScorer::Product { table }
+    }
+
+    fn memory_usage(&self) -> usize {
+        let centroids: usize = self.codebooks.iter().flatten().map(|c| c.len()).sum();
+        centroids * size_of::<f32>() + self.bounds.len() * size_of::<usize>()
+    }
+}
+
+// part is the values of the vector in the subspace range, padded with zeros when the vector
+// is shorter than the trained dimension
+fn part<'a>(vector: &'a [f32], range: &[usize]) -> Cow<'a, [f32]> {
+    if range[1] <= vector.len() {
+        return Cow::Borrowed(&vector[range[0]..range[1]]);
+    }
+    let mut padded = vector[range[0].min(vector.len())..].to_vec();
+    padded.resize(range[1] - range[0], 0.0);
+    Cow::Owned(padded)
+}
+
+// Quantizer encodes vectors into byte codes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Quantizer {
+    Scalar(ScalarQuantizer),
+    Product(ProductQuantizer),
+}
+
+impl Quantizer {
+    // train fits a quantizer to the vectors, None without quantization or vectors
+    pub fn train(quantization: Quantization, vectors: &[&[f32]], seed: u64) -> Option<Self> {
+        if vectors.is_empty() {
+            return None;
+        }
+        match quantization {
+            Quantization::None => None,
+            Quantization::Scalar => Some(Quantizer::Scalar(ScalarQuantizer::train(vectors))),
+            Quantization::Product { subspaces } => {
+                Some(Quantizer::Product(ProductQuantizer::train(vectors, subspaces, seed)))
+            }
+        }
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        match self {
+            Quantizer::Scalar(quantizer) => quantizer.encode(vector),
+            Quantizer::Product(quantizer) => quantizer.encode(vector),
+        }
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        match self {
+            Quantizer::Scalar(quantizer) => quantizer.decode(codes),
+            Quantizer::Product(quantizer) => quantizer.decode(codes),
+        }
+    }
+
+    // scorer prepares the asymmetric comparison of a full precision query with codes
+    pub fn scorer(&self, query: &[f32]) -> QueryScorer {
+        match self {
+            Quantizer::Scalar(quantizer) => quantizer.scorer(query),
+            Quantizer::Product(quantizer) => quantizer.scorer(query),
+        }
+    }
+
+    // memory_usage is the size of the trained parameters in bytes
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Quantizer::Scalar(quantizer) => quantizer.memory_usage(),
+            Quantizer::Product(quantizer) => quantizer.memory_usage(),
+        }
+    }
+}
+
+// QueryScorer computes the dot product of a query with encoded vectors without decoding them
+#[derive(Debug, Clone)]
+pub enum QueryScorer {
+    Scalar { bias: f32, weights: Vec<f32> },
+    Product { table: Vec<f32> },
+}
+
+impl QueryScorer {
+    pub fn score(&self, codes: &[u8]) -> f32 {
+        match self {
+            QueryScorer::Scalar { bias, weights } => bias + dot_product_u8(weights, codes),
+            QueryScorer::Product { table } => table_lookup_sum(table, PQ_CENTROIDS, codes),
+        }
+    }
+}
+
+// StoredVector is a vector held by an index, in full precision or encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StoredVector {
+    Full(Vec<f32>),
+    Codes(Vec<u8>),
+}
+
+impl StoredVector {
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            StoredVector::Full(vector) => vector.len() * size_of::<f32>(),
+            StoredVector::Codes(codes) => codes.len(),
+        }
+    }
+}
+
+// kmeans_l2 clusters vectors by euclidean distance, seeded with k-means++. With fewer
+// vectors than k, every vector is a centroid.
+fn kmeans_l2(vectors: &[&[f32]], k: usize, seed: u64) -> Vec<Vec<f32>> {
+    if vectors.len() <= k {
+        return vectors.iter().map(|v| v.to_vec()).collect();
+    }
+
+    let mut rng = seed;
+    let first = ((next_random(&mut rng) * vectors.len() as f64) as usize).min(vectors.len() - 1);
+    let mut centroids = vec![vectors[first].to_vec()];
+    let mut distances: Vec<f32> = vectors.iter().map(|v| squared_euclidean(v, &centroids[0])).collect();
+    while centroids.len() < k {
+        let total: f32 = distances.iter().sum();
+        let chosen = if total > 0.0 {
+            let mut target = next_random(&mut rng) as f32 * total;
+            distances
+                .iter()
+                .position(|d| {
+                    target -= d;
+                    target <= 0.0
+                })
+                .unwrap_or(vectors.len() - 1)
+        } else {
+            centroids.len()
+        };
+        centroids.push(vectors[chosen].to_vec());
+        let last = centroids.len() - 1;
+        for (distance, vector) in distances.iter_mut().zip(vectors) {
+            *distance = distance.min(squared_euclidean(vector, &centroids[last]));
+        }
+    }
+
+    let dimension = vectors[0].len();
+    for _ in 0..PQ_ITERATIONS {
+        let mut sums = vec![vec![0.0f32; dimension]; k];
+        let mut counts = vec![0usize; k];
+        for vector in vectors {
+            let nearest = nearest_l2(&centroids, vector);
+            counts[nearest] += 1;
+            for (sum, value) in sums[nearest].iter_mut().zip(vector.iter()) {
+                *sum += value;
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *centroid = sum.iter().map(|s| s / count as f32).collect();
+            }
+        }
+    }
+    centroids
+}
+
+fn nearest_l2(centroids: &[Vec<f32>], vector: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(position, centroid)| (position, squared_euclidean(vector, centroid)))
+        .fold((0, f32::INFINITY), |best, current| if current.1 < best.1 { current } else { best })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vector::index::testing::random_vectors;
+    use crate::vector::quantization::{Quantization, Quantizer};
+    use crate::vector::similarity::{dot_product, normalize};
+
+    fn sample() -> Vec<Vec<f32>> {
+        random_vectors(600, 32, 13).into_iter().map(|(_, v)| normalize(&v)).collect()
+    }
+
+    #[test]
+    fn test_scalar_quantization() {
+        let vectors = sample();
+        let refs: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let quantizer = Quantizer::train(Quantization::Scalar, &refs, 0).unwrap();
+
+        let codes = quantizer.encode(&vectors[0]);
+        assert_eq!(codes.len(), 32);
+        let decoded = quantizer.decode(&codes);
+        let error = decoded.iter().zip(&vectors[0]).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(error < 0.01, "max error {}", error);
+
+        // the asymmetric score is the dot product with the decoded vector
+        let query = &vectors[1];
+        let score = quantizer.scorer(query).score(&codes);
+        assert!((score - dot_product(query, &decoded)).abs() < 1e-4);
+        assert!((score - dot_product(query, &vectors[0])).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_product_quantization() {
+        let vectors = sample();
+        let refs: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        let quantizer = Quantizer::train(Quantization::Product { subspaces: 8 }, &refs, 0).unwrap();
+
+        let codes = quantizer.encode(&vectors[0]);
+        assert_eq!(codes.len(), 8);
+        let decoded = quantizer.decode(&codes);
+        assert_eq!(decoded.len(), 32);
+
+        let query = &vectors[1];
+        let score = quantizer.scorer(query).score(&codes);
+        assert!((score - dot_product(query, &decoded)).abs() < 1e-4);
+        assert!((score - dot_product(query, &vectors[0])).abs() < 0.1);
+
+        assert!(Quantizer::train(Quantization::None, &refs, 0).is_none());
+    }
+
+    #[test]
+    fn test_shorter_vectors_are_padded() {
+        let vectors = sample();
+        let short = &vectors[0][..13];
+        let mut refs: Vec<&[f32]> = vectors.iter().map(|v| v.as_slice()).collect();
+        refs.push(short);
+        for quantization in [Quantization::Scalar, Quantization::Product { subspaces: 8 }] {
+            let quantizer = Quantizer::train(quantization, &refs, 0).unwrap();
+            let codes = quantizer.encode(short);
+            let score = quantizer.scorer(short).score(&codes);
+            assert!((score - dot_product(short, short)).abs() < 0.1, "{:?} {}", quantization, score);
+            assert!(quantizer.scorer(&vectors[1]).score(&codes).is_finite());
+        }
+    }
+}
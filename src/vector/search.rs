@@ -1,4 +1,44 @@
-use crate::vector::similarity::cosine_similarity;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use rayon::prelude::*;
+
+use crate::vector::metric::DistanceMetric;
+use crate::vector::mmr::{Mmr, mmr_select};
+use crate::vector::storage::{OwnedValues, QueryVector, StoredValues};
+
+// VectorSearchOptions tunes a vector search. Without a metric, searches use cosine
+// similarity, or the metric of the collection for repository searches.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VectorSearchOptions {
+    pub metric: Option<DistanceMetric>,
+    // lowest similarity, or highest distance, of the results
+    pub min_score: Option<f32>,
+    // diversifies the results, see Mmr
+    pub mmr: Option<Mmr>,
+}
+
+impl VectorSearchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_metric(mut self, metric: DistanceMetric) -> Self {
+        self.metric = Some(metric);
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    pub fn with_mmr(mut self, mmr: Mmr) -> Self {
+        self.mmr = Some(mmr);
+        self
+    }
+}
 
 // vector_search uses cosine_simularity to get the score. Returns truncated top_k.
 pub fn vector_search<K: Clone>(
@@ -6,23 +46,230 @@ pub fn vector_search<K: Clone>(
     candidates: &[(K, Vec<f32>)],
     top_k: usize,
 ) -> Vec<(K, f32)> {
-    // Iterate the embeddings, perform cosine_similarity and return vector of id(String) and score
-    let mut scores: Vec<(K, f32)> = candidates
+    vector_search_with_options(vec_a, candidates, top_k, &VectorSearchOptions::new())
+}
+
+// vector_search_with_metric scores the candidates with the metric and returns the top_k best
+// first: highest similarities, or lowest distances
+pub fn vector_search_with_metric<K: Clone>(
+    vec_a: &[f32],
+    candidates: &[(K, Vec<f32>)],
+    top_k: usize,
+    metric: DistanceMetric,
+) -> Vec<(K, f32)> {
+    vector_search_with_options(vec_a, candidates, top_k, &VectorSearchOptions::new().with_metric(metric))
+}
+
+// vector_search_with_options scores the candidates and selects the top_k best, see select_top_k.
+// With mmr, the top_k are picked by maximal marginal relevance from the best candidates.
+pub fn vector_search_with_options<K: Clone>(
+    vec_a: &[f32],
+    candidates: &[(K, Vec<f32>)],
+    top_k: usize,
+    options: &VectorSearchOptions,
+) -> Vec<(K, f32)> {
+    let metric = options.metric.unwrap_or_default();
+    let fetch_k = options.mmr.map_or(top_k, |mmr| mmr.candidates(top_k));
+    let scores = candidates.iter().enumerate().map(|(i, (id, vec))| (Indexed(i, id), metric.score(vec_a, vec)));
+    let best = select_top_k(scores, fetch_k, metric, options.min_score);
+    finish_search(vec_a, best, candidates, top_k, options.mmr)
+}
+
+// CandidateVector is a candidate vector of vector_search_batch, in f32 or in the format of
+// the vector store
+pub trait CandidateVector: Sync {
+    // score compares the query with the vector by the metric, see QueryVector::score
+    fn score(&self, query: &QueryVector, metric: DistanceMetric) -> f32;
+
+    // values returns the vector as f32, for maximal marginal relevance
+    fn values(&self) -> Cow<'_, [f32]>;
+}
+
+impl CandidateVector for Vec<f32> {
+    fn score(&self, query: &QueryVector, metric: DistanceMetric) -> f32 {
+        query.score(metric, &StoredValues::F32(self))
+    }
+
+    fn values(&self) -> Cow<'_, [f32]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl CandidateVector for OwnedValues {
+    fn score(&self, query: &QueryVector, metric: DistanceMetric) -> f32 {
+        query.score(metric, &self.as_values())
+    }
+
+    fn values(&self) -> Cow<'_, [f32]> {
+        match self {
+            OwnedValues::F32(values) => Cow::Borrowed(values),
+            values => Cow::Owned(values.as_values().to_f32()),
+        }
+    }
+}
+
+// BATCH_CHUNK is the number of candidates a thread scores against all the queries at once
+const BATCH_CHUNK: usize = 1024;
+
+// vector_search_batch is vector_search_with_options for many queries. The candidates are
+// read once: they are split in chunks scored in parallel against every query, and the best
+// of each chunk are merged per query. Stored candidates are scored in their format.
+pub fn vector_search_batch<K: Clone + Sync, V: CandidateVector>(
+    queries: &[Vec<f32>],
+    candidates: &[(K, V)],
+    top_k: usize,
+    options: &VectorSearchOptions,
+) -> Vec<Vec<(K, f32)>> {
+    let metric = options.metric.unwrap_or_default();
+    let fetch_k = options.mmr.map_or(top_k, |mmr| mmr.candidates(top_k));
+    let query_vectors: Vec<QueryVector> = queries.iter().map(|query| QueryVector::new(query)).collect();
+    let empty = || -> Vec<Vec<(Indexed<K>, f32)>> { queries.iter().map(|_| Vec::new()).collect() };
+    let best = candidates
+        .par_chunks(BATCH_CHUNK)
+        .enumerate()
+        .map(|(position, chunk)| {
+            let mut scores: Vec<Vec<(Indexed<K>, f32)>> =
+                queries.iter().map(|_| Vec::with_capacity(chunk.len())).collect();
+            for (i, (id, vec)) in chunk.iter().enumerate() {
+                let id = Indexed(position * BATCH_CHUNK + i, id);
+                for (query, scores) in query_vectors.iter().zip(scores.iter_mut()) {
+                    scores.push((id, vec.score(query, metric)));
+                }
+            }
+            scores
+                .into_iter()
+                .map(|scores| select_top_k(scores, fetch_k, metric, options.min_score))
+                .collect()
+        })
+        .reduce(empty, |best_a, best_b| {
+            best_a
+                .into_iter()
+                .zip(best_b)
+                .map(|(a, b)| select_top_k(a.into_iter().chain(b), fetch_k, metric, None))
+                .collect()
+        });
+    queries
+        .iter()
+        .zip(best)
+        .map(|(query, best)| finish_search(query, best, candidates, top_k, options.mmr))
+        .collect()
+}
+
+// finish_search turns the best candidates into the results, picking them by maximal
+// marginal relevance with mmr
+fn finish_search<K: Clone, V: CandidateVector>(
+    vec_a: &[f32],
+    best: Vec<(Indexed<K>, f32)>,
+    candidates: &[(K, V)],
+    top_k: usize,
+    mmr: Option<Mmr>,
+) -> Vec<(K, f32)> {
+    let Some(mmr) = mmr else {
+        return best.into_iter().map(|(Indexed(_, id), score)| (id.clone(), score)).collect();
+    };
+    let values: Vec<Cow<[f32]>> = best.iter().map(|(Indexed(i, _), _)| candidates[*i].1.values()).collect();
+    let vectors: Vec<&[f32]> = values.iter().map(|values| values.as_ref()).collect();
+    mmr_select(vec_a, &vectors, top_k, mmr.lambda)
         .into_iter()
-        .map(|(id, vec)| (id.clone(), cosine_similarity(vec_a, vec)))
-        .collect();
+        .map(|i| (best[i].0.1.clone(), best[i].1))
+        .collect()
+}
 
-    // Sort descending by score (highest first)
-    scores.sort_by(|a, b: &(K, f32)| b.1.partial_cmp(&a.1).unwrap());
+// Indexed is an id with the position of its candidate
+struct Indexed<'a, K>(usize, &'a K);
 
-    // truncate by top_k
-    scores.truncate(top_k);
-    scores
+impl<K> Clone for Indexed<'_, K> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
+impl<K> Copy for Indexed<'_, K> {}
+
+// select_top_k keeps the top_k best scores for the metric, best first, in a heap of top_k
+// entries. NaN scores and scores worse than min_score are dropped. Equal scores keep the
+// order of the scores given.
+pub fn select_top_k<K>(
+    scores: impl IntoIterator<Item = (K, f32)>,
+    top_k: usize,
+    metric: DistanceMetric,
+    min_score: Option<f32>,
+) -> Vec<(K, f32)> {
+    let scores = scores.into_iter().enumerate().map(|(position, (id, score))| (id, score, position));
+    select_top_k_by_position(scores, top_k, metric, min_score)
+}
+
+// select_top_k_by_position is select_top_k for scores with a position, such as the offset of
+// their record in the log, that orders equal scores
+pub fn select_top_k_by_position<K, P: Ord>(
+    scores: impl IntoIterator<Item = (K, f32, P)>,
+    top_k: usize,
+    metric: DistanceMetric,
+    min_score: Option<f32>,
+) -> Vec<(K, f32)> {
+    if top_k == 0 {
+        return Vec::new();
+    }
+    let scores = scores.into_iter();
+    let mut heap: BinaryHeap<Ranked<K, P>> = BinaryHeap::with_capacity(top_k.min(scores.size_hint().0).saturating_add(1));
+    for (id, score, position) in scores {
+        if score.is_nan() || min_score.is_some_and(|min| metric.compare(score, min) == Ordering::Greater) {
+            continue;
+        }
+        let ranked = Ranked { id, score, metric, position };
+        if heap.len() < top_k {
+            heap.push(ranked);
+        } else if heap.peek().is_some_and(|worst| ranked < *worst) {
+            heap.pop();
+            heap.push(ranked);
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|ranked| (ranked.id, ranked.score))
+        .collect()
+}
+
+// Ranked orders scored ids best first, then by position, so the top of the max-heap is the
+// worst kept
+struct Ranked<K, P> {
+    id: K,
+    score: f32,
+    metric: DistanceMetric,
+    position: P,
+}
+
+impl<K, P: Ord> Ord for Ranked<K, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.metric
+            .compare(self.score, other.score)
+            .then_with(|| self.position.cmp(&other.position))
+    }
+}
+
+impl<K, P: Ord> PartialOrd for Ranked<K, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, P: Ord> PartialEq for Ranked<K, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K, P: Ord> Eq for Ranked<K, P> {}
+
 #[cfg(test)]
 mod tests {
-    use crate::vector::search::vector_search;
+    use crate::vector::index::testing::random_vectors;
+    use crate::vector::metric::DistanceMetric;
+    use crate::vector::mmr::Mmr;
+    use crate::vector::search::{
+        VectorSearchOptions, select_top_k, vector_search, vector_search_batch, vector_search_with_metric,
+        vector_search_with_options,
+    };
 
 
     #[test]
@@ -41,4 +288,105 @@ mod tests {
         assert_eq!(results[1].0, 2);
         println!("{:?}", results);
     }
+
+    #[test]
+    fn test_vector_search_with_metrics() {
+        let query = vec![1.0, 1.0];
+        let candidates = vec![
+            (1, vec![10.0, 10.0]),
+            (2, vec![1.0, 0.5]),
+            (3, vec![0.0, 2.0]),
+            (4, vec![-1.0, -1.0]),
+        ];
+        let ids = |metric| -> Vec<i32> {
+            vector_search_with_metric(&query, &candidates, 4, metric)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect()
+        };
+
+        // 1 points the same way as the query but lies far from it, 2 is the closest
+        assert_eq!(ids(DistanceMetric::Cosine), vec![1, 2, 3, 4]);
+        assert_eq!(ids(DistanceMetric::DotProduct), vec![1, 3, 2, 4]);
+        assert_eq!(ids(DistanceMetric::Euclidean), vec![2, 3, 4, 1]);
+        assert_eq!(ids(DistanceMetric::Manhattan), vec![2, 3, 4, 1]);
+
+        let results = vector_search_with_metric(&query, &candidates, 1, DistanceMetric::Manhattan);
+        assert_eq!(results, vec![(2, 0.5)]);
+    }
+
+    #[test]
+    fn test_nan_scores_are_dropped() {
+        let query = vec![1.0, 0.0];
+        let candidates = vec![(1, vec![f32::NAN, 0.0]), (2, vec![0.5, 0.5]), (3, vec![1.0, 0.1])];
+        for metric in [DistanceMetric::Cosine, DistanceMetric::Euclidean] {
+            let ids: Vec<i32> = vector_search_with_metric(&query, &candidates, 3, metric)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(ids, vec![3, 2]);
+        }
+    }
+
+    #[test]
+    fn test_min_score_and_ties() {
+        let query = vec![1.0, 0.0];
+        let candidates = vec![
+            ("c", vec![1.0, 1.0]),
+            ("b", vec![2.0, 0.0]),
+            ("a", vec![1.0, -1.0]),
+            ("d", vec![0.0, 1.0]),
+        ];
+
+        // b is the most similar; c and a tie and keep the order of the candidates; d is orthogonal
+        let results = vector_search(&query, &candidates, 4);
+        let ids: Vec<&str> = results.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec!["b", "c", "a", "d"]);
+
+        let options = VectorSearchOptions::new().with_min_score(0.5);
+        let results = vector_search_with_options(&query, &candidates, 4, &options);
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec!["b", "c", "a"]);
+
+        // for a distance, min_score is the farthest kept
+        let options = VectorSearchOptions::new().with_metric(DistanceMetric::Euclidean).with_min_score(1.0);
+        let results = vector_search_with_options(&query, &candidates, 4, &options);
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec!["c", "b", "a"]);
+
+        // a tie on the last kept score keeps the earliest candidate
+        let results = vector_search(&query, &candidates, 2);
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_select_top_k_matches_sorting() {
+        let scores: Vec<(u32, f32)> = (0..500).map(|i| (i, ((i * 37) % 101) as f32 / 7.0)).collect();
+        for metric in [DistanceMetric::DotProduct, DistanceMetric::Manhattan] {
+            let mut sorted = scores.clone();
+            // a stable sort keeps equal scores in the order given
+            sorted.sort_by(|a, b| metric.compare(a.1, b.1));
+            sorted.truncate(10);
+            assert_eq!(select_top_k(scores.clone(), 10, metric, None), sorted);
+        }
+        assert!(select_top_k(scores, 0, DistanceMetric::Cosine, None).is_empty());
+    }
+
+    #[test]
+    fn test_batch_matches_single_queries() {
+        let candidates = random_vectors(3000, 8, 11);
+        let queries: Vec<Vec<f32>> = random_vectors(5, 8, 13).into_iter().map(|(_, vector)| vector).collect();
+        let options = [
+            VectorSearchOptions::new(),
+            VectorSearchOptions::new().with_metric(DistanceMetric::Manhattan).with_min_score(2.0),
+            VectorSearchOptions::new().with_mmr(Mmr::new(0.5)),
+        ];
+        for options in options {
+            let batch = vector_search_batch(&queries, &candidates, 10, &options);
+            assert_eq!(batch.len(), queries.len());
+            for (query, results) in queries.iter().zip(batch) {
+                assert_eq!(results, vector_search_with_options(query, &candidates, 10, &options));
+            }
+        }
+        assert!(vector_search_batch(&[], &candidates, 10, &VectorSearchOptions::new()).is_empty());
+        assert_eq!(vector_search_batch::<u32, Vec<f32>>(&queries, &[], 10, &VectorSearchOptions::new()), vec![vec![]; 5]);
+    }
 }
@@ -1,22 +1,515 @@
+// Kernels: dot_product, squared_euclidean, manhattan_distance and cosine_similarity run on
+// AVX2 with FMA when the cpu supports them, detected at runtime, and otherwise on portable
+// code accumulating eight lanes at a time, which the compiler can vectorize. Slices of
+// different lengths are compared over the shorter one.
 
+use half::slice::HalfFloatSliceExt;
+use half::{bf16, f16};
 
-// cosine_similarity - dot product provides the cosing of the anglet between 2 vectors
+// cosine_similarity - dot product provides the cosing of the anglet between 2 vectors.
+// The dot product and both magnitudes are accumulated in a single pass.
 pub fn cosine_similarity(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    let (dot, norm_a, norm_b) = cosine_terms(vec_a, vec_b);
+    cosine(dot, norm_a, norm_b)
+}
 
-    let mag_a = magnitude(vec_a);
-    let mag_b = magnitude(vec_b);
+// cosine_terms - the dot product and squared magnitudes, accumulated in a single pass
+fn cosine_terms(vec_a: &[f32], vec_b: &[f32]) -> (f32, f32, f32) {
+    #[cfg(target_arch = "x86_64")]
+    if simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2 and fma
+        return unsafe { avx2::cosine_terms(vec_a, vec_b) };
+    }
+    scalar::cosine_terms(vec_a, vec_b)
+}
 
-    let dot_prod = vec_a.iter().zip(vec_b).map(|(x,y)| x*y).sum::<f32>();
-    if mag_a == 0.0 || mag_b == 0.0 {
-        return 0.0;
+// dot_product - sum of the element-wise products, the cosine similarity of normalized vectors
+pub fn dot_product(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2 and fma
+        return unsafe { avx2::dot_product(vec_a, vec_b) };
+    }
+    scalar::dot_product(vec_a, vec_b)
+}
+
+// squared_euclidean - sum of the squared element-wise differences
+pub fn squared_euclidean(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2 and fma
+        return unsafe { avx2::squared_euclidean(vec_a, vec_b) };
+    }
+    scalar::squared_euclidean(vec_a, vec_b)
+}
+
+// euclidean_distance - length of the difference of the vectors (L2)
+pub fn euclidean_distance(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    squared_euclidean(vec_a, vec_b).sqrt()
+}
+
+// manhattan_distance - sum of the absolute element-wise differences (L1)
+pub fn manhattan_distance(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2 and fma
+        return unsafe { avx2::manhattan_distance(vec_a, vec_b) };
+    }
+    scalar::manhattan_distance(vec_a, vec_b)
+}
+
+// kernel_name - the kernels in use: "avx2+fma" or "scalar"
+pub fn kernel_name() -> &'static str {
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        return "avx2+fma";
+    }
+    "scalar"
+}
+
+// avx2_available - the std macro caches the cpu detection, so this is a load and a test
+#[cfg(target_arch = "x86_64")]
+fn avx2_available() -> bool {
+    std::arch::is_x86_feature_detected!("avx2") && std::arch::is_x86_feature_detected!("fma")
+}
+
+// simd_worthwhile - vectors shorter than one avx2 step, such as product quantization
+// subspaces, go straight to the portable kernels
+#[cfg(target_arch = "x86_64")]
+fn simd_worthwhile(vec: &[f32]) -> bool {
+    vec.len() >= 16 && avx2_available()
+}
+
+// dot_product_u8 - asymmetric dot product of full precision weights with byte codes,
+// used to score scalar quantized vectors without decoding them
+pub fn dot_product_u8(weights: &[f32], codes: &[u8]) -> f32 {
+    weights.iter().zip(codes).map(|(w, c)| w * *c as f32).sum::<f32>()
+}
+
+// table_lookup_sum - asymmetric score of product quantized codes: the sum over the
+// subspaces of the precomputed query score of each code, table holding `width` scores per subspace
+pub fn table_lookup_sum(table: &[f32], width: usize, codes: &[u8]) -> f32 {
+    codes
+        .iter()
+        .enumerate()
+        .map(|(subspace, code)| table[subspace * width + *code as usize])
+        .sum::<f32>()
+}
+
+// Compact kernels: vectors stored as f16 or bf16 are compared with a full precision query,
+// the first argument, without decoding them. On AVX2 (with F16C for f16) each register of
+// 8 values is widened as it is loaded; otherwise BLOCK values at a time are widened into a
+// buffer on the stack and compared with the kernels above.
+
+// BLOCK - compact values widened at a time by the portable kernels
+const BLOCK: usize = 64;
+
+// CompactFloat - 16 bit float formats of the compact kernels
+pub trait CompactFloat: Copy {
+    // EXPONENT_BITS - 5 for f16, 8 for bf16, which is the upper half of an f32
+    const EXPONENT_BITS: u32;
+
+    fn widen(values: &[Self], out: &mut [f32]);
+}
+
+impl CompactFloat for f16 {
+    const EXPONENT_BITS: u32 = 5;
+
+    fn widen(values: &[Self], out: &mut [f32]) {
+        values.convert_to_f32_slice(out);
+    }
+}
+
+impl CompactFloat for bf16 {
+    const EXPONENT_BITS: u32 = 8;
+
+    fn widen(values: &[Self], out: &mut [f32]) {
+        values.convert_to_f32_slice(out);
+    }
+}
+
+// for_each_block - passes each block of the query with the widened block of the vector to f
+#[inline(always)]
+fn for_each_block<T: CompactFloat>(vec_a: &[f32], vec_b: &[T], mut f: impl FnMut(&[f32], &[f32])) {
+    let len = vec_a.len().min(vec_b.len());
+    let mut buffer = [0.0f32; BLOCK];
+    for (block_a, block_b) in vec_a[..len].chunks(BLOCK).zip(vec_b[..len].chunks(BLOCK)) {
+        let widened = &mut buffer[..block_b.len()];
+        T::widen(block_b, widened);
+        f(block_a, widened);
+    }
+}
+
+// compact_simd_worthwhile - the compact kernels are compiled with f16c for both formats, so it
+// is required even for bf16, which is widened with a shift
+#[cfg(target_arch = "x86_64")]
+fn compact_simd_worthwhile(vec: &[f32]) -> bool {
+    simd_worthwhile(vec) && std::arch::is_x86_feature_detected!("f16c")
+}
+
+pub fn dot_product_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if compact_simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2, fma and f16c
+        return unsafe { avx2::dot_product_compact(vec_a, vec_b) };
+    }
+    widened_dot_product(vec_a, vec_b)
+}
+
+fn widened_dot_product<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    let mut dot = 0.0;
+    for_each_block(vec_a, vec_b, |block_a, block_b| dot += scalar::dot_product(block_a, block_b));
+    dot
+}
+
+pub fn cosine_similarity_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    let (dot, norm_a, norm_b) = cosine_terms_compact(vec_a, vec_b);
+    cosine(dot, norm_a, norm_b)
+}
+
+fn cosine_terms_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> (f32, f32, f32) {
+    #[cfg(target_arch = "x86_64")]
+    if compact_simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2, fma and f16c
+        return unsafe { avx2::cosine_terms_compact(vec_a, vec_b) };
+    }
+    widened_cosine_terms(vec_a, vec_b)
+}
+
+fn widened_cosine_terms<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> (f32, f32, f32) {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for_each_block(vec_a, vec_b, |block_a, block_b| {
+        let terms = scalar::cosine_terms(block_a, block_b);
+        dot += terms.0;
+        norm_a += terms.1;
+        norm_b += terms.2;
+    });
+    (dot, norm_a, norm_b)
+}
+
+pub fn squared_euclidean_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if compact_simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2, fma and f16c
+        return unsafe { avx2::squared_euclidean_compact(vec_a, vec_b) };
+    }
+    widened_squared_euclidean(vec_a, vec_b)
+}
+
+fn widened_squared_euclidean<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    let mut sum = 0.0;
+    for_each_block(vec_a, vec_b, |block_a, block_b| sum += scalar::squared_euclidean(block_a, block_b));
+    sum
+}
+
+pub fn euclidean_distance_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    squared_euclidean_compact(vec_a, vec_b).sqrt()
+}
+
+pub fn manhattan_distance_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    #[cfg(target_arch = "x86_64")]
+    if compact_simd_worthwhile(vec_a) {
+        // SAFETY: the cpu supports avx2, fma and f16c
+        return unsafe { avx2::manhattan_distance_compact(vec_a, vec_b) };
+    }
+    widened_manhattan_distance(vec_a, vec_b)
+}
+
+fn widened_manhattan_distance<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    let mut sum = 0.0;
+    for_each_block(vec_a, vec_b, |block_a, block_b| sum += scalar::manhattan_distance(block_a, block_b));
+    sum
+}
+
+pub fn sign_distance_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+    let mut sum = 0.0;
+    for_each_block(vec_a, vec_b, |block_a, block_b| sum += sign_distance(block_a, block_b));
+    sum
+}
+
+// pack_signs - one bit per value, set for positive values, 64 values per word starting
+// with the lowest bit
+pub fn pack_signs(vec: &[f32]) -> Vec<u64> {
+    vec.chunks(64)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u64, |bits, (i, value)| bits | (((*value > 0.0) as u64) << i))
+        })
+        .collect()
+}
+
+// hamming_distance - number of differing bits of two packed sign vectors
+pub fn hamming_distance(bits_a: &[u64], bits_b: &[u64]) -> u32 {
+    bits_a.iter().zip(bits_b).map(|(a, b)| (a ^ b).count_ones()).sum()
+}
+
+// sign_distance - hamming distance of the signs of two vectors, without packing them
+pub fn sign_distance(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+    vec_a.iter().zip(vec_b).filter(|(x, y)| (**x > 0.0) != (**y > 0.0)).count() as f32
+}
+
+// normalize - scales the vector to unit length; the zero vector is returned unchanged
+pub fn normalize(vec: &[f32]) -> Vec<f32> {
+    let mag = magnitude(vec);
+    if mag == 0.0 {
+        return vec.to_vec();
     }
-    
-    dot_prod / (mag_a *  mag_b)
+    vec.iter().map(|e| e / mag).collect()
 }
 
 // magniture - calculates the magnitude(length) of the vector
 fn magnitude(vec: &[f32]) -> f32 {
-    vec.iter().map(|e| e.powf(2.0)).sum::<f32>().sqrt()
+    dot_product(vec, vec).sqrt()
+}
+
+// cosine - the cosine from the dot product and squared magnitudes, 0 for a zero vector
+fn cosine(dot: f32, norm_a: f32, norm_b: f32) -> f32 {
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+// scalar - portable kernels over eight independent lanes, so additions do not wait on
+// each other and the loops vectorize
+mod scalar {
+    const LANES: usize = 8;
+
+    // sum_lanes - sum of term over the element pairs
+    #[inline(always)]
+    fn sum_lanes(vec_a: &[f32], vec_b: &[f32], term: impl Fn(f32, f32) -> f32) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        if len < LANES {
+            return vec_a.iter().zip(vec_b).map(|(x, y)| term(*x, *y)).sum();
+        }
+        let chunks_a = vec_a[..len].chunks_exact(LANES);
+        let chunks_b = vec_b[..len].chunks_exact(LANES);
+        let tail: f32 = chunks_a
+            .remainder()
+            .iter()
+            .zip(chunks_b.remainder())
+            .map(|(x, y)| term(*x, *y))
+            .sum();
+
+        let mut lanes = [0.0f32; LANES];
+        for (x, y) in chunks_a.zip(chunks_b) {
+            for ((lane, x), y) in lanes.iter_mut().zip(x).zip(y) {
+                *lane += term(*x, *y);
+            }
+        }
+        lanes.iter().sum::<f32>() + tail
+    }
+
+    pub(super) fn dot_product(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        sum_lanes(vec_a, vec_b, |x, y| x * y)
+    }
+
+    pub(super) fn squared_euclidean(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        sum_lanes(vec_a, vec_b, |x, y| (x - y) * (x - y))
+    }
+
+    pub(super) fn manhattan_distance(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        sum_lanes(vec_a, vec_b, |x, y| (x - y).abs())
+    }
+
+    pub(super) fn cosine_terms(vec_a: &[f32], vec_b: &[f32]) -> (f32, f32, f32) {
+        let len = vec_a.len().min(vec_b.len());
+        let chunks_a = vec_a[..len].chunks_exact(LANES);
+        let chunks_b = vec_b[..len].chunks_exact(LANES);
+        let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+        for (x, y) in chunks_a.remainder().iter().zip(chunks_b.remainder()) {
+            dot += x * y;
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+
+        let mut lanes = [[0.0f32; LANES]; 3];
+        for (x, y) in chunks_a.zip(chunks_b) {
+            for i in 0..LANES {
+                lanes[0][i] += x[i] * y[i];
+                lanes[1][i] += x[i] * x[i];
+                lanes[2][i] += y[i] * y[i];
+            }
+        }
+        dot += lanes[0].iter().sum::<f32>();
+        norm_a += lanes[1].iter().sum::<f32>();
+        norm_b += lanes[2].iter().sum::<f32>();
+        (dot, norm_a, norm_b)
+    }
+
+    #[cfg(test)]
+    pub(super) fn cosine_similarity(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        let (dot, norm_a, norm_b) = cosine_terms(vec_a, vec_b);
+        super::cosine(dot, norm_a, norm_b)
+    }
+}
+
+// avx2 - kernels over two 8 x f32 registers per iteration with fused multiply-add.
+// Callers must check that the cpu supports avx2 and fma.
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::CompactFloat;
+
+    const STEP: usize = 16;
+
+    // hsum - sum of the eight lanes
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn hsum(v: __m256) -> f32 {
+        let sum = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+        let sum = _mm_add_ps(sum, _mm_movehl_ps(sum, sum));
+        let sum = _mm_add_ss(sum, _mm_shuffle_ps(sum, sum, 1));
+        _mm_cvtss_f32(sum)
+    }
+
+    // load - the 8 values at offset; inline for the generic kernels, built in other crates
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn load(vec: &[f32], offset: usize) -> __m256 {
+        let lanes = &vec[offset..offset + 8];
+        // SAFETY: lanes holds 8 values; loadu has no alignment requirement
+        unsafe { _mm256_loadu_ps(lanes.as_ptr()) }
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) fn dot_product(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % STEP;
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(STEP) {
+            acc0 = _mm256_fmadd_ps(load(vec_a, offset), load(vec_b, offset), acc0);
+            acc1 = _mm256_fmadd_ps(load(vec_a, offset + 8), load(vec_b, offset + 8), acc1);
+        }
+        hsum(_mm256_add_ps(acc0, acc1)) + super::scalar::dot_product(&vec_a[end..len], &vec_b[end..len])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) fn squared_euclidean(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % STEP;
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(STEP) {
+            let diff0 = _mm256_sub_ps(load(vec_a, offset), load(vec_b, offset));
+            let diff1 = _mm256_sub_ps(load(vec_a, offset + 8), load(vec_b, offset + 8));
+            acc0 = _mm256_fmadd_ps(diff0, diff0, acc0);
+            acc1 = _mm256_fmadd_ps(diff1, diff1, acc1);
+        }
+        hsum(_mm256_add_ps(acc0, acc1)) + super::scalar::squared_euclidean(&vec_a[end..len], &vec_b[end..len])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) fn manhattan_distance(vec_a: &[f32], vec_b: &[f32]) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % STEP;
+        // clearing the sign bit gives the absolute value
+        let sign = _mm256_set1_ps(-0.0);
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(STEP) {
+            let diff0 = _mm256_sub_ps(load(vec_a, offset), load(vec_b, offset));
+            let diff1 = _mm256_sub_ps(load(vec_a, offset + 8), load(vec_b, offset + 8));
+            acc0 = _mm256_add_ps(acc0, _mm256_andnot_ps(sign, diff0));
+            acc1 = _mm256_add_ps(acc1, _mm256_andnot_ps(sign, diff1));
+        }
+        hsum(_mm256_add_ps(acc0, acc1)) + super::scalar::manhattan_distance(&vec_a[end..len], &vec_b[end..len])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub(super) fn cosine_terms(vec_a: &[f32], vec_b: &[f32]) -> (f32, f32, f32) {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % 8;
+        let (mut dot, mut norm_a, mut norm_b) = (_mm256_setzero_ps(), _mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(8) {
+            let (x, y) = (load(vec_a, offset), load(vec_b, offset));
+            dot = _mm256_fmadd_ps(x, y, dot);
+            norm_a = _mm256_fmadd_ps(x, x, norm_a);
+            norm_b = _mm256_fmadd_ps(y, y, norm_b);
+        }
+        let (mut dot, mut norm_a, mut norm_b) = (hsum(dot), hsum(norm_a), hsum(norm_b));
+        for (x, y) in vec_a[end..len].iter().zip(&vec_b[end..len]) {
+            dot += x * y;
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+        (dot, norm_a, norm_b)
+    }
+
+    // load_compact - the 8 compact values at offset, widened
+    #[target_feature(enable = "avx2,fma,f16c")]
+    fn load_compact<T: CompactFloat>(vec: &[T], offset: usize) -> __m256 {
+        let lanes = &vec[offset..offset + 8];
+        // SAFETY: lanes holds 8 values of 16 bits, f16 and bf16 being a u16; loadu has no
+        // alignment requirement
+        let bits = unsafe { _mm_loadu_si128(lanes.as_ptr() as *const __m128i) };
+        if T::EXPONENT_BITS == 5 {
+            _mm256_cvtph_ps(bits)
+        } else {
+            _mm256_castsi256_ps(_mm256_slli_epi32(_mm256_cvtepu16_epi32(bits), 16))
+        }
+    }
+
+    #[target_feature(enable = "avx2,fma,f16c")]
+    pub(super) fn dot_product_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % STEP;
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(STEP) {
+            let (y0, y1) = (load_compact(vec_b, offset), load_compact(vec_b, offset + 8));
+            acc0 = _mm256_fmadd_ps(load(vec_a, offset), y0, acc0);
+            acc1 = _mm256_fmadd_ps(load(vec_a, offset + 8), y1, acc1);
+        }
+        hsum(_mm256_add_ps(acc0, acc1)) + super::widened_dot_product(&vec_a[end..len], &vec_b[end..len])
+    }
+
+    #[target_feature(enable = "avx2,fma,f16c")]
+    pub(super) fn squared_euclidean_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % STEP;
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(STEP) {
+            let (y0, y1) = (load_compact(vec_b, offset), load_compact(vec_b, offset + 8));
+            let diff0 = _mm256_sub_ps(load(vec_a, offset), y0);
+            let diff1 = _mm256_sub_ps(load(vec_a, offset + 8), y1);
+            acc0 = _mm256_fmadd_ps(diff0, diff0, acc0);
+            acc1 = _mm256_fmadd_ps(diff1, diff1, acc1);
+        }
+        let tail = super::widened_squared_euclidean(&vec_a[end..len], &vec_b[end..len]);
+        hsum(_mm256_add_ps(acc0, acc1)) + tail
+    }
+
+    #[target_feature(enable = "avx2,fma,f16c")]
+    pub(super) fn manhattan_distance_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> f32 {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % STEP;
+        let sign = _mm256_set1_ps(-0.0);
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(STEP) {
+            let (y0, y1) = (load_compact(vec_b, offset), load_compact(vec_b, offset + 8));
+            acc0 = _mm256_add_ps(acc0, _mm256_andnot_ps(sign, _mm256_sub_ps(load(vec_a, offset), y0)));
+            acc1 = _mm256_add_ps(acc1, _mm256_andnot_ps(sign, _mm256_sub_ps(load(vec_a, offset + 8), y1)));
+        }
+        let tail = super::widened_manhattan_distance(&vec_a[end..len], &vec_b[end..len]);
+        hsum(_mm256_add_ps(acc0, acc1)) + tail
+    }
+
+    #[target_feature(enable = "avx2,fma,f16c")]
+    pub(super) fn cosine_terms_compact<T: CompactFloat>(vec_a: &[f32], vec_b: &[T]) -> (f32, f32, f32) {
+        let len = vec_a.len().min(vec_b.len());
+        let end = len - len % 8;
+        let (mut dot, mut norm_a, mut norm_b) = (_mm256_setzero_ps(), _mm256_setzero_ps(), _mm256_setzero_ps());
+        for offset in (0..end).step_by(8) {
+            let y = load_compact(vec_b, offset);
+            let x = load(vec_a, offset);
+            dot = _mm256_fmadd_ps(x, y, dot);
+            norm_a = _mm256_fmadd_ps(x, x, norm_a);
+            norm_b = _mm256_fmadd_ps(y, y, norm_b);
+        }
+        let (tail_dot, tail_a, tail_b) = super::widened_cosine_terms(&vec_a[end..len], &vec_b[end..len]);
+        (hsum(dot) + tail_dot, hsum(norm_a) + tail_a, hsum(norm_b) + tail_b)
+    }
 }
 
 
@@ -24,7 +517,14 @@ fn magnitude(vec: &[f32]) -> f32 {
 #[cfg(test)]
 mod tests {
 
-    use crate::vector::similarity::cosine_similarity;
+    use half::{bf16, f16};
+
+    use crate::vector::similarity::{
+        cosine, cosine_similarity, cosine_similarity_compact, dot_product, dot_product_compact, hamming_distance,
+        manhattan_distance, manhattan_distance_compact, normalize, pack_signs, scalar, sign_distance,
+        sign_distance_compact, squared_euclidean, squared_euclidean_compact, widened_cosine_terms, widened_dot_product,
+        widened_manhattan_distance, widened_squared_euclidean,
+    };
 
     #[test]
     fn test_cosine_similarity_identical_vectors() {
@@ -39,7 +539,7 @@ mod tests {
         let vec_a: Vec<f32> = vec![1.0, 2.0, 3.0];
         let vec_b: Vec<f32> = vec![-1.0, -2.0, -3.0];
         let similarity = cosine_similarity(&vec_a, &vec_b);
-        assert!( (similarity -1.0).abs() < 0.0001 );
+        assert!( (similarity + 1.0).abs() < 0.0001 );
     }
 
 
@@ -50,6 +550,88 @@ mod tests {
         
         let similarity = cosine_similarity(&vec_a, &vec_b);
         assert_eq!(similarity, 0.0); // Should return 0.0
-    }    
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_normalized_dot_product_is_cosine() {
+        let vec_a = vec![1.0, 2.0, 3.0];
+        let vec_b = vec![-2.0, 0.5, 4.0];
+
+        let dot = dot_product(&normalize(&vec_a), &normalize(&vec_b));
+        assert!((dot - cosine_similarity(&vec_a, &vec_b)).abs() < 0.0001);
+        assert_eq!(normalize(&[0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_kernels_match_reference() {
+        let close = |a: f32, b: f32| (a - b).abs() <= 1e-4 * (1.0 + b.abs());
+        // lengths around the register width, with tails, and slices of different lengths
+        for (len_a, len_b) in [(0, 0), (1, 1), (7, 7), (8, 8), (15, 15), (16, 16), (33, 33), (384, 384), (20, 17)] {
+            let vec_a: Vec<f32> = (0..len_a).map(|i| ((i * 7 % 11) as f32 - 5.0) / 3.0).collect();
+            let vec_b: Vec<f32> = (0..len_b).map(|i| ((i * 5 % 13) as f32 - 6.0) / 4.0).collect();
+            let pairs = || vec_a.iter().zip(&vec_b);
+
+            let dot: f32 = pairs().map(|(x, y)| x * y).sum();
+            let squared: f32 = pairs().map(|(x, y)| (x - y) * (x - y)).sum();
+            let manhattan: f32 = pairs().map(|(x, y)| (x - y).abs()).sum();
+            let norm_a: f32 = pairs().map(|(x, _)| x * x).sum::<f32>().sqrt();
+            let norm_b: f32 = pairs().map(|(_, y)| y * y).sum::<f32>().sqrt();
+            let cosine = if norm_a == 0.0 || norm_b == 0.0 { 0.0 } else { dot / (norm_a * norm_b) };
+
+            assert!(close(dot_product(&vec_a, &vec_b), dot), "dot {} {}", len_a, len_b);
+            assert!(close(squared_euclidean(&vec_a, &vec_b), squared), "l2 {} {}", len_a, len_b);
+            assert!(close(manhattan_distance(&vec_a, &vec_b), manhattan), "l1 {} {}", len_a, len_b);
+            assert!(close(cosine_similarity(&vec_a, &vec_b), cosine), "cosine {} {}", len_a, len_b);
+            assert!(close(scalar::dot_product(&vec_a, &vec_b), dot));
+            assert!(close(scalar::squared_euclidean(&vec_a, &vec_b), squared));
+            assert!(close(scalar::manhattan_distance(&vec_a, &vec_b), manhattan));
+            assert!(close(scalar::cosine_similarity(&vec_a, &vec_b), cosine));
+        }
+    }
+
+    #[test]
+    fn test_compact_kernels_match_widened_vectors() {
+        let close = |a: f32, b: f32| (a - b).abs() <= 1e-4 * (1.0 + b.abs());
+        // blocks with a partial last one, and slices of different lengths
+        for (len_a, len_b) in [(0, 0), (5, 5), (64, 64), (130, 130), (384, 384), (100, 70)] {
+            let vec_a: Vec<f32> = (0..len_a).map(|i| ((i * 7 % 11) as f32 - 5.0) / 3.0).collect();
+            let vec_b: Vec<f32> = (0..len_b).map(|i| ((i * 5 % 13) as f32 - 6.0) / 4.0).collect();
+            let halves: Vec<f16> = vec_b.iter().map(|value| f16::from_f32(*value)).collect();
+            let brains: Vec<bf16> = vec_b.iter().map(|value| bf16::from_f32(*value)).collect();
+            let widened: Vec<f32> = halves.iter().map(|value| value.to_f32()).collect();
+            let widened_brains: Vec<f32> = brains.iter().map(|value| value.to_f32()).collect();
+
+            assert!(close(dot_product_compact(&vec_a, &halves), dot_product(&vec_a, &widened)));
+            assert!(close(cosine_similarity_compact(&vec_a, &halves), cosine_similarity(&vec_a, &widened)));
+            assert!(close(squared_euclidean_compact(&vec_a, &halves), squared_euclidean(&vec_a, &widened)));
+            assert!(close(manhattan_distance_compact(&vec_a, &halves), manhattan_distance(&vec_a, &widened)));
+            assert_eq!(sign_distance_compact(&vec_a, &halves), sign_distance(&vec_a, &vec_b));
+            assert!(close(dot_product_compact(&vec_a, &brains), dot_product(&vec_a, &widened_brains)));
+            assert!(close(cosine_similarity_compact(&vec_a, &brains), cosine_similarity(&vec_a, &widened_brains)));
+            // the portable kernels
+            assert!(close(widened_dot_product(&vec_a, &halves), dot_product(&vec_a, &widened)));
+            assert!(close(widened_squared_euclidean(&vec_a, &brains), squared_euclidean(&vec_a, &widened_brains)));
+            assert!(close(widened_manhattan_distance(&vec_a, &halves), manhattan_distance(&vec_a, &widened)));
+            let (dot, norm_a, norm_b) = widened_cosine_terms(&vec_a, &brains);
+            assert!(close(cosine(dot, norm_a, norm_b), cosine_similarity(&vec_a, &widened_brains)));
+            assert!(close(squared_euclidean_compact(&vec_a, &brains), squared_euclidean(&vec_a, &widened_brains)));
+            assert!(close(manhattan_distance_compact(&vec_a, &brains), manhattan_distance(&vec_a, &widened_brains)));
+            // within the precision of the formats
+            assert!((cosine_similarity_compact(&vec_a, &halves) - cosine_similarity(&vec_a, &vec_b)).abs() < 1e-3);
+            assert!((cosine_similarity_compact(&vec_a, &brains) - cosine_similarity(&vec_a, &vec_b)).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_sign_bits() {
+        let vec_a: Vec<f32> = (0..70).map(|i| if i % 3 == 0 { 1.0 } else { -0.5 }).collect();
+        let vec_b: Vec<f32> = (0..70).map(|i| if i % 2 == 0 { 0.25 } else { 0.0 }).collect();
+        let bits_a = pack_signs(&vec_a);
+        assert_eq!(bits_a.len(), 2);
+        assert_eq!(bits_a[0] & 0b1111, 0b1001);
+        assert_eq!(bits_a[1], 0b10_0100);
+        assert_eq!(hamming_distance(&bits_a, &pack_signs(&vec_b)) as f32, sign_distance(&vec_a, &vec_b));
+        assert_eq!(hamming_distance(&bits_a, &bits_a), 0);
+        assert!(pack_signs(&[]).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use half::{bf16, f16};
+use serde::{Deserialize, Serialize};
+
+use crate::vector::metric::DistanceMetric;
+use crate::vector::similarity::{hamming_distance, pack_signs};
+
+// VectorStorage is the format of the vectors in the vector store of a collection. F16 and
+// Bf16 halve the file, keeping about 3 and 2 significant digits; Binary keeps only the sign
+// of each value, a 32nd of the file, for the Hamming metric.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorStorage {
+    #[default]
+    F32,
+    F16,
+    Bf16,
+    Binary,
+}
+
+impl VectorStorage {
+    // code identifies the format in the entries of the vector file
+    pub fn code(&self) -> u8 {
+        match self {
+            VectorStorage::F32 => 0,
+            VectorStorage::F16 => 1,
+            VectorStorage::Bf16 => 2,
+            VectorStorage::Binary => 3,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(VectorStorage::F32),
+            1 => Some(VectorStorage::F16),
+            2 => Some(VectorStorage::Bf16),
+            3 => Some(VectorStorage::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn is_f32(&self) -> bool {
+        *self == VectorStorage::F32
+    }
+
+    // encoded_size is the size in bytes of an encoded vector of the dimension
+    pub fn encoded_size(&self, dimension: usize) -> usize {
+        match self {
+            VectorStorage::F32 => dimension * 4,
+            VectorStorage::F16 | VectorStorage::Bf16 => dimension * 2,
+            VectorStorage::Binary => dimension.div_ceil(64) * 8,
+        }
+    }
+
+    // encode converts the vector to the format, little endian
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        match self {
+            VectorStorage::F32 => vector.iter().flat_map(|value| value.to_le_bytes()).collect(),
+            VectorStorage::F16 => vector.iter().flat_map(|value| f16::from_f32(*value).to_le_bytes()).collect(),
+            VectorStorage::Bf16 => vector.iter().flat_map(|value| bf16::from_f32(*value).to_le_bytes()).collect(),
+            VectorStorage::Binary => pack_signs(vector).iter().flat_map(|bits| bits.to_le_bytes()).collect(),
+        }
+    }
+}
+
+// StoredValues is a vector of the vector store in its format, borrowed from a VectorBuffers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoredValues<'a> {
+    F32(&'a [f32]),
+    F16(&'a [f16]),
+    Bf16(&'a [bf16]),
+    Binary { bits: &'a [u64], dimension: usize },
+}
+
+impl StoredValues<'_> {
+    pub fn dimension(&self) -> usize {
+        match self {
+            StoredValues::F32(values) => values.len(),
+            StoredValues::F16(values) => values.len(),
+            StoredValues::Bf16(values) => values.len(),
+            StoredValues::Binary { dimension, .. } => *dimension,
+        }
+    }
+
+    // widen writes the vector as f32 values to out; the bits of binary vectors become 1
+    // for positive values and -1 otherwise
+    pub fn widen(&self, out: &mut Vec<f32>) {
+        out.clear();
+        match self {
+            StoredValues::F32(values) => out.extend_from_slice(values),
+            StoredValues::F16(values) => out.extend(values.iter().map(|value| value.to_f32())),
+            StoredValues::Bf16(values) => out.extend(values.iter().map(|value| value.to_f32())),
+            StoredValues::Binary { bits, dimension } => {
+                out.extend((0..*dimension).map(|i| if bits[i / 64] >> (i % 64) & 1 == 1 { 1.0 } else { -1.0 }))
+            }
+        }
+    }
+
+    pub fn to_f32(&self) -> Vec<f32> {
+        let mut values = Vec::with_capacity(self.dimension());
+        self.widen(&mut values);
+        values
+    }
+
+    pub fn to_owned_values(&self) -> OwnedValues {
+        match self {
+            StoredValues::F32(values) => OwnedValues::F32(values.to_vec()),
+            StoredValues::F16(values) => OwnedValues::F16(values.to_vec()),
+            StoredValues::Bf16(values) => OwnedValues::Bf16(values.to_vec()),
+            StoredValues::Binary { bits, dimension } => OwnedValues::Binary {
+                bits: bits.to_vec(),
+                dimension: *dimension,
+            },
+        }
+    }
+}
+
+// OwnedValues is a vector of the vector store in its format, kept after the scan that read it
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValues {
+    F32(Vec<f32>),
+    F16(Vec<f16>),
+    Bf16(Vec<bf16>),
+    Binary { bits: Vec<u64>, dimension: usize },
+}
+
+impl OwnedValues {
+    pub fn as_values(&self) -> StoredValues<'_> {
+        match self {
+            OwnedValues::F32(values) => StoredValues::F32(values),
+            OwnedValues::F16(values) => StoredValues::F16(values),
+            OwnedValues::Bf16(values) => StoredValues::Bf16(values),
+            OwnedValues::Binary { bits, dimension } => StoredValues::Binary {
+                bits,
+                dimension: *dimension,
+            },
+        }
+    }
+}
+
+// VectorBuffers holds the values decoded from the vector file, reused from one vector to
+// the next while scanning
+#[derive(Debug, Default)]
+pub struct VectorBuffers {
+    f32: Vec<f32>,
+    f16: Vec<f16>,
+    bf16: Vec<bf16>,
+    bits: Vec<u64>,
+}
+
+impl VectorBuffers {
+    // decode reads a vector of the format and dimension from its encoded bytes
+    pub fn decode(&mut self, storage: VectorStorage, bytes: &[u8], dimension: usize) -> StoredValues<'_> {
+        match storage {
+            VectorStorage::F32 => {
+                self.f32.clear();
+                self.f32.extend(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])));
+                StoredValues::F32(&self.f32)
+            }
+            VectorStorage::F16 => {
+                self.f16.clear();
+                self.f16.extend(bytes.chunks_exact(2).map(|b| f16::from_le_bytes([b[0], b[1]])));
+                StoredValues::F16(&self.f16)
+            }
+            VectorStorage::Bf16 => {
+                self.bf16.clear();
+                self.bf16.extend(bytes.chunks_exact(2).map(|b| bf16::from_le_bytes([b[0], b[1]])));
+                StoredValues::Bf16(&self.bf16)
+            }
+            VectorStorage::Binary => {
+                self.bits.clear();
+                self.bits.extend(bytes.chunks_exact(8).map(|b| u64::from_le_bytes(b.try_into().unwrap_or_default())));
+                StoredValues::Binary {
+                    bits: &self.bits,
+                    dimension,
+                }
+            }
+        }
+    }
+}
+
+// QueryVector is a query with its packed signs, to score stored vectors of any format
+#[derive(Debug, Clone)]
+pub struct QueryVector<'a> {
+    values: &'a [f32],
+    signs: Vec<u64>,
+}
+
+impl<'a> QueryVector<'a> {
+    pub fn new(values: &'a [f32]) -> Self {
+        Self {
+            values,
+            signs: pack_signs(values),
+        }
+    }
+
+    // score compares the query with a stored vector by the metric, on the stored format.
+    // Binary vectors are compared by the Hamming distance of the signs, with cosine by the
+    // cosine of the sign vectors, 1 - 2 * distance / dimension, and with the other metrics
+    // as vectors of 1 and -1.
+    pub fn score(&self, metric: DistanceMetric, stored: &StoredValues) -> f32 {
+        match stored {
+            StoredValues::F32(values) => metric.score(self.values, values),
+            StoredValues::F16(values) => metric.score_compact(self.values, values),
+            StoredValues::Bf16(values) => metric.score_compact(self.values, values),
+            StoredValues::Binary { bits, dimension } => match metric {
+                DistanceMetric::Hamming => hamming_distance(&self.signs, bits) as f32,
+                DistanceMetric::Cosine if *dimension > 0 => {
+                    1.0 - 2.0 * hamming_distance(&self.signs, bits) as f32 / *dimension as f32
+                }
+                _ => metric.score(self.values, &stored.to_f32()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vector::metric::DistanceMetric;
+    use crate::vector::storage::{QueryVector, StoredValues, VectorBuffers, VectorStorage};
+
+    #[test]
+    fn test_encode_and_decode() {
+        let vector: Vec<f32> = (0..70).map(|i| (i as f32 - 35.0) / 8.0).collect();
+        let mut buffers = VectorBuffers::default();
+        for storage in [VectorStorage::F32, VectorStorage::F16, VectorStorage::Bf16, VectorStorage::Binary] {
+            let bytes = storage.encode(&vector);
+            assert_eq!(bytes.len(), storage.encoded_size(vector.len()), "{:?}", storage);
+            assert_eq!(VectorStorage::from_code(storage.code()), Some(storage));
+
+            let stored = buffers.decode(storage, &bytes, vector.len());
+            assert_eq!(stored.dimension(), 70);
+            let widened = stored.to_f32();
+            match storage {
+                // multiples of 1/8 in this range are exact in every float format
+                VectorStorage::Binary => {
+                    let signs: Vec<f32> = vector.iter().map(|v| if *v > 0.0 { 1.0 } else { -1.0 }).collect();
+                    assert_eq!(widened, signs);
+                }
+                _ => assert_eq!(widened, vector, "{:?}", storage),
+            }
+        }
+        assert_eq!(VectorStorage::Binary.encoded_size(128), 16);
+        assert_eq!(VectorStorage::from_code(4), None);
+        assert_eq!(serde_json::to_string(&VectorStorage::Bf16).unwrap(), "\"bf16\"");
+    }
+
+    #[test]
+    fn test_query_scores_stored_formats() {
+        let query = [1.0, -2.0, 0.5, 3.0];
+        let vector = [0.5, -1.0, -0.25, 2.0];
+        let prepared = QueryVector::new(&query);
+        let mut buffers = VectorBuffers::default();
+
+        let bytes = VectorStorage::F16.encode(&vector);
+        let stored = buffers.decode(VectorStorage::F16, &bytes, 4);
+        for metric in [DistanceMetric::Cosine, DistanceMetric::Euclidean, DistanceMetric::Hamming] {
+            assert_eq!(prepared.score(metric, &stored), metric.score(&query, &vector));
+        }
+
+        let bytes = VectorStorage::Binary.encode(&vector);
+        let stored = buffers.decode(VectorStorage::Binary, &bytes, 4);
+        assert!(matches!(stored, StoredValues::Binary { dimension: 4, .. }));
+        assert_eq!(prepared.score(DistanceMetric::Hamming, &stored), 1.0);
+        assert_eq!(prepared.score(DistanceMetric::Cosine, &stored), 0.5);
+        // other metrics compare with the vector of the signs
+        assert_eq!(prepared.score(DistanceMetric::DotProduct, &stored), 1.0 + 2.0 - 0.5 + 3.0);
+    }
+}